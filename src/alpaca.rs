@@ -0,0 +1,343 @@
+//! Client for the Telescope device type of the ASCOM Alpaca REST API.
+
+use crate::astro::Equatorial;
+use crate::mount::{Epoch, Error, Mount, PierSide, Pointing};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::net::{IpAddr, UdpSocket};
+use std::time::{Duration, Instant};
+
+const DISCOVERY_PORT: u16 = 32227;
+const DISCOVERY_MESSAGE: &[u8] = b"alpacadiscovery1";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Identifies us towards the server, which may log requests per client.
+const CLIENT_ID: u32 = 4711;
+
+/// Values of `EquatorialSystem`
+const EQUATORIAL_TOPOCENTRIC: i32 = 1;
+const EQUATORIAL_J2000: i32 = 2;
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Response<T> {
+    #[serde(default = "Option::default")]
+    value: Option<T>,
+    #[serde(default)]
+    error_number: i32,
+    #[serde(default)]
+    error_message: String,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DiscoveryResponse {
+    alpaca_port: u16,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ConfiguredDevice {
+    device_name: String,
+    device_type: String,
+    device_number: u32,
+}
+
+/// A telescope found on the local network.
+#[derive(Debug, Clone)]
+pub struct DiscoveredTelescope {
+    /// `host:port` of the Alpaca server
+    pub address: String,
+    pub name: String,
+    pub number: u32,
+}
+
+pub struct Alpaca {
+    agent: ureq::Agent,
+    base_url: String,
+    transaction_id: u32,
+    epoch: Epoch,
+}
+
+impl Alpaca {
+    /// Connects to telescope `number` of the Alpaca server at `address`, given as `host:port`.
+    pub fn new(address: &str, number: u32) -> Result<Alpaca, Error> {
+        let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+        let mut alpaca = Alpaca {
+            agent,
+            base_url: format!("http://{}/api/v1/telescope/{}", address, number),
+            transaction_id: 0,
+            epoch: Epoch::OfDate,
+        };
+        alpaca.put("connected", &[("Connected", "true".to_string())])?;
+        alpaca.epoch = match alpaca.get::<i32>("equatorialsystem") {
+            Ok(EQUATORIAL_J2000) => { Epoch::J2000 }
+            // Other means custom, which is as good a guess as drivers not knowing the property
+            Ok(0 | EQUATORIAL_TOPOCENTRIC) | Err(Error::Rejected(_) | Error::Http(_)) => { Epoch::OfDate }
+            Ok(system) => {
+                return Err(Error::Rejected(format!("unsupported equatorial system {}", system)));
+            }
+            Err(e) => { return Err(e); }
+        };
+        Ok(alpaca)
+    }
+
+    fn next_transaction(&mut self) -> String {
+        self.transaction_id += 1;
+        self.transaction_id.to_string()
+    }
+
+    fn get<T: DeserializeOwned>(&mut self, method: &str) -> Result<T, Error> {
+        let transaction_id = self.next_transaction();
+        let response = self.agent.get(&format!("{}/{}", self.base_url, method))
+            .query("ClientID", &CLIENT_ID.to_string())
+            .query("ClientTransactionID", &transaction_id)
+            .call()?;
+        let response: Response<T> = response.into_json()?;
+        check(method, &response)?;
+        response.value.ok_or_else(|| Error::Protocol(format!("no value for {}", method)))
+    }
+
+    fn put(&mut self, method: &str, parameters: &[(&str, String)]) -> Result<(), Error> {
+        let mut form = vec![("ClientID", CLIENT_ID.to_string()), ("ClientTransactionID", self.next_transaction())];
+        form.extend(parameters.iter().cloned());
+        let form = form.iter().map(|(k, v)| (*k, v.as_str())).collect::<Vec<_>>();
+        let response = self.agent.put(&format!("{}/{}", self.base_url, method)).send_form(&form)?;
+        let response: Response<serde_json::Value> = response.into_json()?;
+        check(method, &response)
+    }
+
+    fn coordinates(target: &Equatorial) -> [(&'static str, String); 2] {
+        [("RightAscension", target.ra_hours.to_string()), ("Declination", target.dec_degrees.to_string())]
+    }
+}
+
+fn check<T>(method: &str, response: &Response<T>) -> Result<(), Error> {
+    match response.error_number {
+        0 => { Ok(()) }
+        number => { Err(Error::Rejected(format!("{} failed with error {}: {}", method, number, response.error_message))) }
+    }
+}
+
+impl Mount for Alpaca {
+    fn slew_to(&mut self, target: &Equatorial) -> Result<(), Error> {
+        // Mounts that cannot switch tracking track anyway
+        let _ = self.put("tracking", &[("Tracking", "true".to_string())]);
+        self.put("slewtocoordinatesasync", &Alpaca::coordinates(target))
+    }
+
+    fn is_slewing(&mut self) -> Result<bool, Error> {
+        self.get("slewing")
+    }
+
+    fn abort(&mut self) -> Result<(), Error> {
+        self.put("abortslew", &[])
+    }
+
+    fn pointing(&mut self) -> Result<Pointing, Error> {
+        let position = Equatorial {
+            ra_hours: self.get("rightascension")?,
+            dec_degrees: self.get("declination")?,
+        };
+        let pier_side = match self.get::<i32>("sideofpier") {
+            Ok(0) => { Some(PierSide::East) }
+            Ok(1) => { Some(PierSide::West) }
+            _ => { None }
+        };
+        Ok(Pointing { position, pier_side, focus_position: None })
+    }
+
+    fn sync(&mut self, position: &Equatorial) -> Result<(), Error> {
+        self.put("synctocoordinates", &Alpaca::coordinates(position))
+    }
+
+    fn epoch(&self) -> Epoch {
+        self.epoch
+    }
+}
+
+/// Finds the telescopes of the Alpaca servers answering a discovery broadcast within `wait`.
+pub fn discover(wait: Duration) -> Result<Vec<DiscoveredTelescope>, Error> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_broadcast(true)?;
+    socket.send_to(DISCOVERY_MESSAGE, ("255.255.255.255", DISCOVERY_PORT))?;
+
+    let mut servers: HashSet<(IpAddr, u16)> = HashSet::new();
+    let deadline = Instant::now() + wait;
+    let mut buffer = [0u8; 1024];
+    while let Some(remaining) = deadline.checked_duration_since(Instant::now()).filter(|d| !d.is_zero()) {
+        socket.set_read_timeout(Some(remaining))?;
+        match socket.recv_from(&mut buffer) {
+            Ok((length, sender)) => {
+                // Anything else answering on the port isn't an Alpaca server
+                if let Ok(response) = serde_json::from_slice::<DiscoveryResponse>(&buffer[..length]) {
+                    servers.insert((sender.ip(), response.alpaca_port));
+                }
+            }
+            Err(e) if matches!(e.kind(), std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut) => { break; }
+            Err(e) => { return Err(e.into()); }
+        }
+    }
+
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let mut telescopes = Vec::new();
+    for (ip, port) in servers {
+        let address = match ip {
+            IpAddr::V4(ip) => { format!("{}:{}", ip, port) }
+            IpAddr::V6(ip) => { format!("[{}]:{}", ip, port) }
+        };
+        let devices = agent.get(&format!("http://{}/management/v1/configureddevices", address))
+            .call()
+            .map_err(Error::from)
+            .and_then(|response| Ok(response.into_json::<Response<Vec<ConfiguredDevice>>>()?));
+        // A server that cannot list its devices offers no telescope to choose
+        if let Ok(response) = devices {
+            telescopes.extend(response.value.unwrap_or_default().into_iter()
+                .filter(|d| d.device_type.eq_ignore_ascii_case("Telescope"))
+                .map(|d| DiscoveredTelescope { address: address.clone(), name: d.device_name, number: d.device_number }));
+        }
+    }
+    telescopes.sort_by(|a, b| (&a.address, a.number).cmp(&(&b.address, b.number)));
+    Ok(telescopes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::{MountHandle, MountSettings};
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    /// State of a telescope simulator behind an Alpaca server.
+    #[derive(Debug, Default)]
+    struct Simulator {
+        equatorial_system: i32,
+        ra: f64,
+        dec: f64,
+        /// Number of polls still reporting the slew
+        slewing: u32,
+        /// Form parameters of the PUT requests by method
+        puts: HashMap<String, HashMap<String, String>>,
+    }
+
+    impl Simulator {
+        fn answer(&mut self, verb: &str, method: &str, form: HashMap<String, String>) -> String {
+            let value = |value: String| format!("{{\"Value\":{},\"ErrorNumber\":0,\"ErrorMessage\":\"\"}}", value);
+            let ok = "{\"ErrorNumber\":0,\"ErrorMessage\":\"\"}".to_string();
+            let number = |name: &str| form[name].parse::<f64>().unwrap();
+            let answer = match (verb, method) {
+                ("GET", "equatorialsystem") => { value(self.equatorial_system.to_string()) }
+                ("GET", "rightascension") => { value(self.ra.to_string()) }
+                ("GET", "declination") => { value(self.dec.to_string()) }
+                ("GET", "sideofpier") => { value("1".to_string()) }
+                ("GET", "slewing") => {
+                    self.slewing = self.slewing.saturating_sub(1);
+                    value((self.slewing > 0).to_string())
+                }
+                ("PUT", "connected") => { ok }
+                ("PUT", "tracking") => { "{\"ErrorNumber\":1024,\"ErrorMessage\":\"Tracking cannot be changed\"}".to_string() }
+                ("PUT", "slewtocoordinatesasync") | ("PUT", "synctocoordinates") => {
+                    self.ra = number("RightAscension");
+                    self.dec = number("Declination");
+                    self.slewing = if method == "synctocoordinates" { 0 } else { 3 };
+                    ok
+                }
+                _ => { format!("{{\"ErrorNumber\":1024,\"ErrorMessage\":\"{} {} not implemented\"}}", verb, method) }
+            };
+            if verb == "PUT" {
+                self.puts.insert(method.to_string(), form);
+            }
+            answer
+        }
+    }
+
+    /// Serves the telescope on a local port, one request per connection.
+    fn serve(simulator: Simulator) -> (String, Arc<Mutex<Simulator>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let simulator = Arc::new(Mutex::new(simulator));
+        let served = simulator.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    if header.trim().is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0u8; length];
+                reader.read_exact(&mut body).unwrap();
+                let mut parts = request_line.split_whitespace();
+                let verb = parts.next().unwrap().to_string();
+                let path = parts.next().unwrap().split('?').next().unwrap().to_string();
+                let form = String::from_utf8(body).unwrap()
+                    .split('&')
+                    .filter_map(|pair| pair.split_once('='))
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect();
+                let method = path.rsplit('/').next().unwrap();
+                let answer = served.lock().unwrap().answer(&verb, method, form);
+                write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                       answer.len(), answer).unwrap();
+            }
+        });
+        (address, simulator)
+    }
+
+    #[test]
+    fn slews_like_simulator() {
+        let (address, simulator) = serve(Simulator { equatorial_system: EQUATORIAL_J2000, ra: 1.0, dec: 10.0, ..Simulator::default() });
+        let mut alpaca = Alpaca::new(&address, 0).unwrap();
+        assert_eq!(alpaca.epoch(), Epoch::J2000);
+        assert_eq!(simulator.lock().unwrap().puts["connected"]["Connected"], "true");
+
+        alpaca.slew_to(&Equatorial { ra_hours: 2.5, dec_degrees: -20.25 }).unwrap();
+        assert_eq!(simulator.lock().unwrap().puts["slewtocoordinatesasync"]["RightAscension"], "2.5");
+        assert!(alpaca.is_slewing().unwrap());
+        assert!(alpaca.is_slewing().unwrap());
+        assert!(!alpaca.is_slewing().unwrap());
+        let pointing = alpaca.pointing().unwrap();
+        assert_eq!(pointing.position, Equatorial { ra_hours: 2.5, dec_degrees: -20.25 });
+        assert_eq!(pointing.pier_side, Some(PierSide::West));
+        assert!(matches!(alpaca.abort(), Err(Error::Rejected(_))));
+    }
+
+    #[test]
+    fn converts_to_equatorial_system() {
+        let position = Equatorial { ra_hours: 6.0, dec_degrees: 30.0 };
+        for system in [EQUATORIAL_J2000, EQUATORIAL_TOPOCENTRIC] {
+            let (address, simulator) = serve(Simulator { equatorial_system: system, ..Simulator::default() });
+            let mount = MountHandle::connect(&MountSettings::Alpaca { address, number: 0 }).unwrap();
+            mount.sync(&position).unwrap();
+            let synced = {
+                let simulator = simulator.lock().unwrap();
+                Equatorial { ra_hours: simulator.ra, dec_degrees: simulator.dec }
+            };
+            if system == EQUATORIAL_J2000 {
+                assert_eq!(synced, position);
+            } else {
+                // Since 2000 the equinox has moved by more than 0.3°
+                assert!(synced.separation(&position) > 0.3, "{:?}", synced);
+            }
+            let pointing = mount.pointing().unwrap().position;
+            assert!(pointing.separation(&position) < 1e-6, "{:?}", pointing);
+        }
+        let (address, _) = serve(Simulator { equatorial_system: 4, ..Simulator::default() });
+        assert!(matches!(Alpaca::new(&address, 0), Err(Error::Rejected(_))));
+    }
+}
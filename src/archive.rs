@@ -0,0 +1,571 @@
+use crate::common::RowKind;
+use crate::measurement::{filter_name, filter_slot, IntegrationTimes};
+use crate::qc::CheckResult;
+use crate::rejection;
+use crate::reduction::{differential, read_log, reduce, DifferentialMagnitude, LogRecord};
+use chrono::{DateTime, Utc};
+use gtk::glib;
+use rusqlite::{params, Connection, OptionalExtension, ToSql};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::{fmt, fs, io};
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    instrument TEXT NOT NULL,
+    log_file TEXT NOT NULL,
+    started TEXT NOT NULL,
+    ended TEXT NOT NULL,
+    outcome TEXT NOT NULL,
+    -- Rows of the log including the rejected ones, together with the start identifying the run
+    log_rows INTEGER NOT NULL,
+    -- Whether the differential magnitudes of the run are in the differentials table
+    reduced INTEGER NOT NULL DEFAULT 0
+);
+CREATE TABLE IF NOT EXISTS measurements (
+    id INTEGER PRIMARY KEY,
+    run_id INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    timestamp TEXT NOT NULL,
+    item_index INTEGER NOT NULL,
+    star_id TEXT NOT NULL,
+    star_type TEXT NOT NULL,
+    kind TEXT NOT NULL,
+    filter TEXT NOT NULL,
+    integration_time INTEGER NOT NULL,
+    count1 INTEGER NOT NULL,
+    count2 INTEGER NOT NULL,
+    count3 INTEGER NOT NULL,
+    note TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS check_results (
+    run_id INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    check_id TEXT NOT NULL,
+    comparison_id TEXT NOT NULL,
+    filter TEXT NOT NULL,
+    measured REAL NOT NULL,
+    measured_error REAL NOT NULL,
+    catalog REAL,
+    historical REAL,
+    flagged INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS integration_times (
+    instrument TEXT NOT NULL,
+    star_id TEXT NOT NULL,
+    filter TEXT NOT NULL,
+    integration_time INTEGER NOT NULL,
+    updated TEXT NOT NULL,
+    PRIMARY KEY (instrument, star_id, filter)
+);
+CREATE TABLE IF NOT EXISTS differentials (
+    run_id INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    timestamp TEXT NOT NULL,
+    star_id TEXT NOT NULL,
+    star_type TEXT NOT NULL,
+    comparison_id TEXT NOT NULL,
+    filter TEXT NOT NULL,
+    magnitude REAL NOT NULL,
+    magnitude_error REAL NOT NULL
+);
+CREATE INDEX IF NOT EXISTS runs_by_start ON runs(started, log_rows);
+CREATE INDEX IF NOT EXISTS measurements_by_star ON measurements(star_id, filter, timestamp);
+CREATE INDEX IF NOT EXISTS measurements_by_run ON measurements(run_id);
+CREATE INDEX IF NOT EXISTS differentials_by_star ON differentials(star_id, star_type);
+CREATE INDEX IF NOT EXISTS differentials_by_run ON differentials(run_id);
+";
+
+#[derive(Debug)]
+pub enum Error {
+    Database(rusqlite::Error),
+    Log(csv::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Database(e) => format!("archive database error: {}", e).fmt(f),
+            Error::Log(e) => format!("error reading run log: {}", e).fmt(f),
+            Error::Io(e) => format!("archive I/O error: {}", e).fmt(f),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        Error::Database(e)
+    }
+}
+
+impl From<csv::Error> for Error {
+    fn from(e: csv::Error) -> Self {
+        Error::Log(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Describes where a run's measurements came from.
+#[derive(Debug, Clone)]
+pub struct RunMetadata {
+    pub instrument: String,
+    pub log_file: String,
+    pub started: DateTime<Utc>,
+    pub ended: DateTime<Utc>,
+    pub outcome: String,
+    /// Number of rows of the log, including rejected ones
+    pub log_rows: usize,
+}
+
+/// Restricts an archive query. Unset fields match everything.
+#[derive(Debug, Clone, Default)]
+pub struct Query {
+    pub star: Option<String>,
+    pub filter: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub instrument: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchivedMeasurement {
+    pub run_id: i64,
+    pub instrument: String,
+    pub record: LogRecord,
+}
+
+/// Local SQLite archive of all executed runs.
+pub struct Archive {
+    connection: Connection,
+}
+
+impl Archive {
+    /// Opens the archive in the user's data directory, creating it if necessary.
+    pub fn open_default() -> Result<Archive, Error> {
+        let dir = glib::user_data_dir().join("peprunner");
+        fs::create_dir_all(&dir)?;
+        Archive::open(&dir.join("archive.sqlite"))
+    }
+
+    pub fn open(path: &Path) -> Result<Archive, Error> {
+        let connection = Connection::open(path)?;
+        connection.execute_batch("PRAGMA foreign_keys = ON;")?;
+        connection.execute_batch(SCHEMA)?;
+        let mut archive = Archive { connection };
+        archive.reduce_pending()?;
+        Ok(archive)
+    }
+
+    /// Caches the differential magnitudes of runs that lack them.
+    fn reduce_pending(&mut self) -> Result<(), Error> {
+        let run_ids = {
+            let mut statement = self.connection.prepare("SELECT id FROM runs WHERE reduced = 0")?;
+            let rows = statement.query_map([], |row| row.get::<_, i64>(0))?;
+            rows.collect::<Result<Vec<_>, _>>()?
+        };
+        for run_id in run_ids {
+            let records = self.run_records(run_id)?;
+            let transaction = self.connection.transaction()?;
+            store_differentials(&transaction, run_id, &records)?;
+            transaction.commit()?;
+        }
+        Ok(())
+    }
+
+    /// Stores a run with its measurements and returns the id of the run.
+    pub fn ingest(&mut self, metadata: &RunMetadata, records: &[LogRecord]) -> Result<i64, Error> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute(
+            "INSERT INTO runs (instrument, log_file, started, ended, outcome, log_rows) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![metadata.instrument, metadata.log_file, metadata.started, metadata.ended, metadata.outcome, metadata.log_rows])?;
+        let run_id = transaction.last_insert_rowid();
+        store_measurements(&transaction, run_id, records)?;
+        store_differentials(&transaction, run_id, records)?;
+        transaction.commit()?;
+        Ok(run_id)
+    }
+
+    /// Reads a run log from file and stores it without the rejected rows. Logs that have
+    /// already been ingested, possibly under another name, are skipped.
+    pub fn ingest_log(&mut self, path: &Path, instrument: &str, outcome: &str) -> Result<Option<i64>, Error> {
+        let records = read_log(path)?;
+        let (Some(first), Some(last)) = (records.first(), records.last()) else {
+            return Ok(None);
+        };
+        let metadata = RunMetadata {
+            instrument: instrument.to_string(),
+            log_file: path.to_string_lossy().to_string(),
+            started: first.timestamp,
+            ended: last.timestamp,
+            outcome: outcome.to_string(),
+            log_rows: records.len(),
+        };
+        if self.find_run(metadata.started, metadata.log_rows)?.is_some() {
+            return Ok(None);
+        }
+        let records = rejection::apply(records, &rejection::load(path)?);
+        self.ingest(&metadata, &records).map(Some)
+    }
+
+    /// Replaces the measurements of the archived run of a log by those left after the current
+    /// rejections. Returns the id of the run, `None` if the log isn't archived.
+    pub fn apply_rejections(&mut self, path: &Path) -> Result<Option<i64>, Error> {
+        let Some(run_id) = self.run_of_log(path)? else {
+            return Ok(None);
+        };
+        let records = rejection::apply(read_log(path)?, &rejection::load(path)?);
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM measurements WHERE run_id = ?1", params![run_id])?;
+        store_measurements(&transaction, run_id, &records)?;
+        store_differentials(&transaction, run_id, &records)?;
+        transaction.commit()?;
+        Ok(Some(run_id))
+    }
+
+    /// The archived run of a log, identified by its content so that renamed or copied logs are found.
+    fn run_of_log(&self, path: &Path) -> Result<Option<i64>, Error> {
+        let records = read_log(path)?;
+        let Some(first) = records.first() else {
+            return Ok(None);
+        };
+        self.find_run(first.timestamp, records.len())
+    }
+
+    fn find_run(&self, started: DateTime<Utc>, log_rows: usize) -> Result<Option<i64>, Error> {
+        Ok(self.connection
+            .query_row("SELECT id FROM runs WHERE started = ?1 AND log_rows = ?2", params![started, log_rows],
+                       |row| row.get::<_, i64>(0))
+            .optional()?)
+    }
+
+    pub fn query(&self, query: &Query) -> Result<Vec<ArchivedMeasurement>, Error> {
+        let mut sql = String::from(
+            "SELECT m.run_id, r.instrument, m.timestamp, m.item_index, m.star_id, m.star_type, m.kind, m.filter,
+                    m.integration_time, m.count1, m.count2, m.count3, m.note
+             FROM measurements m JOIN runs r ON r.id = m.run_id WHERE 1 = 1");
+        let mut values: Vec<&dyn ToSql> = Vec::new();
+        if let Some(star) = &query.star {
+            sql.push_str(" AND m.star_id = ?");
+            values.push(star);
+        }
+        if let Some(filter) = &query.filter {
+            sql.push_str(" AND m.filter = ?");
+            values.push(filter);
+        }
+        if let Some(from) = &query.from {
+            sql.push_str(" AND m.timestamp >= ?");
+            values.push(from);
+        }
+        if let Some(to) = &query.to {
+            sql.push_str(" AND m.timestamp < ?");
+            values.push(to);
+        }
+        if let Some(instrument) = &query.instrument {
+            sql.push_str(" AND r.instrument = ?");
+            values.push(instrument);
+        }
+        sql.push_str(" ORDER BY m.timestamp");
+
+        let mut statement = self.connection.prepare(&sql)?;
+        let rows = statement.query_map(values.as_slice(), |row| {
+            Ok(ArchivedMeasurement {
+                run_id: row.get(0)?,
+                instrument: row.get(1)?,
+                record: record_from_row(row, 2)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// All stars that have been measured as PGM.
+    pub fn program_stars(&self) -> Result<Vec<String>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT DISTINCT star_id FROM measurements WHERE star_type = 'PGM' AND kind = 'STAR' ORDER BY star_id")?;
+        let rows = statement.query_map([], |row| row.get(0))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Differential magnitudes of a program star and of the check stars of the runs it was measured in.
+    pub fn light_curve(&self, pgm: &str) -> Result<Vec<DifferentialMagnitude>, Error> {
+        self.differentials(
+            "SELECT timestamp, star_id, star_type, comparison_id, filter, magnitude, magnitude_error FROM differentials
+             WHERE run_id IN (SELECT run_id FROM differentials WHERE star_id = ?1 AND star_type = 'PGM')
+               AND (star_type = 'CHK' OR (star_id = ?1 AND star_type = 'PGM'))
+             ORDER BY timestamp",
+            params![pgm])
+    }
+
+    /// Differential magnitudes of a star from all runs it was measured in with the given type.
+    pub fn differentials_of(&self, star_id: &str, star_type: &str) -> Result<Vec<(i64, DifferentialMagnitude)>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT run_id, timestamp, star_id, star_type, comparison_id, filter, magnitude, magnitude_error
+             FROM differentials WHERE star_id = ?1 AND star_type = ?2 ORDER BY timestamp")?;
+        let rows = statement.query_map(params![star_id, star_type], |row| {
+            Ok((row.get(0)?, differential_from_row(row, 1)?))
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    pub fn run_differentials(&self, run_id: i64) -> Result<Vec<DifferentialMagnitude>, Error> {
+        self.differentials(
+            "SELECT timestamp, star_id, star_type, comparison_id, filter, magnitude, magnitude_error
+             FROM differentials WHERE run_id = ?1 ORDER BY timestamp",
+            params![run_id])
+    }
+
+    fn differentials(&self, sql: &str, values: &[&dyn ToSql]) -> Result<Vec<DifferentialMagnitude>, Error> {
+        let mut statement = self.connection.prepare(sql)?;
+        let rows = statement.query_map(values, |row| differential_from_row(row, 0))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Replaces the check star results of a run.
+    pub fn store_check_results(&self, run_id: i64, results: &[CheckResult]) -> Result<(), Error> {
+        self.connection.execute("DELETE FROM check_results WHERE run_id = ?1", params![run_id])?;
+        let mut statement = self.connection.prepare(
+            "INSERT INTO check_results (run_id, check_id, comparison_id, filter, measured, measured_error, catalog, historical, flagged)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)")?;
+        for r in results {
+            statement.execute(params![run_id, r.check_id, r.comparison_id, r.filter, r.measured, r.measured_error,
+                r.catalog, r.historical, r.flagged])?;
+        }
+        Ok(())
+    }
+
+    /// Check star results of the run logged to a file, empty if the log isn't archived.
+    pub fn check_results_for_log(&self, path: &Path) -> Result<Vec<CheckResult>, Error> {
+        let Some(run_id) = self.run_of_log(path)? else {
+            return Ok(Vec::new());
+        };
+        let mut statement = self.connection.prepare(
+            "SELECT check_id, comparison_id, filter, measured, measured_error, catalog, historical, flagged
+             FROM check_results WHERE run_id = ?1")?;
+        let rows = statement.query_map(params![run_id], |row| {
+            Ok(CheckResult {
+                check_id: row.get(0)?,
+                comparison_id: row.get(1)?,
+                filter: row.get(2)?,
+                measured: row.get(3)?,
+                measured_error: row.get(4)?,
+                catalog: row.get(5)?,
+                historical: row.get(6)?,
+                flagged: row.get(7)?,
+            })
+        })?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Integration times remembered for the stars measured with an instrument.
+    pub fn integration_times(&self, instrument: &str) -> Result<IntegrationTimes, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT star_id, filter, integration_time FROM integration_times WHERE instrument = ?1")?;
+        let rows = statement.query_map(params![instrument], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?, row.get::<_, u16>(2)?))
+        })?;
+        let mut result = IntegrationTimes::new();
+        for row in rows {
+            let (star_id, filter, integration_time) = row?;
+            if let Some(slot) = filter_slot(&filter) {
+                result.entry(star_id).or_default().insert(slot, integration_time);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Remembers the integration time of a star in a filter, replacing an earlier one.
+    pub fn store_integration_time(&self, instrument: &str, star_id: &str, filter_slot: u8, integration_time: u16) -> Result<(), Error> {
+        self.connection.execute(
+            "INSERT OR REPLACE INTO integration_times (instrument, star_id, filter, integration_time, updated)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![instrument, star_id, filter_name(filter_slot), integration_time, Utc::now()])?;
+        Ok(())
+    }
+
+    /// Replaces all remembered integration times of a star.
+    pub fn replace_integration_times(&mut self, instrument: &str, star_id: &str, times: &BTreeMap<u8, u16>) -> Result<(), Error> {
+        let transaction = self.connection.transaction()?;
+        transaction.execute("DELETE FROM integration_times WHERE instrument = ?1 AND star_id = ?2",
+                            params![instrument, star_id])?;
+        for (slot, integration_time) in times {
+            transaction.execute(
+                "INSERT INTO integration_times (instrument, star_id, filter, integration_time, updated)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![instrument, star_id, filter_name(*slot), integration_time, Utc::now()])?;
+        }
+        transaction.commit()?;
+        Ok(())
+    }
+
+    fn run_records(&self, run_id: i64) -> Result<Vec<LogRecord>, Error> {
+        let mut statement = self.connection.prepare(
+            "SELECT timestamp, item_index, star_id, star_type, kind, filter, integration_time, count1, count2, count3, note
+             FROM measurements WHERE run_id = ?1 ORDER BY id")?;
+        let rows = statement.query_map(params![run_id], |row| record_from_row(row, 0))?;
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+}
+
+fn store_measurements(connection: &Connection, run_id: i64, records: &[LogRecord]) -> Result<(), Error> {
+    let mut statement = connection.prepare(
+        "INSERT INTO measurements (run_id, timestamp, item_index, star_id, star_type, kind, filter,
+                                   integration_time, count1, count2, count3, note)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)")?;
+    for record in records {
+        statement.execute(params![run_id, record.timestamp, record.index, record.star_id, record.star_type,
+            record.kind().as_str(), record.filter, record.integration_time,
+            record.count1, record.count2, record.count3, record.note])?;
+    }
+    Ok(())
+}
+
+/// Replaces the cached differential magnitudes of a run by those reduced from `records`.
+fn store_differentials(connection: &Connection, run_id: i64, records: &[LogRecord]) -> Result<(), Error> {
+    connection.execute("DELETE FROM differentials WHERE run_id = ?1", params![run_id])?;
+    let mut statement = connection.prepare(
+        "INSERT INTO differentials (run_id, timestamp, star_id, star_type, comparison_id, filter, magnitude, magnitude_error)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)")?;
+    for m in differential(&reduce(records).0) {
+        statement.execute(params![run_id, m.timestamp, m.star_id, m.star_type, m.comparison_id, m.filter,
+            m.magnitude, m.magnitude_error])?;
+    }
+    connection.execute("UPDATE runs SET reduced = 1 WHERE id = ?1", params![run_id])?;
+    Ok(())
+}
+
+/// Maps the differential magnitude columns starting at `offset`.
+fn differential_from_row(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<DifferentialMagnitude> {
+    Ok(DifferentialMagnitude {
+        timestamp: row.get(offset)?,
+        star_id: row.get(offset + 1)?,
+        star_type: row.get(offset + 2)?,
+        comparison_id: row.get(offset + 3)?,
+        filter: row.get(offset + 4)?,
+        magnitude: row.get(offset + 5)?,
+        magnitude_error: row.get(offset + 6)?,
+    })
+}
+
+/// Maps the measurement columns starting at `offset` to a log record.
+fn record_from_row(row: &rusqlite::Row, offset: usize) -> rusqlite::Result<LogRecord> {
+    let kind = RowKind::from_name(&row.get::<_, String>(offset + 4)?);
+    Ok(LogRecord {
+        timestamp: row.get(offset)?,
+        index: row.get(offset + 1)?,
+        star_id: row.get(offset + 2)?,
+        star_type: row.get(offset + 3)?,
+        is_star: kind == Some(RowKind::Star),
+        filter: row.get(offset + 5)?,
+        integration_time: row.get(offset + 6)?,
+        count1: row.get(offset + 7)?,
+        count2: row.get(offset + 8)?,
+        count3: row.get(offset + 9)?,
+        kind,
+        note: row.get(offset + 10)?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::path::PathBuf;
+
+    const LOG: &str = "Timestamp,Index,StarId,StarType,IsStar,Filter,IntegrationTime,Count1,Count2,Count3,Kind,Note
+2024-10-01T22:00:00Z,0,A,CMP,true,V,1000,10000,10000,10000,STAR,
+2024-10-01T22:01:00Z,0,A,CMP,false,V,1000,1000,1000,1000,SKY,
+";
+
+    /// Empty directory for the files of a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("peprunner-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn ingests_log_once_under_any_name() {
+        let dir = test_dir("ingest");
+        fs::write(dir.join("a.csv"), LOG).unwrap();
+        fs::write(dir.join("b.csv"), LOG).unwrap();
+        let mut archive = Archive::open(&dir.join("archive.sqlite")).unwrap();
+        assert!(archive.ingest_log(&dir.join("a.csv"), "SSP3", "Completed").unwrap().is_some());
+        assert!(archive.ingest_log(&dir.join("b.csv"), "SSP3", "Imported").unwrap().is_none());
+        // A later run saved under the name of an archived log
+        fs::write(dir.join("a.csv"), LOG.replace("T22:", "T23:")).unwrap();
+        assert!(archive.ingest_log(&dir.join("a.csv"), "SSP3", "Completed").unwrap().is_some());
+    }
+
+    #[test]
+    fn caches_differential_magnitudes() {
+        let dir = test_dir("light-curve");
+        let log = format!("{}{}", LOG, "2024-10-01T22:02:00Z,1,B,PGM,true,V,1000,5000,5000,5000,STAR,
+2024-10-01T22:03:00Z,1,B,PGM,false,V,1000,1000,1000,1000,SKY,
+");
+        fs::write(dir.join("run.csv"), log).unwrap();
+        let mut archive = Archive::open(&dir.join("archive.sqlite")).unwrap();
+        let run_id = archive.ingest_log(&dir.join("run.csv"), "SSP3", "Completed").unwrap().unwrap();
+        let light_curve = archive.light_curve("B").unwrap();
+        assert_eq!(light_curve.len(), 1);
+        assert_eq!(light_curve[0].comparison_id, "A");
+        assert!((light_curve[0].magnitude - 2.5 * 2.25f64.log10()).abs() < 1e-9);
+        assert_eq!(archive.differentials_of("B", "PGM").unwrap()[0].0, run_id);
+        assert!(archive.light_curve("A").unwrap().is_empty());
+    }
+
+    #[test]
+    fn finds_check_results_of_copied_log() {
+        let dir = test_dir("check-results");
+        fs::write(dir.join("run.csv"), LOG).unwrap();
+        let mut archive = Archive::open(&dir.join("archive.sqlite")).unwrap();
+        let run_id = archive.ingest_log(&dir.join("run.csv"), "SSP3", "Completed").unwrap().unwrap();
+        let result = CheckResult {
+            check_id: "C".to_string(),
+            comparison_id: "A".to_string(),
+            filter: "V".to_string(),
+            measured: 0.5,
+            measured_error: 0.01,
+            catalog: Some(0.3),
+            historical: None,
+            flagged: true,
+        };
+        archive.store_check_results(run_id, &[result]).unwrap();
+        fs::copy(dir.join("run.csv"), dir.join("copy.csv")).unwrap();
+        let results = archive.check_results_for_log(&dir.join("copy.csv")).unwrap();
+        assert_eq!(results.len(), 1);
+        assert!(results[0].flagged);
+        fs::write(dir.join("other.csv"), LOG.replace("T22:", "T23:")).unwrap();
+        assert!(archive.check_results_for_log(&dir.join("other.csv")).unwrap().is_empty());
+    }
+
+    #[test]
+    fn applies_rejections_to_archived_run() {
+        let dir = test_dir("rejections");
+        let log = format!("{}{}", LOG, "2024-10-01T22:02:00Z,1,B,PGM,true,V,1000,5000,5000,5000,STAR,
+2024-10-01T22:03:00Z,1,B,PGM,false,V,1000,1000,1000,1000,SKY,
+");
+        let path = dir.join("run.csv");
+        fs::write(&path, log).unwrap();
+        let mut archive = Archive::open(&dir.join("archive.sqlite")).unwrap();
+        let run_id = archive.ingest_log(&path, "SSP3", "Completed").unwrap().unwrap();
+        assert_eq!(archive.light_curve("B").unwrap().len(), 1);
+
+        let records = read_log(&path).unwrap();
+        let rejection = rejection::Rejection { row: 2, timestamp: records[2].timestamp, reason: "cloud".to_string() };
+        rejection::save(&path, &[rejection]).unwrap();
+        assert_eq!(archive.apply_rejections(&path).unwrap(), Some(run_id));
+        assert!(archive.light_curve("B").unwrap().is_empty());
+        let query = Query { star: Some("B".to_string()), ..Query::default() };
+        assert_eq!(archive.query(&query).unwrap().len(), 1);
+
+        rejection::save(&path, &[]).unwrap();
+        assert_eq!(archive.apply_rejections(&path).unwrap(), Some(run_id));
+        assert_eq!(archive.light_curve("B").unwrap().len(), 1);
+        // Still the same run for the import
+        assert!(archive.ingest_log(&path, "SSP3", "Imported").unwrap().is_none());
+    }
+}
@@ -0,0 +1,248 @@
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// Julian date of the Unix epoch
+const JD_UNIX_EPOCH: f64 = 2440587.5;
+
+pub fn julian_date(timestamp: DateTime<Utc>) -> f64 {
+    JD_UNIX_EPOCH + timestamp.timestamp_millis() as f64 / 86_400_000.0
+}
+
+/// Equatorial coordinates for J2000 like those of the catalog, unless noted otherwise.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Equatorial {
+    pub ra_hours: f64,
+    pub dec_degrees: f64,
+}
+
+/// Offset on the sky in arc minutes, positive towards east and north.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Offset {
+    pub east: f64,
+    pub north: f64,
+}
+
+impl Equatorial {
+    pub fn offset_by(&self, offset: &Offset) -> Equatorial {
+        let dec_degrees = (self.dec_degrees + offset.north / 60.0).clamp(-90.0, 90.0);
+        // An offset towards east spans more right ascension near the poles
+        let cos_dec = self.dec_degrees.to_radians().cos().max(1e-3);
+        let ra_hours = (self.ra_hours + offset.east / 60.0 / 15.0 / cos_dec).rem_euclid(24.0);
+        Equatorial { ra_hours, dec_degrees }
+    }
+}
+
+/// Parses an angle given either as decimal number or as sexagesimal with `:` or blanks
+/// as separators, e.g. `-12:30:15.5`.
+pub fn parse_sexagesimal(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if let Ok(value) = text.parse::<f64>() {
+        return Some(value);
+    }
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(rest) => { (true, rest) }
+        None => { (false, text.strip_prefix('+').unwrap_or(text)) }
+    };
+    let parts = unsigned.split(|c: char| c == ':' || c.is_whitespace())
+        .filter(|p| !p.is_empty())
+        .map(|p| p.parse::<f64>().ok())
+        .collect::<Option<Vec<_>>>()?;
+    if parts.is_empty() || parts.len() > 3 || parts[1..].iter().any(|p| *p < 0.0 || *p >= 60.0) {
+        return None;
+    }
+    let value = parts.iter().rev().fold(0.0, |acc, p| p + acc / 60.0);
+    Some(if negative { -value } else { value })
+}
+
+/// Splits an angle into sign, whole units, minutes and whole seconds.
+pub fn to_sexagesimal(value: f64) -> (bool, u32, u32, u32) {
+    let total_seconds = (value.abs() * 3600.0).round() as u32;
+    (value < 0.0, total_seconds / 3600, total_seconds / 60 % 60, total_seconds % 60)
+}
+
+/// Geographic position of the observatory in degrees, longitude positive towards east.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Site {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+/// Days since J2000.0
+fn days_since_j2000(timestamp: DateTime<Utc>) -> f64 {
+    julian_date(timestamp) - 2451545.0
+}
+
+/// Local mean sidereal time in hours.
+pub fn local_sidereal_time(timestamp: DateTime<Utc>, site: &Site) -> f64 {
+    let gmst = 18.697374558 + 24.06570982441908 * days_since_j2000(timestamp);
+    (gmst + site.longitude / 15.0).rem_euclid(24.0)
+}
+
+impl Equatorial {
+    /// Altitude above the horizon in degrees, without refraction.
+    pub fn altitude(&self, site: &Site, timestamp: DateTime<Utc>) -> f64 {
+        let hour_angle = ((local_sidereal_time(timestamp, site) - self.ra_hours) * 15.0).to_radians();
+        let latitude = site.latitude.to_radians();
+        let dec = self.dec_degrees.to_radians();
+        (latitude.sin() * dec.sin() + latitude.cos() * dec.cos() * hour_angle.cos()).asin().to_degrees()
+    }
+
+    /// Angular distance in degrees.
+    pub fn separation(&self, other: &Equatorial) -> f64 {
+        let (ra1, dec1) = ((self.ra_hours * 15.0).to_radians(), self.dec_degrees.to_radians());
+        let (ra2, dec2) = ((other.ra_hours * 15.0).to_radians(), other.dec_degrees.to_radians());
+        let cos = dec1.sin() * dec2.sin() + dec1.cos() * dec2.cos() * (ra1 - ra2).cos();
+        cos.clamp(-1.0, 1.0).acos().to_degrees()
+    }
+
+    /// Upper culmination nearest to `around`.
+    pub fn transit(&self, site: &Site, around: DateTime<Utc>) -> DateTime<Utc> {
+        let hour_angle = (local_sidereal_time(around, site) - self.ra_hours + 12.0).rem_euclid(24.0) - 12.0;
+        // Sidereal hours run faster than solar ones
+        let solar_hours = hour_angle / 1.00273790935;
+        around - TimeDelta::milliseconds((solar_hours * 3_600_000.0) as i64)
+    }
+
+    /// Position for the mean equinox of `timestamp`, as used by most mounts.
+    pub fn precessed_from_j2000(&self, timestamp: DateTime<Utc>) -> Equatorial {
+        let (zeta, z, theta) = precession_angles(timestamp);
+        self.rotated(zeta, z, theta)
+    }
+
+    /// Inverse of `precessed_from_j2000`.
+    pub fn precessed_to_j2000(&self, timestamp: DateTime<Utc>) -> Equatorial {
+        let (zeta, z, theta) = precession_angles(timestamp);
+        self.rotated(-z, -zeta, -theta)
+    }
+
+    fn rotated(&self, zeta: f64, z: f64, theta: f64) -> Equatorial {
+        let (ra, dec) = ((self.ra_hours * 15.0).to_radians() + zeta, self.dec_degrees.to_radians());
+        let a = dec.cos() * ra.sin();
+        let b = theta.cos() * dec.cos() * ra.cos() - theta.sin() * dec.sin();
+        let c = theta.sin() * dec.cos() * ra.cos() + theta.cos() * dec.sin();
+        let ra_hours = ((a.atan2(b) + z).to_degrees() / 15.0).rem_euclid(24.0);
+        Equatorial { ra_hours, dec_degrees: c.clamp(-1.0, 1.0).asin().to_degrees() }
+    }
+
+    fn from_ecliptic(longitude: f64, latitude: f64, timestamp: DateTime<Utc>) -> Equatorial {
+        let obliquity = (23.439 - 0.0000004 * days_since_j2000(timestamp)).to_radians();
+        let (lambda, beta) = (longitude.to_radians(), latitude.to_radians());
+        let ra = (lambda.sin() * obliquity.cos() - beta.tan() * obliquity.sin()).atan2(lambda.cos());
+        let dec = (beta.sin() * obliquity.cos() + beta.cos() * obliquity.sin() * lambda.sin()).asin();
+        Equatorial { ra_hours: (ra.to_degrees() / 15.0).rem_euclid(24.0), dec_degrees: dec.to_degrees() }
+    }
+}
+
+/// Precession angles ζ, z and θ in radians from J2000 to `timestamp` (Meeus, chapter 21).
+fn precession_angles(timestamp: DateTime<Utc>) -> (f64, f64, f64) {
+    let t = days_since_j2000(timestamp) / 36525.0;
+    let arcsec = |a: f64, b: f64, c: f64| ((a + (b + c * t) * t) * t / 3600.0).to_radians();
+    (arcsec(2306.2181, 0.30188, 0.017998), arcsec(2306.2181, 1.09468, 0.018203), arcsec(2004.3109, -0.42665, -0.041833))
+}
+
+/// Airmass after Pickering (2002), `None` below the horizon.
+pub fn airmass(altitude: f64) -> Option<f64> {
+    if altitude <= 0.0 {
+        return None;
+    }
+    Some(1.0 / (altitude + 244.0 / (165.0 + 47.0 * altitude.powf(1.1))).to_radians().sin())
+}
+
+/// Position of the sun, accurate to about 0.01°.
+pub fn sun_position(timestamp: DateTime<Utc>) -> Equatorial {
+    let days = days_since_j2000(timestamp);
+    let mean_longitude = 280.460 + 0.9856474 * days;
+    let anomaly = (357.528 + 0.9856003 * days).to_radians();
+    let longitude = mean_longitude + 1.915 * anomaly.sin() + 0.020 * (2.0 * anomaly).sin();
+    Equatorial::from_ecliptic(longitude.rem_euclid(360.0), 0.0, timestamp)
+}
+
+/// Geocentric position of the moon, accurate to a few tenths of a degree, which is
+/// good enough for the distance to a star.
+pub fn moon_position(timestamp: DateTime<Utc>) -> Equatorial {
+    let t = days_since_j2000(timestamp) / 36525.0;
+    let sin = |a: f64, b: f64| (a + b * t).to_radians().sin();
+    let longitude = 218.32 + 481267.881 * t
+        + 6.29 * sin(135.0, 477198.87) - 1.27 * sin(259.3, -413335.36)
+        + 0.66 * sin(235.7, 890534.22) + 0.21 * sin(269.9, 954397.74)
+        - 0.19 * sin(357.5, 35999.05) - 0.11 * sin(186.5, 966404.03);
+    let latitude = 5.13 * sin(93.3, 483202.02) + 0.28 * sin(228.2, 960400.89)
+        - 0.28 * sin(318.3, 6003.15) - 0.17 * sin(217.6, -407332.21);
+    Equatorial::from_ecliptic(longitude.rem_euclid(360.0), latitude, timestamp)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_julian_date(jd: f64) -> DateTime<Utc> {
+        DateTime::from_timestamp_millis(((jd - JD_UNIX_EPOCH) * 86_400_000.0).round() as i64).unwrap()
+    }
+
+    #[test]
+    fn precesses_from_j2000() {
+        // θ Persei, example 21.b of Meeus
+        let j2000 = Equatorial { ra_hours: 41.054063 / 15.0, dec_degrees: 49.227750 };
+        let date = from_julian_date(2462088.69);
+        let of_date = j2000.precessed_from_j2000(date);
+        assert!((of_date.ra_hours * 15.0 - 41.547214).abs() < 1e-5, "{:?}", of_date);
+        assert!((of_date.dec_degrees - 49.348483).abs() < 1e-5, "{:?}", of_date);
+        let back = of_date.precessed_to_j2000(date);
+        assert!((back.ra_hours - j2000.ra_hours).abs() < 1e-9);
+        assert!((back.dec_degrees - j2000.dec_degrees).abs() < 1e-9);
+    }
+
+    const FRANKFURT: Site = Site { latitude: 50.1, longitude: 8.7 };
+
+    #[test]
+    fn computes_sidereal_time() {
+        // Example 12.a of Meeus, 1987 April 10 0h UT at Greenwich
+        let lst = local_sidereal_time(from_julian_date(2446895.5), &Site { latitude: 0.0, longitude: 0.0 });
+        assert!((lst - (13.0 + 10.0 / 60.0 + 46.3668 / 3600.0)).abs() < 1e-4, "{}", lst);
+    }
+
+    #[test]
+    fn computes_altitude_and_transit() {
+        let star = Equatorial { ra_hours: 5.919529, dec_degrees: 7.407064 };
+        let transit = star.transit(&FRANKFURT, from_julian_date(2460666.5));
+        assert!((star.altitude(&FRANKFURT, transit) - (90.0 - 50.1 + 7.407064)).abs() < 1e-3);
+        assert!(star.altitude(&FRANKFURT, transit + TimeDelta::hours(2)) < star.altitude(&FRANKFURT, transit));
+        let next = star.transit(&FRANKFURT, transit + TimeDelta::hours(20));
+        assert!(((next - transit).num_seconds() - 86164).abs() < 2, "{}", next - transit);
+        // Polaris barely moves
+        let polaris = Equatorial { ra_hours: 2.530301, dec_degrees: 89.264109 };
+        assert!((polaris.altitude(&FRANKFURT, transit) - 50.1).abs() < 1.0);
+    }
+
+    #[test]
+    fn computes_airmass() {
+        assert!((airmass(90.0).unwrap() - 1.0).abs() < 1e-3);
+        assert!((airmass(30.0).unwrap() - 2.0).abs() < 0.01);
+        assert!(airmass(5.0).unwrap() > 10.0);
+        assert_eq!(airmass(0.0), None);
+        assert_eq!(airmass(-10.0), None);
+    }
+
+    #[test]
+    fn computes_separation_and_offsets() {
+        let star = Equatorial { ra_hours: 12.0, dec_degrees: 60.0 };
+        let east = star.offset_by(&Offset { east: 30.0, north: 0.0 });
+        assert!((east.separation(&star) - 0.5).abs() < 1e-3, "{:?}", east);
+        assert!(east.ra_hours > star.ra_hours);
+        let north = star.offset_by(&Offset { east: 0.0, north: 30.0 });
+        assert!((north.dec_degrees - 60.5).abs() < 1e-9);
+        let pole = Equatorial { ra_hours: 0.0, dec_degrees: 90.0 };
+        assert!((pole.separation(&star) - 30.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn computes_sun_and_moon() {
+        // Example 25.a of Meeus, 1992 October 13 0h TD
+        let sun = sun_position(from_julian_date(2448908.5));
+        assert!((sun.ra_hours * 15.0 - 198.38083).abs() < 0.02, "{:?}", sun);
+        assert!((sun.dec_degrees + 7.78507).abs() < 0.02, "{:?}", sun);
+        // Example 47.a of Meeus, 1992 April 12 0h TD
+        let moon = moon_position(from_julian_date(2448724.5));
+        assert!((moon.ra_hours * 15.0 - 134.688470).abs() < 0.5, "{:?}", moon);
+        assert!((moon.dec_degrees - 13.768368).abs() < 0.5, "{:?}", moon);
+    }
+}
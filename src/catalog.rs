@@ -0,0 +1,80 @@
+use crate::astro::{parse_sexagesimal, Equatorial};
+use csv::Reader;
+use serde::Deserialize;
+use std::path::Path;
+
+/// A star of the catalog file. Magnitudes a star has no value for are left empty. RA in hours
+/// and Dec in degrees are given either decimal or sexagesimal and are optional, too.
+#[derive(Debug, Clone, Deserialize)]
+pub struct CatalogStar {
+    #[serde(rename = "Name")]
+    pub name: String,
+    #[serde(rename = "U", default)]
+    pub u: Option<f64>,
+    #[serde(rename = "B", default)]
+    pub b: Option<f64>,
+    #[serde(rename = "V", default)]
+    pub v: Option<f64>,
+    #[serde(rename = "R", default)]
+    pub r: Option<f64>,
+    #[serde(rename = "I", default)]
+    pub i: Option<f64>,
+    #[serde(rename = "RA", default)]
+    pub ra: Option<String>,
+    #[serde(rename = "Dec", default)]
+    pub dec: Option<String>,
+}
+
+impl CatalogStar {
+    pub fn magnitude(&self, filter: &str) -> Option<f64> {
+        match filter {
+            "U" => { self.u }
+            "B" => { self.b }
+            "V" => { self.v }
+            "R" => { self.r }
+            "I" => { self.i }
+            _ => { None }
+        }
+    }
+
+    pub fn position(&self) -> Option<Equatorial> {
+        let ra_hours = parse_sexagesimal(self.ra.as_deref()?)?;
+        let dec_degrees = parse_sexagesimal(self.dec.as_deref()?)?;
+        if !(0.0..24.0).contains(&ra_hours) || !(-90.0..=90.0).contains(&dec_degrees) {
+            return None;
+        }
+        Some(Equatorial { ra_hours, dec_degrees })
+    }
+}
+
+/// Reference data of the comparison and check stars, read from a CSV file.
+#[derive(Debug, Clone, Default)]
+pub struct Catalog {
+    stars: Vec<CatalogStar>,
+}
+
+impl Catalog {
+    pub fn load(path: &Path) -> Result<Catalog, csv::Error> {
+        let mut reader = Reader::from_path(path)?;
+        let stars = reader.deserialize().collect::<Result<Vec<CatalogStar>, _>>()?;
+        Ok(Catalog { stars })
+    }
+
+    pub fn stars(&self) -> &[CatalogStar] {
+        &self.stars
+    }
+
+    /// Looks up a star by name, ignoring case and surrounding blanks.
+    pub fn find(&self, name: &str) -> Option<&CatalogStar> {
+        let name = name.trim();
+        self.stars.iter().find(|star| star.name.trim().eq_ignore_ascii_case(name))
+    }
+
+    pub fn magnitude(&self, name: &str, filter: &str) -> Option<f64> {
+        self.find(name).and_then(|star| star.magnitude(filter))
+    }
+
+    pub fn position(&self, name: &str) -> Option<Equatorial> {
+        self.find(name).and_then(|star| star.position())
+    }
+}
@@ -1,4 +1,46 @@
+use crate::astro::{parse_sexagesimal, to_sexagesimal, Equatorial, Offset};
 use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+/// Type of a row in a run log.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum RowKind {
+    #[default]
+    Star,
+    Sky,
+    Dark,
+    Pause,
+    Repeat,
+    Skip,
+    Insert,
+}
+
+impl RowKind {
+    const ALL: [RowKind; 7] = [RowKind::Star, RowKind::Sky, RowKind::Dark, RowKind::Pause,
+        RowKind::Repeat, RowKind::Skip, RowKind::Insert];
+
+    /// Name as written to the run log.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RowKind::Star => { "STAR" }
+            RowKind::Sky => { "SKY" }
+            RowKind::Dark => { "DARK" }
+            RowKind::Pause => { "PAUSE" }
+            RowKind::Repeat => { "REPEAT" }
+            RowKind::Skip => { "SKIP" }
+            RowKind::Insert => { "INSERT" }
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<RowKind> {
+        RowKind::ALL.into_iter().find(|kind| kind.as_str() == name)
+    }
+}
+
+/// Star types in the order of the type drop down of the main window.
+pub const STAR_TYPES: [&str; 4] = ["CMP", "PGM", "CHK", "EXT"];
 
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct StarData {
@@ -6,13 +48,160 @@ pub struct StarData {
     // Make it compatible with PepPy
     #[serde(rename = "star_id")]
     pub name: String,
+    /// Where to measure the sky, the configured offset if not given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sky: Option<SkyPosition>,
+    /// Fixed integration times in 1/100 s by filter slot, measured without calibration
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub i_times: BTreeMap<u8, u16>,
 }
 
 impl StarData {
     pub fn new(star_type: &String, name: &String) -> StarData {
         StarData {
             star_type: star_type.clone(),
-            name: name.clone()
+            name: name.clone(),
+            sky: None,
+            i_times: BTreeMap::new(),
+        }
+    }
+}
+
+/// Compass direction of a sky offset.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Direction {
+    N,
+    NE,
+    E,
+    SE,
+    S,
+    SW,
+    W,
+    NW,
+}
+
+impl Direction {
+    const ALL: [Direction; 8] = [Direction::N, Direction::NE, Direction::E, Direction::SE,
+        Direction::S, Direction::SW, Direction::W, Direction::NW];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Direction::N => { "N" }
+            Direction::NE => { "NE" }
+            Direction::E => { "E" }
+            Direction::SE => { "SE" }
+            Direction::S => { "S" }
+            Direction::SW => { "SW" }
+            Direction::W => { "W" }
+            Direction::NW => { "NW" }
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Direction> {
+        Direction::ALL.into_iter().find(|d| d.as_str().eq_ignore_ascii_case(name))
+    }
+
+    /// Offset of `arcmin` arc minutes in this direction.
+    pub fn offset(&self, arcmin: f64) -> Offset {
+        let angle = Direction::ALL.iter().position(|d| d == self).unwrap() as f64 * 45f64.to_radians();
+        Offset { east: arcmin * angle.sin(), north: arcmin * angle.cos() }
+    }
+}
+
+/// Largest sky offset in arc minutes, farther away the sky no longer belongs to the star
+pub const MAX_SKY_OFFSET: f64 = 120.0;
+
+/// Sky position of a star, written e.g. as `5' N` or as `12:30:00 +45:10:00`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum SkyPosition {
+    /// Offset from the star
+    Offset { direction: Direction, arcmin: f64 },
+    /// Fixed position, RA in hours and Dec in degrees
+    Coordinates { ra: f64, dec: f64 },
+}
+
+impl SkyPosition {
+    pub fn parse(text: &str) -> Option<SkyPosition> {
+        let tokens = text.split_whitespace().collect::<Vec<_>>();
+        let [first, second] = tokens[..] else {
+            return None;
+        };
+        let arcmin = |token: &str| token.trim_end_matches(['\'', '′']).parse::<f64>().ok().filter(|a| *a > 0.0);
+        if let (Some(arcmin), Some(direction)) = (arcmin(first), Direction::from_name(second)) {
+            return Some(SkyPosition::Offset { direction, arcmin });
+        }
+        if let (Some(direction), Some(arcmin)) = (Direction::from_name(first), arcmin(second)) {
+            return Some(SkyPosition::Offset { direction, arcmin });
+        }
+        let ra = parse_sexagesimal(first).filter(|ra| (0.0..24.0).contains(ra))?;
+        let dec = parse_sexagesimal(second).filter(|dec| (-90.0..=90.0).contains(dec))?;
+        Some(SkyPosition::Coordinates { ra, dec })
+    }
+
+    /// Where to point for the sky of a star at `star`.
+    pub fn position(&self, star: Option<Equatorial>) -> Option<Equatorial> {
+        match self {
+            SkyPosition::Offset { direction, arcmin } => { star.map(|star| star.offset_by(&direction.offset(*arcmin))) }
+            SkyPosition::Coordinates { ra, dec } => { Some(Equatorial { ra_hours: *ra, dec_degrees: *dec }) }
+        }
+    }
+}
+
+impl fmt::Display for SkyPosition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SkyPosition::Offset { direction, arcmin } => {
+                write!(f, "{}' {}", arcmin, direction.as_str())
+            }
+            SkyPosition::Coordinates { ra, dec } => {
+                let (_, h, m, s) = to_sexagesimal(*ra);
+                let (negative, d, dm, ds) = to_sexagesimal(*dec);
+                // Seconds rounding up to the next day start at 0h again
+                write!(f, "{:02}:{:02}:{:02} {}{:02}:{:02}:{:02}", h % 24, m, s, if negative { '-' } else { '+' }, d, dm, ds)
+            }
+        }
+    }
+}
+
+/// Determines where sky measurements are placed in a run.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkyStrategy {
+    /// One sky after every star
+    #[default]
+    AfterEachStar,
+    /// A sky directly before and directly after every star
+    BeforeAndAfterEachStar,
+    /// One sky after each adjacent CMP/PGM pair
+    PerPair,
+    /// One sky whenever the next star differs from the current one
+    OnStarChange,
+}
+
+impl SkyStrategy {
+    /// Same order as in the sky drop down of the main window.
+    pub const ALL: [SkyStrategy; 4] = [SkyStrategy::AfterEachStar, SkyStrategy::BeforeAndAfterEachStar,
+        SkyStrategy::PerPair, SkyStrategy::OnStarChange];
+
+    fn is_default(&self) -> bool {
+        *self == SkyStrategy::default()
+    }
+}
+
+/// A single step of a run. The index refers to the run items; for a sky it is
+/// the star the sky belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Star(u16),
+    Sky(u16),
+}
+
+impl Step {
+    pub fn index(&self) -> u16 {
+        match self {
+            Step::Star(index) | Step::Sky(index) => { *index }
         }
     }
 }
@@ -21,6 +210,9 @@ impl StarData {
 pub struct PepRun {
     pub filters: Vec<u8>,
     pub items: Vec<StarData>,
+    // Omitted for the default to stay compatible with PepPy
+    #[serde(default, skip_serializing_if = "SkyStrategy::is_default")]
+    pub sky_strategy: SkyStrategy,
 }
 
 impl PepRun {
@@ -28,6 +220,90 @@ impl PepRun {
         PepRun {
             filters,
             items,
+            sky_strategy: SkyStrategy::default(),
+        }
+    }
+
+    /// Sequence of star and sky measurements according to the sky strategy.
+    pub fn steps(&self) -> Vec<Step> {
+        let count = self.items.len();
+        let mut steps = Vec::with_capacity(3 * count);
+        let mut index = 0;
+        while index < count {
+            let star = index as u16;
+            match self.sky_strategy {
+                SkyStrategy::AfterEachStar | SkyStrategy::BeforeAndAfterEachStar => {
+                    steps.extend(self.star_steps(star));
+                }
+                SkyStrategy::PerPair => {
+                    steps.push(Step::Star(star));
+                    if index + 1 < count && self.is_pair(index) {
+                        index += 1;
+                        steps.push(Step::Star(star + 1));
+                    }
+                    steps.push(Step::Sky(index as u16));
+                }
+                SkyStrategy::OnStarChange => {
+                    steps.push(Step::Star(star));
+                    if index + 1 == count || self.items[index + 1].name != self.items[index].name {
+                        steps.push(Step::Sky(star));
+                    }
+                }
+            }
+            index += 1;
+        }
+        steps
+    }
+
+    /// Steps for measuring a single star with its own sky.
+    pub fn star_steps(&self, star: u16) -> Vec<Step> {
+        match self.sky_strategy {
+            SkyStrategy::BeforeAndAfterEachStar => { vec![Step::Sky(star), Step::Star(star), Step::Sky(star)] }
+            _ => { vec![Step::Star(star), Step::Sky(star)] }
+        }
+    }
+
+    fn is_pair(&self, index: usize) -> bool {
+        let first = self.items[index].star_type.as_str();
+        let second = self.items[index + 1].star_type.as_str();
+        matches!((first, second), ("CMP", "PGM") | ("PGM", "CMP"))
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sky_offsets() {
+        let offset = Some(SkyPosition::Offset { direction: Direction::NE, arcmin: 5.0 });
+        assert_eq!(SkyPosition::parse("5' NE"), offset);
+        assert_eq!(SkyPosition::parse(" ne 5 "), offset);
+        assert_eq!(SkyPosition::parse("5′ ne"), offset);
+        assert_eq!(SkyPosition::parse("0' N"), None);
+        assert_eq!(SkyPosition::parse("-5' N"), None);
+        assert_eq!(SkyPosition::parse("5' NNE"), None);
+        assert_eq!(SkyPosition::parse("5' N E"), None);
+        assert_eq!(SkyPosition::parse(""), None);
+    }
+
+    #[test]
+    fn parses_sky_coordinates() {
+        assert_eq!(SkyPosition::parse("12:30:00 +45:10:00"), Some(SkyPosition::Coordinates { ra: 12.5, dec: 45.0 + 10.0 / 60.0 }));
+        assert_eq!(SkyPosition::parse("6.25 -30.5"), Some(SkyPosition::Coordinates { ra: 6.25, dec: -30.5 }));
+        assert_eq!(SkyPosition::parse("24:00:00 +45:00:00"), None);
+        assert_eq!(SkyPosition::parse("12:00:00 +91:00:00"), None);
+        assert_eq!(SkyPosition::parse("12:60:00 +45:00:00"), None);
+    }
+
+    #[test]
+    fn writes_parsable_sky_positions() {
+        for (sky, text) in [
+            (SkyPosition::Offset { direction: Direction::SW, arcmin: 2.5 }, "2.5' SW"),
+            (SkyPosition::Coordinates { ra: 12.5, dec: -0.5 }, "12:30:00 -00:30:00"),
+            (SkyPosition::Coordinates { ra: 23.0 + 59.0 / 60.0 + 59.8 / 3600.0, dec: 89.99999 }, "00:00:00 +90:00:00"),
+        ] {
+            assert_eq!(sky.to_string(), text);
+            assert!(SkyPosition::parse(text).is_some(), "{}", text);
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,195 @@
+//! Estimate of how long a run takes.
+
+use crate::astro::Equatorial;
+use crate::catalog::Catalog;
+use crate::common::PepRun;
+use crate::measurement::{initial_i_time, DarkSchedule, IntegrationTimes, DARK_I_TIME};
+use chrono::TimeDelta;
+
+/// Counts taken per filter, as written to a row of the run log.
+const COUNTS_PER_FILTER: i32 = 3;
+/// Transfer of the counts and starting the next integration.
+const COUNT_OVERHEAD: TimeDelta = TimeDelta::milliseconds(500);
+const FILTER_CHANGE_TIME: TimeDelta = TimeDelta::seconds(5);
+/// Slew speed of a typical mount in degrees per second.
+const SLEW_RATE: f64 = 3.0;
+/// Time to center a star once the mount has slewed to it.
+const CENTERING_TIME: TimeDelta = TimeDelta::seconds(60);
+/// Time for the operator to find and center a star by hand.
+const MANUAL_POINTING_TIME: TimeDelta = TimeDelta::seconds(120);
+/// Time to move between a star and its sky.
+const SKY_MOVE_TIME: TimeDelta = TimeDelta::seconds(20);
+
+/// Expected time of a run, split by activity.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Estimate {
+    /// Counting on stars and sky, including calibration and filter changes
+    pub measuring: TimeDelta,
+    /// Slewing, centering and moving to the sky
+    pub pointing: TimeDelta,
+    pub darks: TimeDelta,
+}
+
+impl Estimate {
+    pub fn total(&self) -> TimeDelta {
+        self.measuring + self.pointing + self.darks
+    }
+}
+
+/// What the duration of a run depends on besides the run itself.
+pub struct Conditions<'a> {
+    /// Integration times remembered from earlier runs, where calibrating a star starts
+    pub i_times: &'a IntegrationTimes,
+    /// Positions of the stars for the slews between them
+    pub catalog: Option<&'a Catalog>,
+    /// Whether a mount slews to the stars
+    pub mount: bool,
+    pub dark_schedule: Option<&'a DarkSchedule>,
+}
+
+pub fn slew_time(from: &Equatorial, to: &Equatorial) -> TimeDelta {
+    TimeDelta::milliseconds((from.separation(to) / SLEW_RATE * 1000.0).round() as i64)
+}
+
+/// Time of counting with an integration time in 1/100 s.
+fn counting_time(i_time: u16, counts: i32) -> TimeDelta {
+    (TimeDelta::milliseconds(10 * i_time as i64) + COUNT_OVERHEAD) * counts
+}
+
+/// Estimated duration of `run` without interruptions. Stars are calibrated the first time they
+/// are measured in a filter without a fixed integration time, assuming it takes both extra
+/// counts and ends with the remembered or initial integration time it starts from.
+pub fn estimate(run: &PepRun, conditions: &Conditions) -> Estimate {
+    let mut calibrated = IntegrationTimes::new();
+    let mut result = Estimate::default();
+    let mut previous_index: Option<u16> = None;
+    let mut previous_position: Option<Equatorial> = None;
+    for step in run.steps() {
+        let star = &run.items[step.index() as usize];
+        let position = conditions.catalog.and_then(|catalog| catalog.position(&star.name));
+        result.pointing += match (previous_index, position) {
+            // Between a star and its own sky
+            (Some(index), _) if index == step.index() => { SKY_MOVE_TIME }
+            (_, Some(position)) if conditions.mount => {
+                previous_position.map(|from| slew_time(&from, &position)).unwrap_or_default() + CENTERING_TIME
+            }
+            _ => { MANUAL_POINTING_TIME }
+        };
+        previous_index = Some(step.index());
+        previous_position = position.or(previous_position);
+
+        let remembered = conditions.i_times.get(&star.name);
+        let star_i_times = calibrated.entry(star.name.clone()).or_default();
+        for filter in run.filters.iter() {
+            result.measuring += FILTER_CHANGE_TIME;
+            let i_time = match star.i_times.get(filter).or(star_i_times.get(filter)) {
+                Some(i_time) => { *i_time }
+                None => {
+                    let initial = remembered.and_then(|times| times.get(filter)).copied()
+                        .unwrap_or_else(|| initial_i_time(*filter));
+                    result.measuring += counting_time(initial, 1) + counting_time(initial + 500, 1);
+                    star_i_times.insert(*filter, initial);
+                    initial
+                }
+            };
+            result.measuring += counting_time(i_time, COUNTS_PER_FILTER);
+        }
+    }
+
+    if let Some(schedule) = conditions.dark_schedule {
+        // At the start, at the end, and whenever the interval has elapsed
+        let mut count = 2;
+        if schedule.interval > TimeDelta::zero() {
+            count += ((result.measuring + result.pointing).num_seconds() / schedule.interval.num_seconds()) as i32;
+        }
+        result.darks = (counting_time(DARK_I_TIME, COUNTS_PER_FILTER) + FILTER_CHANGE_TIME) * count;
+    }
+    result
+}
+
+/// Duration as shown to the user, e.g. "1 h 05 min".
+pub fn format_duration(duration: TimeDelta) -> String {
+    let minutes = (duration.num_seconds() + 30) / 60;
+    if minutes < 60 {
+        format!("{} min", minutes)
+    } else {
+        format!("{} h {:02} min", minutes / 60, minutes % 60)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::StarData;
+    use std::collections::HashMap;
+
+    /// V filter slot
+    const V: u8 = 2;
+
+    fn run(names: &[&str]) -> PepRun {
+        let items = names.iter().map(|name| StarData::new(&"PGM".to_string(), &name.to_string())).collect();
+        PepRun::new(vec![V], items)
+    }
+
+    fn conditions<'a>(i_times: &'a IntegrationTimes, dark_schedule: Option<&'a DarkSchedule>) -> Conditions<'a> {
+        Conditions { i_times, catalog: None, mount: false, dark_schedule }
+    }
+
+    #[test]
+    fn estimates_star_with_fixed_time() {
+        let i_times = HashMap::from([("A".to_string(), HashMap::from([(V, 2000)]))]);
+        let mut fixed = run(&["A"]);
+        fixed.items[0].i_times.insert(V, 1000);
+        let estimate = estimate(&fixed, &conditions(&i_times, None));
+        // Star and sky, each with a filter change and three counts of 10 s
+        assert_eq!(estimate.measuring, TimeDelta::milliseconds(2 * (5000 + 3 * 10_500)));
+        assert_eq!(estimate.pointing, MANUAL_POINTING_TIME + SKY_MOVE_TIME);
+        assert_eq!(estimate.darks, TimeDelta::zero());
+        assert_eq!(estimate.total(), estimate.measuring + estimate.pointing);
+    }
+
+    #[test]
+    fn calibrates_stars_once_from_remembered_time() {
+        let steps = |i_time| (FILTER_CHANGE_TIME + counting_time(i_time, COUNTS_PER_FILTER)) * 4;
+        let unknown = estimate(&run(&["A", "A"]), &conditions(&IntegrationTimes::new(), None));
+        assert_eq!(unknown.measuring, steps(1000) + counting_time(1000, 1) + counting_time(1500, 1));
+        // Calibrating starts from the remembered time
+        let remembered = HashMap::from([("A".to_string(), HashMap::from([(V, 500)]))]);
+        let known = estimate(&run(&["A", "A"]), &conditions(&remembered, None));
+        assert_eq!(known.measuring, steps(500) + counting_time(500, 1) + counting_time(1000, 1));
+    }
+
+    #[test]
+    fn adds_slews_and_darks() {
+        let i_times = IntegrationTimes::new();
+        let mut conditions = conditions(&i_times, None);
+        let by_hand = estimate(&run(&["A", "B"]), &conditions);
+        assert_eq!(by_hand.pointing, (MANUAL_POINTING_TIME + SKY_MOVE_TIME) * 2);
+        // Without positions the mount doesn't help
+        conditions.mount = true;
+        assert_eq!(estimate(&run(&["A", "B"]), &conditions).pointing, by_hand.pointing);
+
+        let once = DarkSchedule { interval: TimeDelta::zero() };
+        conditions.dark_schedule = Some(&once);
+        let dark_set = counting_time(DARK_I_TIME, COUNTS_PER_FILTER) + FILTER_CHANGE_TIME;
+        assert_eq!(estimate(&run(&["A", "B"]), &conditions).darks, dark_set * 2);
+        let often = DarkSchedule { interval: TimeDelta::minutes(1) };
+        conditions.dark_schedule = Some(&often);
+        let minutes = (by_hand.measuring + by_hand.pointing).num_minutes() as i32;
+        assert_eq!(estimate(&run(&["A", "B"]), &conditions).darks, dark_set * (2 + minutes));
+    }
+
+    #[test]
+    fn estimates_slew_time() {
+        let from = Equatorial { ra_hours: 0.0, dec_degrees: 0.0 };
+        assert_eq!(slew_time(&from, &Equatorial { ra_hours: 2.0, dec_degrees: 0.0 }), TimeDelta::seconds(10));
+        assert_eq!(slew_time(&from, &from), TimeDelta::zero());
+    }
+
+    #[test]
+    fn formats_duration() {
+        assert_eq!(format_duration(TimeDelta::seconds(29)), "0 min");
+        assert_eq!(format_duration(TimeDelta::seconds(90)), "2 min");
+        assert_eq!(format_duration(TimeDelta::seconds(3600 + 125)), "1 h 02 min");
+    }
+}
@@ -0,0 +1,391 @@
+//! Client for the INDI XML protocol, talking to a mount driver and optionally a focuser
+//! driver through an indiserver.
+
+use crate::astro::{parse_sexagesimal, Equatorial};
+use crate::mount::{Error, Mount, PierSide, Pointing};
+use quick_xml::escape::escape;
+use quick_xml::events::{BytesStart, Event};
+use quick_xml::Reader;
+use std::collections::HashMap;
+use std::io::{BufReader, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::thread;
+
+pub const DEFAULT_PORT: u16 = 7624;
+
+/// How long the driver may take to define its properties after connecting.
+const DEFINE_TIMEOUT: Duration = Duration::from_secs(10);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// How long the driver may take to report a requested slew as busy.
+const ACCEPT_TIMEOUT: Duration = Duration::from_secs(5);
+
+const COORDINATES: &str = "EQUATORIAL_EOD_COORD";
+const FOCUS_POSITION: &str = "ABS_FOCUS_POSITION";
+
+/// Last known state and element values of a property vector.
+#[derive(Debug, Default)]
+struct Property {
+    state: String,
+    values: HashMap<String, String>,
+    /// Number of updates received
+    generation: u64,
+    /// Generation of the last update reporting the property busy
+    busy_generation: u64,
+}
+
+/// Properties as sent by the server, kept up to date by the reader thread.
+#[derive(Debug, Default)]
+struct Properties {
+    by_name: HashMap<(String, String), Property>,
+    closed: bool,
+}
+
+/// Vector being parsed by the reader thread.
+struct Update {
+    device: String,
+    name: String,
+    state: Option<String>,
+    values: Vec<(String, String)>,
+}
+
+pub struct Indi {
+    stream: TcpStream,
+    device: String,
+    focuser: Option<String>,
+    properties: Arc<Mutex<Properties>>,
+    /// Generation of the coordinates when the last slew was requested, and when that was
+    slew: Option<(u64, Instant)>,
+}
+
+impl Indi {
+    /// Connects to the server at `address`, given as `host` or `host:port`, to the mount
+    /// driver named `device`, e.g. "Telescope Simulator", and to the `focuser` driver if given.
+    pub fn new(address: &str, device: &str, focuser: Option<&str>) -> Result<Indi, Error> {
+        let address = if address.contains(':') { address.to_string() } else { format!("{}:{}", address, DEFAULT_PORT) };
+        let stream = TcpStream::connect(&address)?;
+        let properties = Arc::new(Mutex::new(Properties::default()));
+        let reader_stream = stream.try_clone()?;
+        let reader_properties = properties.clone();
+        thread::spawn(move || {
+            read_properties(reader_stream, &reader_properties);
+            reader_properties.lock().unwrap().closed = true;
+        });
+
+        let mut indi = Indi { stream, device: device.to_string(), focuser: focuser.map(|f| f.to_string()), properties, slew: None };
+        indi.connect_driver(device, COORDINATES)?;
+        if let Some(focuser) = focuser {
+            indi.connect_driver(focuser, FOCUS_POSITION)?;
+        }
+        Ok(indi)
+    }
+
+    /// Has a driver connect to its device and waits for the property defined once it has.
+    fn connect_driver(&mut self, device: &str, property: &str) -> Result<(), Error> {
+        self.send(&format!("<getProperties version=\"1.7\" device=\"{}\"/>", escape(device)))?;
+        self.wait_for(device, "CONNECTION")?;
+        if self.device_value(device, "CONNECTION", "CONNECT").as_deref() != Some("On") {
+            self.device_switch(device, "CONNECTION", "CONNECT")?;
+        }
+        self.wait_for(device, property)
+    }
+
+    fn send(&mut self, xml: &str) -> Result<(), Error> {
+        self.stream.write_all(xml.as_bytes())?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    fn wait_for(&self, device: &str, name: &str) -> Result<(), Error> {
+        let deadline = Instant::now() + DEFINE_TIMEOUT;
+        loop {
+            let properties = self.properties.lock().unwrap();
+            if properties.by_name.contains_key(&(device.to_string(), name.to_string())) {
+                return Ok(());
+            }
+            if properties.closed {
+                return Err(Error::Protocol("connection to INDI server closed".to_string()));
+            }
+            drop(properties);
+            if Instant::now() >= deadline {
+                return Err(Error::Protocol(format!("{} did not define {}", device, name)));
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn with_property<T>(&self, name: &str, f: impl FnOnce(Option<&mut Property>) -> T) -> Result<T, Error> {
+        let mut properties = self.properties.lock().unwrap();
+        if properties.closed {
+            return Err(Error::Protocol("connection to INDI server closed".to_string()));
+        }
+        Ok(f(properties.by_name.get_mut(&(self.device.clone(), name.to_string()))))
+    }
+
+    fn value(&self, name: &str, element: &str) -> Option<String> {
+        self.device_value(&self.device, name, element)
+    }
+
+    fn device_value(&self, device: &str, name: &str, element: &str) -> Option<String> {
+        let properties = self.properties.lock().unwrap();
+        properties.by_name.get(&(device.to_string(), name.to_string())).and_then(|p| p.values.get(element).cloned())
+    }
+
+    /// Turns on one switch of a vector, the driver turns off the others if exclusive.
+    fn switch(&mut self, name: &str, element: &str) -> Result<(), Error> {
+        let device = self.device.clone();
+        self.device_switch(&device, name, element)
+    }
+
+    fn device_switch(&mut self, device: &str, name: &str, element: &str) -> Result<(), Error> {
+        let xml = format!("<newSwitchVector device=\"{}\" name=\"{}\"><oneSwitch name=\"{}\">On</oneSwitch></newSwitchVector>",
+                          escape(device), name, element);
+        self.send(&xml)
+    }
+
+    /// Sets the coordinates, what the mount does with them depends on ON_COORD_SET.
+    fn send_coordinates(&mut self, position: &Equatorial) -> Result<(), Error> {
+        let xml = format!("<newNumberVector device=\"{}\" name=\"{}\"><oneNumber name=\"RA\">{:.6}</oneNumber><oneNumber name=\"DEC\">{:.6}</oneNumber></newNumberVector>",
+                          escape(&self.device), COORDINATES, position.ra_hours, position.dec_degrees);
+        self.send(&xml)
+    }
+
+    fn number(&self, name: &str, element: &str) -> Result<f64, Error> {
+        self.device_number(&self.device, name, element)
+    }
+
+    fn device_number(&self, device: &str, name: &str, element: &str) -> Result<f64, Error> {
+        let value = self.device_value(device, name, element)
+            .ok_or_else(|| Error::Protocol(format!("{} has no {}.{}", device, name, element)))?;
+        parse_sexagesimal(&value)
+            .ok_or_else(|| Error::Protocol(format!("invalid value {} of {}.{}", value, name, element)))
+    }
+}
+
+impl Mount for Indi {
+    fn slew_to(&mut self, target: &Equatorial) -> Result<(), Error> {
+        // Keep tracking once the target is reached
+        self.switch("ON_COORD_SET", "TRACK")?;
+        let generation = self.with_property(COORDINATES, |p| p.map(|p| p.generation).unwrap_or_default())?;
+        self.slew = Some((generation, Instant::now()));
+        self.send_coordinates(target)
+    }
+
+    fn is_slewing(&mut self) -> Result<bool, Error> {
+        let (state, generation, busy_generation) = self.with_property(COORDINATES, |p| {
+            p.map(|p| (p.state.clone(), p.generation, p.busy_generation)).unwrap_or_default()
+        })?;
+        // Updates queued before the driver took up the slew still report the previous state
+        if let Some((requested, since)) = self.slew {
+            let accepted = busy_generation > requested || (state == "Alert" && generation > requested);
+            if !accepted && since.elapsed() < ACCEPT_TIMEOUT {
+                return Ok(true);
+            }
+        }
+        match state.as_str() {
+            "Busy" => { Ok(true) }
+            "Alert" => { Err(Error::Rejected("slew failed".to_string())) }
+            _ => { Ok(false) }
+        }
+    }
+
+    fn abort(&mut self) -> Result<(), Error> {
+        self.switch("TELESCOPE_ABORT_MOTION", "ABORT")
+    }
+
+    fn pointing(&mut self) -> Result<Pointing, Error> {
+        let position = Equatorial {
+            ra_hours: self.number(COORDINATES, "RA")?,
+            dec_degrees: self.number(COORDINATES, "DEC")?,
+        };
+        let pier_side = if self.value("TELESCOPE_PIER_SIDE", "PIER_EAST").as_deref() == Some("On") {
+            Some(PierSide::East)
+        } else if self.value("TELESCOPE_PIER_SIDE", "PIER_WEST").as_deref() == Some("On") {
+            Some(PierSide::West)
+        } else {
+            None
+        };
+        let focus_position = match &self.focuser {
+            Some(focuser) => { Some(self.device_number(focuser, FOCUS_POSITION, "FOCUS_ABSOLUTE_POSITION")?) }
+            None => { None }
+        };
+        Ok(Pointing { position, pier_side, focus_position })
+    }
+
+    fn sync(&mut self, position: &Equatorial) -> Result<(), Error> {
+        self.switch("ON_COORD_SET", "SYNC")?;
+        self.send_coordinates(position)
+    }
+}
+
+impl Drop for Indi {
+    fn drop(&mut self) {
+        // Ends the reader thread, which holds a clone of the stream
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// Parses the messages of the server until the connection is closed. The server sends a
+/// sequence of elements without a common root, which is why the reader is fed directly.
+fn read_properties(stream: TcpStream, properties: &Mutex<Properties>) {
+    let mut reader = Reader::from_reader(BufReader::new(stream));
+    reader.config_mut().trim_text(true);
+    let mut buffer = Vec::new();
+    let mut update: Option<Update> = None;
+    let mut element: Option<(String, String)> = None;
+    loop {
+        let event = match reader.read_event_into(&mut buffer) {
+            Ok(event) => { event }
+            Err(e) => {
+                eprintln!("INDI: {}", e);
+                return;
+            }
+        };
+        match event {
+            Event::Start(start) => {
+                let tag = start.name();
+                if is_vector(tag.as_ref()) {
+                    update = Some(Update {
+                        device: attribute(&start, "device").unwrap_or_default(),
+                        name: attribute(&start, "name").unwrap_or_default(),
+                        state: attribute(&start, "state"),
+                        values: Vec::new(),
+                    });
+                } else if update.is_some() {
+                    element = attribute(&start, "name").map(|name| (name, String::new()));
+                }
+            }
+            Event::Empty(empty) => {
+                let tag = empty.name();
+                if is_vector(tag.as_ref()) {
+                    apply(properties, Update {
+                        device: attribute(&empty, "device").unwrap_or_default(),
+                        name: attribute(&empty, "name").unwrap_or_default(),
+                        state: attribute(&empty, "state"),
+                        values: Vec::new(),
+                    });
+                } else if tag.as_ref() == b"delProperty" {
+                    let device = attribute(&empty, "device").unwrap_or_default();
+                    let name = attribute(&empty, "name");
+                    properties.lock().unwrap().by_name
+                        .retain(|key, _| key.0 != device || name.as_ref().is_some_and(|name| key.1 != *name));
+                }
+            }
+            Event::Text(text) => {
+                if let Some((_, value)) = element.as_mut() {
+                    value.push_str(&text.unescape().unwrap_or_default());
+                }
+            }
+            Event::End(end) => {
+                if is_vector(end.name().as_ref()) {
+                    if let Some(update) = update.take() {
+                        apply(properties, update);
+                    }
+                } else if let (Some(update), Some(value)) = (update.as_mut(), element.take()) {
+                    update.values.push(value);
+                }
+            }
+            Event::Eof => { return; }
+            _ => {}
+        }
+        buffer.clear();
+    }
+}
+
+fn is_vector(tag: &[u8]) -> bool {
+    (tag.starts_with(b"def") || tag.starts_with(b"set")) && tag.ends_with(b"Vector")
+}
+
+fn attribute(start: &BytesStart, name: &str) -> Option<String> {
+    start.attributes()
+        .filter_map(|a| a.ok())
+        .find(|a| a.key.as_ref() == name.as_bytes())
+        .and_then(|a| a.unescape_value().ok().map(|v| v.to_string()))
+}
+
+fn apply(properties: &Mutex<Properties>, update: Update) {
+    let mut properties = properties.lock().unwrap();
+    let property = properties.by_name.entry((update.device, update.name)).or_default();
+    property.generation += 1;
+    if let Some(state) = update.state {
+        if state == "Busy" {
+            property.busy_generation = property.generation;
+        }
+        property.state = state;
+    }
+    for (name, value) in update.values {
+        property.values.insert(name, value.trim().to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+
+    const MOUNT: &str = "Telescope Simulator";
+    const FOCUSER: &str = "Focuser Simulator";
+
+    fn coordinates(state: &str, ra: f64, dec: f64) -> String {
+        format!("<setNumberVector device=\"{}\" name=\"{}\" state=\"{}\"><oneNumber name=\"RA\">{}</oneNumber><oneNumber name=\"DEC\">{}</oneNumber></setNumberVector>",
+                MOUNT, COORDINATES, state, ra, dec)
+    }
+
+    /// Waits until the reader thread has seen the mount at `ra`.
+    fn wait_for_ra(indi: &mut Indi, ra: f64) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while (indi.pointing().unwrap().position.ra_hours - ra).abs() > 1e-9 {
+            assert!(Instant::now() < deadline, "mount never reported RA {}", ra);
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    /// Plays the simulator drivers of an indiserver, which define their properties when asked.
+    #[test]
+    fn slews_like_simulator() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            for device in [MOUNT, FOCUSER] {
+                write!(stream, "<defSwitchVector device=\"{}\" name=\"CONNECTION\" state=\"Ok\"><defSwitch name=\"CONNECT\">On</defSwitch><defSwitch name=\"DISCONNECT\">Off</defSwitch></defSwitchVector>", device).unwrap();
+            }
+            write!(stream, "<defNumberVector device=\"{}\" name=\"{}\" state=\"Ok\"><defNumber name=\"RA\">1:00:00</defNumber><defNumber name=\"DEC\">10</defNumber></defNumberVector>",
+                   MOUNT, COORDINATES).unwrap();
+            write!(stream, "<defSwitchVector device=\"{}\" name=\"TELESCOPE_PIER_SIDE\" state=\"Ok\"><defSwitch name=\"PIER_EAST\">Off</defSwitch><defSwitch name=\"PIER_WEST\">On</defSwitch></defSwitchVector>",
+                   MOUNT).unwrap();
+            write!(stream, "<defNumberVector device=\"{}\" name=\"{}\" state=\"Ok\"><defNumber name=\"FOCUS_ABSOLUTE_POSITION\">12000</defNumber></defNumberVector>",
+                   FOCUSER, FOCUS_POSITION).unwrap();
+            stream
+        });
+        let mut indi = Indi::new(&address, MOUNT, Some(FOCUSER)).unwrap();
+        let mut server = server.join().unwrap();
+
+        let pointing = indi.pointing().unwrap();
+        assert!((pointing.position.ra_hours - 1.0).abs() < 1e-9);
+        assert_eq!(pointing.pier_side, Some(PierSide::West));
+        assert_eq!(pointing.focus_position, Some(12000.0));
+
+        indi.slew_to(&Equatorial { ra_hours: 2.0, dec_degrees: 20.0 }).unwrap();
+        // Tracking update sent before the driver took up the slew
+        server.write_all(coordinates("Ok", 1.5, 10.0).as_bytes()).unwrap();
+        wait_for_ra(&mut indi, 1.5);
+        assert!(indi.is_slewing().unwrap());
+        server.write_all(coordinates("Busy", 1.75, 15.0).as_bytes()).unwrap();
+        wait_for_ra(&mut indi, 1.75);
+        assert!(indi.is_slewing().unwrap());
+        server.write_all(coordinates("Ok", 2.0, 20.0).as_bytes()).unwrap();
+        wait_for_ra(&mut indi, 2.0);
+        assert!(!indi.is_slewing().unwrap());
+
+        drop(indi);
+        server.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let mut received = String::new();
+        server.read_to_string(&mut received).unwrap();
+        assert!(received.contains("<getProperties version=\"1.7\" device=\"Focuser Simulator\"/>"));
+        assert!(received.contains("<oneSwitch name=\"TRACK\">On</oneSwitch>"));
+        assert!(received.contains("<oneNumber name=\"RA\">2.000000</oneNumber>"));
+    }
+}
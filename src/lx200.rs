@@ -0,0 +1,128 @@
+//! Meade LX200 command set, as understood by most serial mounts.
+
+use crate::astro::{parse_sexagesimal, to_sexagesimal, Equatorial};
+use crate::mount::{Error, Mount, Pointing};
+use serial::prelude::*;
+use serial::SystemPort;
+use std::ffi::OsStr;
+use std::io::{Read, Write};
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(3);
+
+pub struct Lx200 {
+    port: Box<SystemPort>,
+}
+
+impl Lx200 {
+    pub fn new<T: AsRef<OsStr> + ?Sized>(port: &T) -> Result<Lx200, Error> {
+        let mut port = serial::open(port)?;
+        port.reconfigure(&|settings| {
+            settings.set_baud_rate(serial::Baud9600)?;
+            settings.set_char_size(serial::Bits8);
+            settings.set_parity(serial::ParityNone);
+            settings.set_stop_bits(serial::Stop1);
+            Ok(())
+        })?;
+        port.set_timeout(TIMEOUT)?;
+        Ok(Lx200 { port: Box::new(port) })
+    }
+
+    fn write(&mut self, command: &str) -> Result<(), Error> {
+        self.port.write_all(command.as_bytes())?;
+        self.port.flush()?;
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error> {
+        let mut buffer = [0u8; 1];
+        self.port.read_exact(&mut buffer)?;
+        Ok(buffer[0])
+    }
+
+    /// Reads a response terminated by `#`, without the terminator.
+    fn read_string(&mut self) -> Result<String, Error> {
+        let mut response = Vec::new();
+        loop {
+            match self.read_byte()? {
+                b'#' => { break; }
+                byte => { response.push(byte); }
+            }
+        }
+        Ok(String::from_utf8_lossy(&response).to_string())
+    }
+
+    /// Sends a command the mount answers with `1` if accepted.
+    fn set(&mut self, command: &str) -> Result<(), Error> {
+        self.write(command)?;
+        match self.read_byte()? {
+            b'1' => { Ok(()) }
+            _ => { Err(Error::Rejected(format!("{} rejected", command))) }
+        }
+    }
+
+    /// Sets the object coordinates used by slews and syncs.
+    fn set_target(&mut self, target: &Equatorial) -> Result<(), Error> {
+        let (_, h, m, s) = to_sexagesimal(target.ra_hours);
+        self.set(&format!(":Sr {:02}:{:02}:{:02}#", h % 24, m, s))?;
+        let (negative, d, m, s) = to_sexagesimal(target.dec_degrees);
+        self.set(&format!(":Sd {}{:02}*{:02}:{:02}#", if negative { '-' } else { '+' }, d, m, s))
+    }
+
+    fn get_angle(&mut self, command: &str) -> Result<f64, Error> {
+        self.write(command)?;
+        let response = self.read_string()?;
+        // Degrees are separated by '*' or the degree sign, low precision uses tenths of minutes
+        let normalized = response.replace(['*', '\u{df}', '\u{b0}', '\''], ":");
+        let (whole, fraction) = match normalized.split_once('.') {
+            Some((whole, tenths)) if whole.matches(':').count() == 1 => {
+                (whole.to_string(), tenths.parse::<f64>().map(|t| t / 10.0 / 60.0).unwrap_or(0.0))
+            }
+            _ => { (normalized.clone(), 0.0) }
+        };
+        let value = parse_sexagesimal(&whole)
+            .ok_or_else(|| Error::Protocol(format!("invalid response {} to {}", response, command)))?;
+        Ok(if whole.trim_start().starts_with('-') { value - fraction } else { value + fraction })
+    }
+}
+
+impl Mount for Lx200 {
+    fn slew_to(&mut self, target: &Equatorial) -> Result<(), Error> {
+        self.set_target(target)?;
+        self.write(":MS#")?;
+        match self.read_byte()? {
+            b'0' => { Ok(()) }
+            _ => {
+                let reason = self.read_string()?;
+                Err(Error::Rejected(format!("slew refused: {}", reason.trim())))
+            }
+        }
+    }
+
+    fn is_slewing(&mut self) -> Result<bool, Error> {
+        // The distance bars are empty once the mount has arrived
+        self.write(":D#")?;
+        Ok(!self.read_string()?.trim().is_empty())
+    }
+
+    fn abort(&mut self) -> Result<(), Error> {
+        self.write(":Q#")
+    }
+
+    fn pointing(&mut self) -> Result<Pointing, Error> {
+        let position = Equatorial {
+            ra_hours: self.get_angle(":GR#")?,
+            dec_degrees: self.get_angle(":GD#")?,
+        };
+        // The classic command set has no way to query the pier side
+        Ok(Pointing { position, pier_side: None, focus_position: None })
+    }
+
+    fn sync(&mut self, position: &Equatorial) -> Result<(), Error> {
+        self.set_target(position)?;
+        // Answered with the name of the object synced to
+        self.write(":CM#")?;
+        self.read_string()?;
+        Ok(())
+    }
+}
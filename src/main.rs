@@ -3,6 +3,22 @@ mod common;
 mod util;
 mod ssp3;
 mod measurement;
+mod reduction;
+mod archive;
+mod astro;
+mod catalog;
+mod peppy;
+mod period;
+mod qc;
+mod rejection;
+mod run_file;
+mod mount;
+mod lx200;
+mod indi;
+mod alpaca;
+mod planner;
+mod scheduler;
+mod estimate;
 
 use gtk::prelude::*;
 use gtk::{gio, glib, Application};
@@ -1,31 +1,53 @@
-use crate::common::PepRun;
+use crate::archive::Archive;
+use crate::astro::{Equatorial, Offset};
+use crate::catalog::Catalog;
+use crate::common::{PepRun, RowKind, Step};
 use crate::measurement::SspRequest::{Finish, Measure};
-use crate::ssp3;
+use crate::mount::{MountHandle, MountSettings, PierSide};
+use crate::{mount, qc, ssp3};
 use crate::ssp3::Ssp3;
-use crate::util::show_error;
+use crate::util::{ask_text, show_error, show_info, show_warning};
 use async_channel::{Receiver, Sender};
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, TimeDelta, Utc};
 use csv::Writer;
 use gtk::glib::IntoGStr;
-use gtk::prelude::{DialogExt, FileChooserExt, FileChooserExtManual, FileExt, GtkWindowExt, IsA, WidgetExt};
+use gtk::prelude::{DialogExt, DialogExtManual, FileChooserExt, FileChooserExtManual, FileExt, GtkWindowExt, IsA, WidgetExt};
 use gtk::{gio, glib, ButtonsType, DialogFlags, FileChooserAction, FileChooserDialog, MessageDialog, MessageType, ResponseType, Window};
 use serde::Serialize;
-use std::borrow::BorrowMut;
 use std::collections::{HashMap, HashSet};
+use std::fs;
 use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
+/// Integration times in 1/100 s by star name and filter slot.
+pub type IntegrationTimes = HashMap<String, HashMap<u8, u16>>;
 
 struct State {
     run: PepRun,
-    i_time_by_star: HashMap<String, HashMap<u8, u16>>,
-    star_index: u16,
+    i_time_by_star: IntegrationTimes,
+    remembered_i_times: IntegrationTimes,
+    instrument: String,
+    /// Where calibrated integration times are remembered, if it could be opened
+    archive: Option<Archive>,
+    steps: Vec<Step>,
+    step_index: usize,
     filter_index: u8,
-    sky: bool,
+    dark: bool,
+    dark_schedule: Option<DarkSchedule>,
+    last_dark: Option<DateTime<Utc>>,
+    // Set once a PGM or CHK has been measured that is still waiting for its closing CMP
+    open_bracket_since: Option<DateTime<Utc>>,
+    catalog: Option<Catalog>,
+    sky_offset: Offset,
+    mount: Option<MountHandle>,
+    sync_mount: bool,
+    // Catalog position of the star the mount has slewed to, synced to once it is centered
+    sync_target: Option<Equatorial>,
 }
 
 impl State {
-    fn new(run: PepRun) -> Self {
+    fn new(run: PepRun, options: &RunOptions, mount: Option<MountHandle>, archive: Option<Archive>) -> Self {
         let star_keys = run.items.iter().map(|s| s.name.clone()).collect::<HashSet<String>>();
         let mut i_time_by_star = HashMap::with_capacity(star_keys.len());
         star_keys.iter().for_each(|k| {
@@ -33,14 +55,201 @@ impl State {
         });
 
         State {
+            steps: run.steps(),
             run,
             i_time_by_star,
-            star_index: 0,
+            remembered_i_times: options.i_times.clone(),
+            instrument: options.instrument.clone(),
+            archive,
+            step_index: 0,
             filter_index: 0,
-            sky: false,
+            dark: options.dark_schedule.is_some(),
+            dark_schedule: options.dark_schedule.clone(),
+            last_dark: None,
+            open_bracket_since: None,
+            catalog: options.catalog.clone(),
+            sky_offset: options.sky_offset,
+            mount,
+            sync_mount: options.sync_mount,
+            sync_target: None,
         }
 
     }
+
+    /// Stores a calibrated integration time as starting value for later runs.
+    fn remember_i_time(&self, star_name: &str, filter_slot: u8, i_time: u16) {
+        let Some(archive) = &self.archive else {
+            return;
+        };
+        if let Err(e) = archive.store_integration_time(&self.instrument, star_name, filter_slot, i_time) {
+            eprintln!("Cannot remember integration time of {}: {}", star_name, e);
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.step_index == self.steps.len()
+    }
+
+    /// Index of the run item measured in the current step.
+    fn star_index(&self) -> u16 {
+        match self.steps.get(self.step_index) {
+            Some(step) => { step.index() }
+            None => { self.run.items.len() as u16 }
+        }
+    }
+
+    fn sky(&self) -> bool {
+        matches!(self.steps.get(self.step_index), Some(Step::Sky(_)))
+    }
+
+    /// Where the mount has to point for the current step. Unknown if the catalog lacks the
+    /// star, unless its sky is given as coordinates.
+    fn target(&self) -> Option<Equatorial> {
+        let star = self.run.items.get(self.star_index() as usize)?;
+        let position = self.catalog.as_ref().and_then(|catalog| catalog.position(&star.name));
+        if !self.sky() {
+            return position;
+        }
+        match &star.sky {
+            Some(sky) => { sky.position(position) }
+            None => { position.map(|position| position.offset_by(&self.sky_offset)) }
+        }
+    }
+
+    fn at_group_start(&self) -> bool {
+        self.group_start(self.step_index)
+    }
+
+    /// Nothing may separate a star from its sky, i.e. a group of steps starts after a sky
+    /// that isn't followed by a sky for the same star.
+    fn group_start(&self, position: usize) -> bool {
+        if position == 0 {
+            return true;
+        }
+        match (self.steps[position - 1], self.steps.get(position)) {
+            (Step::Sky(_), None | Some(Step::Star(_))) => { true }
+            (Step::Sky(previous), Some(Step::Sky(next))) => { previous != *next }
+            _ => { false }
+        }
+    }
+
+    /// Measures the previous star, together with its sky, once more before the current step.
+    fn repeat_previous(&mut self) -> Option<u16> {
+        let mut start = (0..self.step_index).rev().find(|k| matches!(self.steps[*k], Step::Star(_)))?;
+        let star = self.steps[start].index();
+        while start > 0 && self.steps[start - 1] == Step::Sky(star) {
+            start -= 1;
+        }
+        let repeated = self.steps[start..self.step_index].to_vec();
+        self.steps.splice(self.step_index..self.step_index, repeated);
+        Some(star)
+    }
+
+    /// Removes the current star from the sequence. A sky shared with a star that has already
+    /// been measured is kept.
+    fn skip_current(&mut self) -> Option<u16> {
+        let star = self.steps.get(self.step_index)?.index();
+        let shares_sky = self.step_index > 0
+            && matches!(self.steps[self.step_index - 1], Step::Star(previous) if previous != star);
+        let mut end = self.step_index;
+        while end < self.steps.len() && self.steps[end].index() == star {
+            if shares_sky && self.steps[end] == Step::Sky(star) {
+                break;
+            }
+            end += 1;
+        }
+        // Only the sky of an already measured star is left
+        if !self.steps[self.step_index..end].contains(&Step::Star(star)) {
+            return None;
+        }
+        self.steps.drain(self.step_index..end);
+        Some(star)
+    }
+
+    /// Adds an extra measurement of the most recent CMP star once the current star and
+    /// its sky are done.
+    fn insert_cmp(&mut self) -> Option<u16> {
+        let items = &self.run.items;
+        let current = (self.star_index() as usize).min(items.len());
+        let cmp = items[..current].iter().rposition(|s| s.star_type == "CMP")
+            .or_else(|| items.iter().position(|s| s.star_type == "CMP"))? as u16;
+        let mut position = self.step_index;
+        while position < self.steps.len() && !self.group_start(position) {
+            position += 1;
+        }
+        let inserted = self.run.star_steps(cmp);
+        self.steps.splice(position..position, inserted);
+        Some(cmp)
+    }
+
+    /// A dark count is due at the end of the run and whenever the interval has elapsed.
+    fn dark_due(&self) -> bool {
+        match &self.dark_schedule {
+            None => { false }
+            Some(schedule) => {
+                if self.finished() {
+                    return true;
+                }
+                if !self.at_group_start() {
+                    return false;
+                }
+                match self.last_dark {
+                    Some(last_dark) => {
+                        schedule.interval > TimeDelta::zero() && Utc::now() - last_dark >= schedule.interval
+                    }
+                    None => { true }
+                }
+            }
+        }
+    }
+}
+
+/// Settings applying to all runs executed on a photometer connection.
+#[derive(Debug, Clone)]
+pub struct RunOptions {
+    pub dark_schedule: Option<DarkSchedule>,
+    /// Recorded with the runs in the archive
+    pub instrument: String,
+    /// Reference magnitudes for the check star QC
+    pub catalog: Option<Catalog>,
+    /// Largest deviation of CHK−CMP from its reference values in mag
+    pub check_tolerance: f64,
+    /// Mount slewing to the stars, `None` if the operator points the telescope
+    pub mount: Option<MountSettings>,
+    /// Position of the sky relative to the star
+    pub sky_offset: Offset,
+    /// Sync the mount to the catalog position once the operator has centered a star
+    pub sync_mount: bool,
+    /// Integration times calibrated in earlier runs with the instrument
+    pub i_times: IntegrationTimes,
+}
+
+/// When to take dark counts during a run. They are always taken at the start and the end
+/// of the run, and additionally every `interval` unless it is zero.
+#[derive(Debug, Clone)]
+pub struct DarkSchedule {
+    pub interval: TimeDelta,
+}
+
+/// Longest interruption after which PGM and CHK measurements are still considered bracketed
+/// by the surrounding CMP measurements.
+const MAX_BRACKET_GAP: TimeDelta = TimeDelta::minutes(10);
+
+const PAUSE_RESPONSE: u16 = 1;
+const REPEAT_RESPONSE: u16 = 2;
+const SKIP_RESPONSE: u16 = 3;
+const INSERT_CMP_RESPONSE: u16 = 4;
+
+/// Integration time for dark counts in 1/100 s.
+pub const DARK_I_TIME: u16 = 1000;
+
+/// Longest integration time of the photometer in 1/100 s.
+pub const MAX_I_TIME: u16 = 5999;
+
+/// Integration time in 1/100 s a star is first measured with before calibrating it.
+pub fn initial_i_time(filter_slot: u8) -> u16 {
+    // U and B are fainter for most stars
+    if filter_slot < 2 { 2000 } else { 1000 }
 }
 
 #[derive(Debug, Serialize)]
@@ -56,6 +265,14 @@ struct Measurement<'a> {
     count1: u16,
     count2: u16,
     count3: u16,
+    kind: RowKind,
+    note: &'a str,
+    /// Where the mount was pointing for J2000 when the star was measured, empty without a mount
+    mount_ra: Option<f64>,
+    mount_dec: Option<f64>,
+    pier_side: Option<PierSide>,
+    /// Position of the focuser, empty without one
+    focus_position: Option<f64>,
 }
 
 enum SspResponse {
@@ -67,11 +284,63 @@ enum SspResponse {
 enum SspRequest {
     Init(),
     Measure(u8, u16),
+    Reconnect(),
+    Cancel(),
     Finish(),
 }
 
-pub fn execute_run<F> (device: &str, run: PepRun, last_dir: gio::File, parent: impl IsA<Window>, completion_callback: F)
-where F: Clone + FnOnce() -> () + 'static {
+/// Number of times a timed-out count is repeated before asking the operator.
+const MAX_COUNT_RETRIES: u8 = 2;
+/// Number of times the port is re-opened after a disconnect before asking the operator.
+const MAX_RECONNECTS: u8 = 1;
+
+enum CountOutcome {
+    Counts(u16),
+    Skip,
+    Abort(String),
+}
+
+enum Recovery {
+    Retry,
+    Skip,
+    Abort,
+}
+
+/// How a single run of a session ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum RunOutcome {
+    Completed,
+    Aborted,
+}
+
+/// A run waiting in the session queue.
+#[derive(Debug, Clone)]
+pub struct QueuedRun {
+    pub name: String,
+    pub run: PepRun,
+}
+
+/// Line of the session summary.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SessionEntry {
+    run: String,
+    log_file: String,
+    started: Option<DateTime<Utc>>,
+    ended: Option<DateTime<Utc>>,
+    outcome: Option<RunOutcome>,
+}
+
+struct Session {
+    runs: Vec<QueuedRun>,
+    options: RunOptions,
+    mount: Option<MountHandle>,
+    dir: PathBuf,
+    entries: Vec<SessionEntry>,
+}
+
+/// Starts the SSP3 driver and initializes the photometer.
+fn connect(device: &str, parent: &impl IsA<Window>) -> Option<(Sender<SspRequest>, Receiver<SspResponse>)> {
     let (gui_ssp_snd, gui_ssp_rcv) = async_channel::bounded(1);
     let (ssp_gui_snd, ssp_gui_rcv) = async_channel::bounded(1);
 
@@ -81,32 +350,110 @@ where F: Clone + FnOnce() -> () + 'static {
     });
 
     if let Err(_) = gui_ssp_snd.send_blocking(SspRequest::Init()) {
-        show_error(Some(&parent), Some("Channel Closed"), "Channel to SSP3 is closed");
-        completion_callback();
-        return;
+        show_error(Some(parent), Some("Channel Closed"), "Channel to SSP3 is closed");
+        return None;
     }
 
     match ssp_gui_rcv.recv_blocking() {
         Ok(SspResponse::Ok()) => {
-
+            Some((gui_ssp_snd, ssp_gui_rcv))
         }
         Ok(SspResponse::Error(e)) => {
-            show_error(Some(&parent), Some("Error Initializing SSP3"), e);
-            completion_callback();
-            return;
+            show_error(Some(parent), Some("Error Initializing SSP3"), e);
+            None
         }
         Ok(SspResponse::Counts(_)) => {
-            show_error(Some(&parent), Some("Unexpected SSP3 Response"), "Received counts response on initialization.");
-            completion_callback();
-            return;
+            show_error(Some(parent), Some("Unexpected SSP3 Response"), "Received counts response on initialization.");
+            None
         }
         Err(_) => {
-            show_error(Some(&parent), Some("Channel Closed"), "Channel to SSP3 is closed");
-            completion_callback();
-            return;
+            show_error(Some(parent), Some("Channel Closed"), "Channel to SSP3 is closed");
+            None
         }
     }
+}
 
+/// Opens the configured mount in the background, as reaching it over the network may take a
+/// while. `Err` if it is configured but cannot be reached.
+async fn connect_mount(options: &RunOptions, parent: &impl IsA<Window>) -> Result<Option<MountHandle>, ()> {
+    let Some(settings) = options.mount.clone() else {
+        return Ok(None);
+    };
+    let result = gio::spawn_blocking(move || MountHandle::connect(&settings)).await
+        .unwrap_or_else(|_| Err(mount::Error::Protocol("mount thread panicked".to_string())));
+    match result {
+        Ok(mount) => { Ok(Some(mount)) }
+        Err(e) => {
+            show_error(Some(parent), Some("Error Connecting Mount"), e);
+            Err(())
+        }
+    }
+}
+
+/// Starts a run logging to `log_path`. Once the run has ended, its log goes into the archive.
+fn start_run<F>(run: PepRun, options: &RunOptions, mount: Option<MountHandle>, log_path: PathBuf, writer: Writer<File>, parent: impl IsA<Window>, sender: Sender<SspRequest>, receiver: Receiver<SspResponse>, completion_callback: F)
+where F: FnOnce(RunOutcome) -> () + Clone + 'static {
+    // Opened once for the whole run rather than for every calibration
+    let archive = match Archive::open_default() {
+        Ok(archive) => { Some(archive) }
+        Err(e) => {
+            show_warning(Some(&parent), Some("Integration Times Not Remembered"), e);
+            None
+        }
+    };
+    let state = State::new(run, options, mount, archive);
+    let state_arc = Arc::new(Mutex::new(state));
+    let writer_arc = Arc::new(Mutex::new(writer));
+    let options = options.clone();
+    let archive_parent = parent.clone();
+    let archiving_callback = move |outcome: RunOutcome| {
+        archive_run(&log_path, &options, outcome, &archive_parent);
+        completion_callback(outcome);
+    };
+    run_step(state_arc, writer_arc, parent, sender, receiver, archiving_callback);
+}
+
+/// Puts the log of a finished run into the archive and reports the check star QC.
+fn archive_run(log_path: &Path, options: &RunOptions, outcome: RunOutcome, parent: &impl IsA<Window>) {
+    let result = Archive::open_default().and_then(|mut archive| {
+        match archive.ingest_log(log_path, &options.instrument, &format!("{:?}", outcome))? {
+            Some(run_id) => { qc::check_run(&archive, run_id, options.catalog.as_ref(), options.check_tolerance) }
+            None => { Ok(Vec::new()) }
+        }
+    });
+    match result {
+        Ok(results) if results.iter().any(|r| r.flagged) => {
+            show_warning(Some(parent), Some("Check Star QC Failed"), qc::report(&results));
+        }
+        Ok(results) if !results.is_empty() => {
+            show_info(Some(parent), Some("Check Star QC Passed"), qc::report(&results));
+        }
+        Ok(_) => {}
+        Err(e) => {
+            show_error(Some(parent), Some("Error Archiving Run"), e);
+        }
+    }
+}
+
+pub fn execute_run<F> (device: &str, run: PepRun, options: RunOptions, last_dir: gio::File, parent: impl IsA<Window>, completion_callback: F)
+where F: Clone + FnOnce() -> () + 'static {
+    let Some((gui_ssp_snd, ssp_gui_rcv)) = connect(device, &parent) else {
+        completion_callback();
+        return;
+    };
+    glib::spawn_future_local(async move {
+        let Ok(mount) = connect_mount(&options, &parent).await else {
+            finish_ssp(&gui_ssp_snd);
+            completion_callback();
+            return;
+        };
+        choose_log(run, options, mount, last_dir, parent, gui_ssp_snd, ssp_gui_rcv, completion_callback);
+    });
+}
+
+/// Asks for the run log and starts the run.
+fn choose_log<F>(run: PepRun, options: RunOptions, mount: Option<MountHandle>, last_dir: gio::File, parent: impl IsA<Window>, gui_ssp_snd: Sender<SspRequest>, ssp_gui_rcv: Receiver<SspResponse>, completion_callback: F)
+where F: Clone + FnOnce() -> () + 'static {
     let dialog = FileChooserDialog::new(Some("Save Run Log"), Some(&parent), FileChooserAction::Save, &[("OK", ResponseType::Ok)]);
     dialog.set_current_folder(Some(&last_dir)).expect("Unable to set current directory");
     dialog.connect_response(move |dlg, response| {
@@ -114,12 +461,16 @@ where F: Clone + FnOnce() -> () + 'static {
         if response != ResponseType::Cancel {
            if let Some(file) = dlg.file() {
                let path = file.path().unwrap();
-               match Writer::from_path(path) {
+               match Writer::from_path(&path) {
                    Ok(writer) => {
-                       let state = State::new(run.clone());
-                       let state_arc = Arc::new(Mutex::new(state));
-                       let writer_arc = Arc::new(Mutex::new(writer));
-                       run_step(state_arc.clone(), writer_arc.clone(), parent.clone(), gui_ssp_snd.clone(), ssp_gui_rcv.clone(), completion_callback.clone());
+                       let finish_sender = gui_ssp_snd.clone();
+                       let completion_callback = completion_callback.clone();
+                       start_run(run.clone(), &options, mount.clone(), path, writer, parent.clone(), gui_ssp_snd.clone(), ssp_gui_rcv.clone(), move |outcome| {
+                           if outcome == RunOutcome::Completed {
+                               finish_ssp(&finish_sender);
+                           }
+                           completion_callback();
+                       });
                    }
                    Err(e) => {
                        gui_ssp_snd.send_blocking(Finish()).expect("Error shutting down SSP");
@@ -138,34 +489,253 @@ where F: Clone + FnOnce() -> () + 'static {
     dialog.show();
 }
 
+/// Executes the queued runs back to back on a single photometer connection. Every run gets
+/// its own log in `session_dir`, which also receives a summary of the session.
+pub fn execute_session<F>(device: &str, runs: Vec<QueuedRun>, options: RunOptions, session_dir: PathBuf, parent: impl IsA<Window>, completion_callback: F)
+where F: Clone + FnOnce() -> () + 'static {
+    if let Err(e) = fs::create_dir_all(&session_dir) {
+        show_error(Some(&parent), Some("Error Creating Session Folder"), e);
+        completion_callback();
+        return;
+    }
+    let Some((sender, receiver)) = connect(device, &parent) else {
+        completion_callback();
+        return;
+    };
+    glib::spawn_future_local(async move {
+        let Ok(mount) = connect_mount(&options, &parent).await else {
+            finish_ssp(&sender);
+            completion_callback();
+            return;
+        };
+
+        let session = Session {
+            runs,
+            options,
+            mount,
+            dir: session_dir,
+            entries: Vec::new(),
+        };
+        session_step(Arc::new(Mutex::new(session)), parent, sender, receiver, completion_callback);
+    });
+}
+
+fn session_step<F>(session: Arc<Mutex<Session>>, parent: impl IsA<Window>, sender: Sender<SspRequest>, receiver: Receiver<SspResponse>, completion_callback: F)
+where F: Clone + FnOnce() -> () + 'static {
+    let session_data = session.lock().unwrap();
+    let position = session_data.entries.len();
+    if position == session_data.runs.len() {
+        drop(session_data);
+        finish_ssp(&sender);
+        finish_session(&session, &parent);
+        completion_callback();
+        return;
+    }
+
+    let queued = &session_data.runs[position];
+    let log_file = format!("{:02}-{}.csv", position + 1, file_name_safe(&queued.name));
+    let path = session_data.dir.join(&log_file);
+    let writer = match Writer::from_path(&path) {
+        Ok(writer) => { writer }
+        Err(e) => {
+            drop(session_data);
+            finish_ssp(&sender);
+            show_error(Some(&parent), Some("Error Opening Run Log"), e);
+            finish_session(&session, &parent);
+            completion_callback();
+            return;
+        }
+    };
+    let run = queued.run.clone();
+    let name = queued.name.clone();
+    let options = session_data.options.clone();
+    let mount = session_data.mount.clone();
+    drop(session_data);
+
+    let started = Utc::now();
+    let cloned_session = session.clone();
+    let cloned_parent = parent.clone();
+    let cloned_sender = sender.clone();
+    let cloned_receiver = receiver.clone();
+    start_run(run, &options, mount, path, writer, parent, sender, receiver, move |outcome| {
+        cloned_session.lock().unwrap().entries.push(SessionEntry {
+            run: name,
+            log_file,
+            started: Some(started),
+            ended: Some(Utc::now()),
+            outcome: Some(outcome),
+        });
+        match outcome {
+            RunOutcome::Completed => {
+                session_step(cloned_session, cloned_parent, cloned_sender, cloned_receiver, completion_callback);
+            }
+            RunOutcome::Aborted => {
+                // The photometer has already been shut down.
+                finish_session(&cloned_session, &cloned_parent);
+                completion_callback();
+            }
+        }
+    });
+}
+
+/// Shuts down the photometer. Called when a run completes, i.e. from within the future
+/// measuring it, so the request is sent asynchronously.
+fn finish_ssp(sender: &Sender<SspRequest>) {
+    let sender = sender.clone();
+    glib::spawn_future_local(async move {
+        // Nothing left to shut down if the driver has already gone
+        let _ = sender.send(Finish()).await;
+    });
+}
+
+/// Writes the session summary, listing runs that weren't executed as well.
+fn finish_session(session: &Arc<Mutex<Session>>, parent: &impl IsA<Window>) {
+    let session_data = session.lock().unwrap();
+    let path = session_data.dir.join("summary.csv");
+    let result = Writer::from_path(&path).and_then(|mut writer| {
+        for entry in session_data.entries.iter() {
+            writer.serialize(entry)?;
+        }
+        for queued in session_data.runs.iter().skip(session_data.entries.len()) {
+            writer.serialize(SessionEntry {
+                run: queued.name.clone(),
+                log_file: String::new(),
+                started: None,
+                ended: None,
+                outcome: None,
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    });
+    if let Err(e) = result {
+        show_error(Some(parent), Some("Error Writing Session Summary"), e);
+    }
+}
+
+fn file_name_safe(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+        .collect()
+}
+
 fn run_step<F>(state: Arc<Mutex<State>>, writer: Arc<Mutex<Writer<File>>>, parent: impl IsA<Window>, sender: Sender<SspRequest>, receiver: Receiver<SspResponse>, completion_callback: F)
-where F: FnOnce() -> () + Clone + 'static {
-    let state_data = state.lock().unwrap();
-    let msg = match state_data.sky {
-        true => {
-            "Go to sky".to_string()
+where F: FnOnce(RunOutcome) -> () + Clone + 'static {
+    let mut state_data = state.lock().unwrap();
+    // Skipping the last star ends the run early
+    if state_data.finished() && !state_data.dark {
+        if state_data.dark_due() {
+            state_data.dark = true;
+        } else {
+            drop(state_data);
+            completion_callback(RunOutcome::Completed);
+            return;
         }
-        false => {
-            format!("Go to {}", state_data.run.items[state_data.star_index as usize].name)
+    }
+    let name = if state_data.dark {
+        "the dark count".to_string()
+    } else if state_data.sky() {
+        let star = &state_data.run.items[state_data.star_index() as usize];
+        match &star.sky {
+            Some(sky) => { format!("sky for {} ({})", star.name, sky) }
+            None => { format!("sky for {}", star.name) }
         }
+    } else {
+        state_data.run.items[state_data.star_index() as usize].name.clone()
+    };
+    let msg = if state_data.dark {
+        "Close the shutter for a dark count".to_string()
+    } else {
+        format!("Go to {}", name)
     };
+    let mount = if state_data.dark { None } else { state_data.mount.clone() };
+    let target = state_data.target();
+    state_data.sync_target = None;
     drop(state_data);
-    run_with_msg(state, writer, parent, sender, receiver, completion_callback, msg);
+    match (mount, target) {
+        (Some(mount), Some(target)) => {
+            slew(state, writer, parent, sender, receiver, completion_callback, mount, target, name, msg);
+        }
+        (Some(_), None) => {
+            let msg = format!("{}\n\nThe catalog has no coordinates for the star, the mount cannot slew.", msg);
+            run_with_msg(state, writer, parent, sender, receiver, completion_callback, msg, true);
+        }
+        (None, _) => {
+            run_with_msg(state, writer, parent, sender, receiver, completion_callback, msg, true);
+        }
+    }
+}
+
+/// Slews the mount to the target of the current step, then asks the operator to center the
+/// star. If the slew fails or is cancelled, the operator points the telescope instead.
+fn slew<F>(state: Arc<Mutex<State>>, writer: Arc<Mutex<Writer<File>>>, parent: impl IsA<Window>, sender: Sender<SspRequest>, receiver: Receiver<SspResponse>, completion_callback: F, mount: MountHandle, target: Equatorial, name: String, msg: String)
+where F: FnOnce(RunOutcome) -> () + Clone + 'static {
+    let dialog = MessageDialog::new(Some(&parent), DialogFlags::MODAL, MessageType::Info, ButtonsType::Cancel,
+                                    format!("Slewing to {}", name));
+    dialog.set_title(Some("Slewing"));
+    let cancel_mount = mount.clone();
+    dialog.connect_response(move |_, _| {
+        cancel_mount.cancel();
+    });
+    dialog.show();
+    glib::spawn_future_local(async move {
+        let result = mount.slew(target).await;
+        dialog.hide();
+        dialog.destroy();
+        let msg = match result {
+            Ok(()) => {
+                let mut state_data = state.lock().unwrap();
+                let sky = state_data.sky();
+                if state_data.sync_mount && !sky {
+                    state_data.sync_target = Some(target);
+                }
+                drop(state_data);
+                if sky {
+                    format!("Pointing at {}, confirm to measure", name)
+                } else {
+                    format!("Center {} and confirm", name)
+                }
+            }
+            Err(mount::Error::Aborted) => { msg }
+            Err(e) => { format!("{}\n\n{}", msg, e) }
+        };
+        run_with_msg(state, writer, parent, sender, receiver, completion_callback, msg, true);
+    });
 }
 
-fn run_with_msg<F>(state: Arc<Mutex<State>>, writer: Arc<Mutex<Writer<File>>>, parent: impl IsA<Window>, sender: Sender<SspRequest>, receiver: Receiver<SspResponse>, completion_callback: F, msg: impl IntoGStr)
-where F: FnOnce() -> () + Clone + 'static {
-    let dialog = MessageDialog::new(Some(&parent), DialogFlags::MODAL, MessageType::Question, ButtonsType::OkCancel, msg);
+/// Asks the operator to confirm the next measurement. Only the prompt for a new step offers
+/// to change the sequence, not e.g. the request to re-center the current star.
+fn run_with_msg<F>(state: Arc<Mutex<State>>, writer: Arc<Mutex<Writer<File>>>, parent: impl IsA<Window>, sender: Sender<SspRequest>, receiver: Receiver<SspResponse>, completion_callback: F, msg: impl Into<String>, step_prompt: bool)
+where F: FnOnce(RunOutcome) -> () + Clone + 'static {
+    let msg = msg.into();
+    let dialog = MessageDialog::new(Some(&parent), DialogFlags::MODAL, MessageType::Question, ButtonsType::OkCancel, &msg);
     dialog.set_title(Some("Operator Action"));
+    dialog.add_button("Pause", ResponseType::Other(PAUSE_RESPONSE));
+    if step_prompt && !state.lock().unwrap().dark {
+        dialog.add_buttons(&[("Repeat Previous", ResponseType::Other(REPEAT_RESPONSE)),
+            ("Skip Star", ResponseType::Other(SKIP_RESPONSE)), ("Insert CMP", ResponseType::Other(INSERT_CMP_RESPONSE))]);
+    }
     let cloned_state = state.clone();
     dialog.connect_response(move |dlg, response| {
         dlg.hide();
         dlg.destroy();
+        match response {
+            ResponseType::Other(PAUSE_RESPONSE) => {
+                pause(cloned_state.clone(), writer.clone(), parent.clone(), sender.clone(), receiver.clone(), completion_callback.clone(), msg.clone(), step_prompt);
+                return;
+            }
+            ResponseType::Other(command @ (REPEAT_RESPONSE | SKIP_RESPONSE | INSERT_CMP_RESPONSE)) => {
+                edit_sequence(cloned_state.clone(), writer.clone(), parent.clone(), sender.clone(), receiver.clone(), completion_callback.clone(), msg.clone(), command);
+                return;
+            }
+            _ => {}
+        }
         if response != ResponseType::Ok {
             sender.clone().send_blocking(Finish()).expect("Error shutting down SSP");
-            completion_callback.clone()();
+            completion_callback.clone()(RunOutcome::Aborted);
             return;
         }
+        sync_mount(&cloned_state, &parent);
 
         let cloned_state = cloned_state.clone();
         let cloned_writer = writer.clone();
@@ -178,187 +748,522 @@ where F: FnOnce() -> () + Clone + 'static {
     dialog.show();
 }
 
-fn abort_with_msg(parent: impl IsA<Window>, message: impl IntoGStr, completion_callback: impl FnOnce() -> () + Clone + 'static) {
+/// Syncs the mount to the star the operator has just centered. The measurement doesn't wait
+/// for it, a failed sync only affects later slews.
+fn sync_mount(state: &Arc<Mutex<State>>, parent: &impl IsA<Window>) {
+    let mut state_data = state.lock().unwrap();
+    let (Some(mount), Some(target)) = (state_data.mount.clone(), state_data.sync_target.take()) else {
+        return;
+    };
+    drop(state_data);
+    let parent = parent.clone();
+    glib::spawn_future_local(async move {
+        let result = gio::spawn_blocking(move || mount.sync(&target)).await
+            .unwrap_or_else(|_| Err(mount::Error::Protocol("mount thread panicked".to_string())));
+        if let Err(e) = result {
+            show_warning(Some(&parent), Some("Mount Not Synced"), e);
+        }
+    });
+}
+
+/// Repeats the previous star, skips the current one or inserts an extra CMP measurement
+/// on request of the operator. The reason given by the operator goes into the run log.
+fn edit_sequence<F>(state: Arc<Mutex<State>>, writer: Arc<Mutex<Writer<File>>>, parent: impl IsA<Window>, sender: Sender<SspRequest>, receiver: Receiver<SspResponse>, completion_callback: F, msg: String, command: u16)
+where F: FnOnce(RunOutcome) -> () + Clone + 'static {
+    let title = match command {
+        REPEAT_RESPONSE => { "Repeat Previous Star" }
+        SKIP_RESPONSE => { "Skip Star" }
+        _ => { "Insert CMP" }
+    };
+    let cloned_parent = parent.clone();
+    ask_text(&cloned_parent, title, "Reason", move |reason| {
+        let Some(reason) = reason else {
+            run_with_msg(state.clone(), writer.clone(), parent.clone(), sender.clone(), receiver.clone(), completion_callback.clone(), msg.clone(), true);
+            return;
+        };
+        let mut state_data = state.lock().unwrap();
+        let (kind, star, verb) = match command {
+            REPEAT_RESPONSE => { (RowKind::Repeat, state_data.repeat_previous(), "Repeating") }
+            SKIP_RESPONSE => { (RowKind::Skip, state_data.skip_current(), "Skipped") }
+            _ => { (RowKind::Insert, state_data.insert_cmp(), "Inserted") }
+        };
+        match star {
+            Some(star) => {
+                let note = format!("{} {}: {}", verb, state_data.run.items[star as usize].name, reason.trim());
+                log_event(&writer, &state_data, kind, Utc::now(), &note);
+                drop(state_data);
+                run_step(state.clone(), writer.clone(), parent.clone(), sender.clone(), receiver.clone(), completion_callback.clone());
+            }
+            None => {
+                drop(state_data);
+                show_error(Some(&parent), Some(title), "Not possible at this point of the run.");
+                run_with_msg(state.clone(), writer.clone(), parent.clone(), sender.clone(), receiver.clone(), completion_callback.clone(), msg.clone(), true);
+            }
+        }
+    });
+}
+
+/// Keeps the photometer session open until the operator resumes or aborts the run.
+/// The pause is recorded in the run log.
+fn pause<F>(state: Arc<Mutex<State>>, writer: Arc<Mutex<Writer<File>>>, parent: impl IsA<Window>, sender: Sender<SspRequest>, receiver: Receiver<SspResponse>, completion_callback: F, msg: String, step_prompt: bool)
+where F: FnOnce(RunOutcome) -> () + Clone + 'static {
+    let paused_at = Utc::now();
+    let dialog = MessageDialog::new(Some(&parent), DialogFlags::MODAL, MessageType::Info, ButtonsType::None,
+                                    format!("Run paused since {} UT", paused_at.format("%H:%M:%S")));
+    dialog.set_title(Some("Paused"));
+    dialog.add_buttons(&[("Abort Run", ResponseType::Cancel), ("Resume", ResponseType::Ok)]);
+    dialog.connect_response(move |dlg, response| {
+        dlg.hide();
+        dlg.destroy();
+        let resumed_at = Utc::now();
+        let gap = resumed_at - paused_at;
+        let state_data = state.lock().unwrap();
+        let note = format!("Paused from {} to {} UT ({} s)", paused_at.format("%H:%M:%S"),
+                           resumed_at.format("%H:%M:%S"), gap.num_seconds());
+        log_event(&writer, &state_data, RowKind::Pause, paused_at, &note);
+        let open_bracket_since = state_data.open_bracket_since;
+        drop(state_data);
+
+        if response != ResponseType::Ok {
+            sender.send_blocking(Finish()).expect("Error shutting down SSP");
+            completion_callback.clone()(RunOutcome::Aborted);
+            return;
+        }
+
+        match open_bracket_since {
+            Some(since) if gap > MAX_BRACKET_GAP => {
+                let warning = MessageDialog::new(Some(&parent), DialogFlags::MODAL, MessageType::Warning, ButtonsType::Ok,
+                                                 format!("The pause of {} min exceeds {} min. The measurements since {} UT are no longer bracketed by CMP measurements; consider repeating them.",
+                                                         gap.num_minutes(), MAX_BRACKET_GAP.num_minutes(), since.format("%H:%M:%S")));
+                warning.set_title(Some("CMP Bracketing"));
+                let state = state.clone();
+                let writer = writer.clone();
+                let parent = parent.clone();
+                let sender = sender.clone();
+                let receiver = receiver.clone();
+                let completion_callback = completion_callback.clone();
+                let msg = msg.clone();
+                warning.connect_response(move |warning, _| {
+                    warning.hide();
+                    warning.destroy();
+                    run_with_msg(state.clone(), writer.clone(), parent.clone(), sender.clone(), receiver.clone(), completion_callback.clone(), msg.clone(), step_prompt);
+                });
+                warning.show();
+            }
+            _ => {
+                run_with_msg(state.clone(), writer.clone(), parent.clone(), sender.clone(), receiver.clone(), completion_callback.clone(), msg.clone(), step_prompt);
+            }
+        }
+    });
+    dialog.show();
+}
+
+/// Records something other than a measurement in the run log, e.g. a pause.
+fn log_event(writer: &Arc<Mutex<Writer<File>>>, state_data: &State, kind: RowKind, timestamp: DateTime<Utc>, note: &str) {
+    let star_index = state_data.star_index();
+    let star = state_data.run.items.get(star_index as usize);
+    let measurement = Measurement {
+        timestamp,
+        index: star_index,
+        star_id: star.map(|s| s.name.as_str()).unwrap_or(""),
+        star_type: star.map(|s| s.star_type.as_str()).unwrap_or(""),
+        is_star: false,
+        filter: "",
+        integration_time: 0,
+        count1: 0,
+        count2: 0,
+        count3: 0,
+        kind,
+        note,
+        mount_ra: None,
+        mount_dec: None,
+        pier_side: None,
+        focus_position: None,
+    };
+    let mut writer_obj = writer.lock().unwrap();
+    writer_obj.serialize(measurement).expect("Error serializing event");
+    writer_obj.flush().expect("Error flushing run log");
+}
+
+fn abort_with_msg(parent: impl IsA<Window>, message: impl IntoGStr, completion_callback: impl FnOnce(RunOutcome) -> () + Clone + 'static) {
     let dialog = MessageDialog::new(Some(&parent), DialogFlags::MODAL, MessageType::Error, ButtonsType::Ok, message);
     dialog.set_title(Some("Error"));
     dialog.connect_response(move |dlg, _| {
         dlg.hide();
         dlg.destroy();
-        completion_callback.clone()();
+        completion_callback.clone()(RunOutcome::Aborted);
     });
     dialog.show();
 }
 
+pub fn filter_name(filter_slot: u8) -> &'static str {
+    match filter_slot {
+        0 => { "U" }
+        1 => { "B" }
+        2 => { "V" }
+        3 => { "R" }
+        4 => { "I" }
+        5 => { "C" }
+        _ => { panic!("Unknown filter index {}", filter_slot); }
+    }
+}
+
+pub fn filter_slot(name: &str) -> Option<u8> {
+    (0..=5).find(|slot| filter_name(*slot) == name)
+}
+
+async fn ask_recovery(parent: &impl IsA<Window>, error: &ssp3::Error) -> Recovery {
+    let (title, msg) = if error.is_cancelled() {
+        ("Count Cancelled", "Count cancelled.".to_string())
+    } else {
+        ("Measurement Failed", error.to_string())
+    };
+    let dialog = MessageDialog::new(Some(parent), DialogFlags::MODAL, MessageType::Warning, ButtonsType::None,
+                                    format!("{}\n\nRetry the count, skip this filter or abort the run?", msg));
+    dialog.set_title(Some(title));
+    dialog.add_buttons(&[("Retry", ResponseType::Other(0)), ("Skip Filter", ResponseType::Other(1)), ("Abort Run", ResponseType::Other(2))]);
+    let response = dialog.run_future().await;
+    dialog.hide();
+    dialog.destroy();
+    match response {
+        ResponseType::Other(0) => { Recovery::Retry }
+        ResponseType::Other(1) => { Recovery::Skip }
+        _ => { Recovery::Abort }
+    }
+}
+
+/// Takes a single count, retrying timeouts and re-opening the port after a disconnect.
+/// If that does not help, the operator decides how to proceed. Automatic recoveries are
+/// shown in the secondary text of `progress`.
+async fn acquire_counts(parent: &impl IsA<Window>, progress: &MessageDialog, sender: &Sender<SspRequest>, receiver: &Receiver<SspResponse>, filter: u8, time: u16) -> CountOutcome {
+    let mut retries = 0u8;
+    let mut reconnects = 0u8;
+    loop {
+        if sender.send(Measure(filter, time)).await.is_err() {
+            return CountOutcome::Abort("Error sending to SSP".to_string());
+        }
+        let error = match receiver.recv().await {
+            Ok(SspResponse::Counts(counts)) => {
+                progress.set_secondary_text(None);
+                return CountOutcome::Counts(counts);
+            }
+            Ok(SspResponse::Error(e)) => {
+                e
+            }
+            Ok(_) => {
+                return CountOutcome::Abort("Unexpected SSP response".to_string());
+            }
+            Err(_) => {
+                return CountOutcome::Abort("Error receiving from SSP".to_string());
+            }
+        };
+
+        if error.is_timeout() && retries < MAX_COUNT_RETRIES {
+            retries += 1;
+            progress.set_secondary_text(Some(&format!("{} - retrying count ({}/{})", error, retries, MAX_COUNT_RETRIES)));
+            continue;
+        }
+        if error.is_disconnect() && reconnects < MAX_RECONNECTS {
+            reconnects += 1;
+            progress.set_secondary_text(Some(&format!("{} - reconnecting ({}/{})", error, reconnects, MAX_RECONNECTS)));
+            if sender.send(SspRequest::Reconnect()).await.is_err() {
+                return CountOutcome::Abort("Error sending to SSP".to_string());
+            }
+            match receiver.recv().await {
+                Ok(SspResponse::Ok()) => {
+                    continue;
+                }
+                Ok(SspResponse::Error(e)) => {
+                    progress.set_secondary_text(Some(&format!("Reconnect failed: {}", e)));
+                }
+                Ok(_) => {
+                    return CountOutcome::Abort("Unexpected SSP response".to_string());
+                }
+                Err(_) => {
+                    return CountOutcome::Abort("Error receiving from SSP".to_string());
+                }
+            }
+        }
+
+        match ask_recovery(parent, &error).await {
+            Recovery::Retry => {
+                retries = 0;
+                reconnects = 0;
+            }
+            Recovery::Skip => {
+                return CountOutcome::Skip;
+            }
+            Recovery::Abort => {
+                return CountOutcome::Abort(error.to_string());
+            }
+        }
+    }
+}
+
 fn measure<F>(state: Arc<Mutex<State>>, writer: Arc<Mutex<Writer<File>>>, parent: impl IsA<Window>, sender: Sender<SspRequest>, receiver: Receiver<SspResponse>, completion_callback: F)
-where F: FnOnce() -> () + Clone + 'static
+where F: FnOnce(RunOutcome) -> () + Clone + 'static
 {
+    // Stays up while counting, so that the operator can cancel a long integration.
+    let progress = MessageDialog::new(Some(&parent), DialogFlags::MODAL, MessageType::Info, ButtonsType::Cancel, "Measuring");
+    progress.set_title(Some("Measuring"));
+    progress.set_deletable(false);
+    let cancel_sender = sender.clone();
+    progress.connect_response(move |_, response| {
+        if response == ResponseType::Cancel {
+            // A full channel means the driver isn't counting, so there's nothing to cancel.
+            if cancel_sender.try_send(SspRequest::Cancel()).is_err() {
+                eprintln!("No count to cancel");
+            }
+        }
+    });
+    progress.show();
+
     glib::spawn_future_local(async move {
-        let mut state_data = state.lock().unwrap();
-        let star_index = state_data.star_index as usize;
-        let star_name = String::from(&state_data.run.items[star_index].name);
-        let star_type = String::from(&state_data.run.items[star_index].star_type);
-
-        for filter_index in state_data.filter_index as usize..state_data.run.filters.len() {
-            let filter_map = state_data.i_time_by_star.get(&star_name).unwrap();
-            let filter_slot = state_data.run.filters[filter_index];
-            let (i_time, mut calibrate_i_time) = match filter_map.get(&filter_slot) {
-                Some(m_i_time) => {
-                    (*m_i_time, false)
+        let dark = state.lock().unwrap().dark;
+        if dark {
+            measure_dark(state, writer, parent, sender, receiver, completion_callback, &progress).await;
+        } else {
+            measure_star(state, writer, parent, sender, receiver, completion_callback, &progress).await;
+        }
+        progress.hide();
+        progress.destroy();
+    });
+}
+
+async fn measure_star<F>(state: Arc<Mutex<State>>, writer: Arc<Mutex<Writer<File>>>, parent: impl IsA<Window>, sender: Sender<SspRequest>, receiver: Receiver<SspResponse>, completion_callback: F, progress: &MessageDialog)
+where F: FnOnce(RunOutcome) -> () + Clone + 'static
+{
+    // Asked without holding the state, the mount may take a while to answer
+    let mount = state.lock().unwrap().mount.clone();
+    let pointing = match mount {
+        // Without an answer, the mount columns of the log stay empty
+        Some(mount) => { gio::spawn_blocking(move || mount.pointing().ok()).await.ok().flatten() }
+        None => { None }
+    };
+    let mut state_data = state.lock().unwrap();
+    let star_index = state_data.star_index() as usize;
+    let star_name = String::from(&state_data.run.items[star_index].name);
+    let star_type = String::from(&state_data.run.items[star_index].star_type);
+
+    for filter_index in state_data.filter_index as usize..state_data.run.filters.len() {
+        let filter_map = state_data.i_time_by_star.get(&star_name).unwrap();
+        let filter_slot = state_data.run.filters[filter_index];
+        let fixed_i_time = state_data.run.items[star_index].i_times.get(&filter_slot);
+        let (i_time, mut calibrate_i_time) = match fixed_i_time.or(filter_map.get(&filter_slot)) {
+            Some(m_i_time) => {
+                (*m_i_time, false)
+            }
+            None => {
+                // A time remembered from earlier runs only gives the calibration a head start
+                let remembered = state_data.remembered_i_times.get(&star_name).and_then(|m| m.get(&filter_slot));
+                (remembered.copied().unwrap_or_else(|| initial_i_time(filter_slot)), true)
+            }
+        };
+        let target = if state_data.sky() { "sky" } else { &star_name };
+        progress.set_text(Some(&format!("Measuring {} in {}", target, filter_name(filter_slot))));
+        let mut used_i_time = i_time;
+        let was_calibrated = calibrate_i_time;
+        let mut count_slots: [u16; 3] = [0; 3];
+        let mut count_slot: usize = 0;
+
+        let start_time = Utc::now();
+        let mut skipped = false;
+        loop {
+            let counts = match acquire_counts(&parent, progress, &sender, &receiver, filter_slot + 1, used_i_time).await {
+                CountOutcome::Counts(counts) => {
+                    counts
                 }
-                None => {
-                    if filter_slot < 2 {
-                        (2000u16, true)
-                    } else {
-                        (1000u16, true)
-                    }
+                CountOutcome::Skip => {
+                    skipped = true;
+                    break;
+                }
+                CountOutcome::Abort(msg) => {
+                    // Nothing left to shut down if the driver has already gone
+                    let _ = sender.send(Finish()).await;
+                    drop(state_data);
+                    abort_with_msg(parent.clone(), msg, completion_callback.clone());
+                    return;
                 }
             };
-            let mut used_i_time = i_time;
-            let was_calibrated = calibrate_i_time;
-            let mut count_slots: [u16; 3] = [0; 3];
-            let mut count_slot: usize = 0;
-
-            let start_time = Utc::now();
-            loop {
-                let command = Measure(filter_slot + 1, used_i_time);
-                match sender.send(command).await {
-                    Ok(_) => {
-                        match receiver.recv().await {
-                            Ok(SspResponse::Counts(counts)) => {
-                                if calibrate_i_time && count_slot == 0 {
-                                    if counts > 5000 {
-                                        println!("Counts {} > 5000", counts);
-                                        calibrate_i_time = false;
-                                        count_slots[0] = counts;
-                                        count_slot += 1;
-                                    } else if count_slots[0] == 0 {
-                                        println!("Count < 5000 - calibrating...");
-                                        count_slots[0] = counts;
-                                        used_i_time = i_time + 500;
-                                    } else {
-                                        if counts > count_slots[0] {
-                                            let delta_t_s = (5 * (5000 - count_slots[0])) / (counts - count_slots[0]);
-                                            if delta_t_s > 0 {
-                                                used_i_time = i_time + 100 * delta_t_s;
-                                                if used_i_time > 5999 {
-                                                    used_i_time = 5999;
-                                                }
-                                                println!("Using calibrated integration time {}", used_i_time);
-                                            } else {
-                                                used_i_time = i_time;
-                                                count_slot += 1;
-                                                println!("Using initial integration time. Counts in slot 0: {}", count_slots[0]);
-                                            }
-                                        } else {
-                                            used_i_time = 3000;
-                                            println!("Using fallback integration time {}", used_i_time);
-                                        }
-                                        calibrate_i_time = false;
-                                    }
-                                } else {
-                                    count_slots[count_slot] = counts;
-                                    println!("Counts in slot {}: {}", count_slot, counts);
-                                    count_slot += 1;
-                                    if count_slot == 3 {
-                                        break;
-                                    }
-                                }
-                            }
-                            Ok(SspResponse::Error(e)) => {
-                                sender.send(Finish()).await.expect("Error shutting down SSP");
-                                drop(state_data);
-                                abort_with_msg(parent.clone(), e.to_string(), completion_callback.clone());
-                                return;
-                            }
-                            Ok(_) => {
-                                sender.send(Finish()).await.expect("Error shutting down SSP");
-                                drop(state_data);
-                                abort_with_msg(parent.clone(), "Unexpected SSP response", completion_callback.clone());
-                                return;
-                            }
-                            Err(_) => {
-                                drop(state_data);
-                                abort_with_msg(parent.clone(), "Error receiving from SSP", completion_callback.clone());
-                                return;
+            if calibrate_i_time && count_slot == 0 {
+                if counts > 5000 {
+                    calibrate_i_time = false;
+                    count_slots[0] = counts;
+                    count_slot += 1;
+                } else if count_slots[0] == 0 {
+                    count_slots[0] = counts;
+                    used_i_time = i_time + 500;
+                } else {
+                    if counts > count_slots[0] {
+                        let delta_t_s = (5 * (5000 - count_slots[0])) / (counts - count_slots[0]);
+                        if delta_t_s > 0 {
+                            used_i_time = i_time + 100 * delta_t_s;
+                            if used_i_time > MAX_I_TIME {
+                                used_i_time = MAX_I_TIME;
                             }
+                        } else {
+                            used_i_time = i_time;
+                            count_slot += 1;
                         }
+                    } else {
+                        used_i_time = 3000;
                     }
-                    Err(_) => {
-                        drop(state_data);
-                        abort_with_msg(parent.clone(), "Error sending to SSP", completion_callback.clone());
-                        return;
-                    }
+                    calibrate_i_time = false;
+                }
+            } else {
+                count_slots[count_slot] = counts;
+                count_slot += 1;
+                if count_slot == 3 {
+                    break;
                 }
             }
+        }
 
-            if !state_data.sky {
-                let avg = count_slots.iter().map(|v| {*v as f32}).sum::<f32>() / count_slots.len() as f32;
-                let min = *count_slots.iter().min().unwrap() as f32;
-                let max = *count_slots.iter().max().unwrap() as f32;
-                println!("Avg: {}, min: {}, max: {}", avg, min, max);
-                if min < 0.99 * avg || max > 1.01 * avg {
-                    // More than 1% deviation: ask user to re-center
-                    drop(state_data);
-                    run_with_msg(state.clone(), writer, parent, sender, receiver, completion_callback, "Deviation > 1%. Please re-center.");
-                    return;
-                }
+        if skipped {
+            continue;
+        }
+
+        if !state_data.sky() {
+            let avg = count_slots.iter().map(|v| {*v as f32}).sum::<f32>() / count_slots.len() as f32;
+            let min = *count_slots.iter().min().unwrap() as f32;
+            let max = *count_slots.iter().max().unwrap() as f32;
+            if min < 0.99 * avg || max > 1.01 * avg {
+                // More than 1% deviation: ask user to re-center
+                drop(state_data);
+                run_with_msg(state.clone(), writer, parent, sender, receiver, completion_callback, "Deviation > 1%. Please re-center.", false);
+                return;
             }
+        }
 
-            let end_time = Utc::now();
-            let middle_time = start_time + end_time.signed_duration_since(start_time);
-            let filter = match filter_slot {
-                0 => { "U" }
-                1 => { "B" }
-                2 => { "V" }
-                3 => { "R" }
-                4 => { "I" }
-                5 => { "C" }
-                _ => { panic!("Unknown filter index {}", filter_slot); }
-            };
-            let measurement = Measurement {
-                timestamp: middle_time,
-                index: state_data.star_index,
-                star_id: &star_name,
-                star_type: &star_type,
-                is_star: !state_data.sky,
-                filter,
-                integration_time: used_i_time,
-                count1: count_slots[0],
-                count2: count_slots[1],
-                count3: count_slots[2],
-            };
-            let mut writer_obj = writer.lock().unwrap();
-            writer_obj.serialize(measurement).expect("Error serializing measurement");
+        let end_time = Utc::now();
+        let middle_time = start_time + (end_time - start_time) / 2;
+        let filter = filter_name(filter_slot);
+        let measurement = Measurement {
+            timestamp: middle_time,
+            index: state_data.star_index(),
+            star_id: &star_name,
+            star_type: &star_type,
+            is_star: !state_data.sky(),
+            filter,
+            integration_time: used_i_time,
+            count1: count_slots[0],
+            count2: count_slots[1],
+            count3: count_slots[2],
+            kind: if state_data.sky() { RowKind::Sky } else { RowKind::Star },
+            note: "",
+            mount_ra: pointing.map(|p| p.position.ra_hours),
+            mount_dec: pointing.map(|p| p.position.dec_degrees),
+            pier_side: pointing.and_then(|p| p.pier_side),
+            focus_position: pointing.and_then(|p| p.focus_position),
+        };
+        let mut writer_obj = writer.lock().unwrap();
+        writer_obj.serialize(measurement).expect("Error serializing measurement");
+        // Make sure everything measured so far survives an aborted run.
+        writer_obj.flush().expect("Error flushing run log");
 
-            if was_calibrated {
-                let filter_map: &mut HashMap<u8, u16> = state_data.i_time_by_star
-                    .get_mut(&star_name).unwrap();
-                filter_map.insert(filter_slot, used_i_time);
+        if was_calibrated {
+            let filter_map: &mut HashMap<u8, u16> = state_data.i_time_by_star
+                .get_mut(&star_name).unwrap();
+            filter_map.insert(filter_slot, used_i_time);
+            // A time calibrated on the sky says nothing about the star
+            if !state_data.sky() {
+                state_data.remember_i_time(&star_name, filter_slot, used_i_time);
             }
         }
+    }
 
-        if state_data.sky {
-            state_data.sky = false;
-            state_data.star_index += 1;
-            if state_data.star_index as usize == state_data.run.items.len() {
-                sender.send(Finish()).await.expect("Error shutting down SSP");
-                completion_callback();
+    if !state_data.sky() {
+        state_data.open_bracket_since = if star_type == "CMP" {
+            None
+        } else {
+            state_data.open_bracket_since.or(Some(Utc::now()))
+        };
+    }
+    state_data.step_index += 1;
+    if state_data.dark_due() {
+        state_data.dark = true;
+    } else if state_data.finished() {
+        drop(state_data);
+        completion_callback(RunOutcome::Completed);
+        return;
+    }
+    // Release Mutex
+    drop(state_data);
+
+    run_step(state, writer, parent, sender, receiver, completion_callback);
+}
+
+async fn measure_dark<F>(state: Arc<Mutex<State>>, writer: Arc<Mutex<Writer<File>>>, parent: impl IsA<Window>, sender: Sender<SspRequest>, receiver: Receiver<SspResponse>, completion_callback: F, progress: &MessageDialog)
+where F: FnOnce(RunOutcome) -> () + Clone + 'static
+{
+    let mut state_data = state.lock().unwrap();
+    // The filter doesn't matter with the shutter closed, so avoid moving the filter wheel.
+    let filter_slot = state_data.run.filters[0];
+    progress.set_text(Some("Measuring dark counts"));
+
+    let mut count_slots: [u16; 3] = [0; 3];
+    let mut skipped = false;
+    let start_time = Utc::now();
+    for count_slot in count_slots.iter_mut() {
+        match acquire_counts(&parent, progress, &sender, &receiver, filter_slot + 1, DARK_I_TIME).await {
+            CountOutcome::Counts(counts) => {
+                *count_slot = counts;
+            }
+            CountOutcome::Skip => {
+                skipped = true;
+                break;
+            }
+            CountOutcome::Abort(msg) => {
+                // Nothing left to shut down if the driver has already gone
+                let _ = sender.send(Finish()).await;
+                drop(state_data);
+                abort_with_msg(parent.clone(), msg, completion_callback.clone());
                 return;
             }
-        } else {
-            state_data.sky = true;
         }
-        // Release Mutex
+    }
+
+    let end_time = Utc::now();
+    if !skipped {
+        let measurement = Measurement {
+            timestamp: start_time + (end_time - start_time) / 2,
+            index: state_data.star_index(),
+            star_id: "DARK",
+            star_type: "DRK",
+            is_star: false,
+            filter: filter_name(filter_slot),
+            integration_time: DARK_I_TIME,
+            count1: count_slots[0],
+            count2: count_slots[1],
+            count3: count_slots[2],
+            kind: RowKind::Dark,
+            note: "",
+            mount_ra: None,
+            mount_dec: None,
+            pier_side: None,
+            focus_position: None,
+        };
+        let mut writer_obj = writer.lock().unwrap();
+        writer_obj.serialize(measurement).expect("Error serializing measurement");
+        writer_obj.flush().expect("Error flushing run log");
+    }
+
+    state_data.dark = false;
+    state_data.last_dark = Some(end_time);
+    if state_data.finished() {
         drop(state_data);
+        completion_callback(RunOutcome::Completed);
+        return;
+    }
+    drop(state_data);
 
-        run_step(state, writer, parent, sender, receiver, completion_callback);
-    });
+    run_step(state, writer, parent, sender, receiver, completion_callback);
 }
 
 fn run_ssp(device: String, gui_ssp_rcv: Receiver<SspRequest>, ssp_gui_snd: Sender<SspResponse>) {
     match Ssp3::new(device.as_str()) {
-        Ok(mut ssp3) => {
-            ssp_main_loop(ssp3.borrow_mut(), &gui_ssp_rcv, &ssp_gui_snd);
+        Ok(ssp3) => {
+            ssp_main_loop(Some(ssp3), &device, &gui_ssp_rcv, &ssp_gui_snd);
         }
         Err(e) => {
             let err_msg = e.to_string();
@@ -367,11 +1272,19 @@ fn run_ssp(device: String, gui_ssp_rcv: Receiver<SspRequest>, ssp_gui_snd: Sende
     }
 }
 
-fn ssp_main_loop(device: &mut Ssp3, gui_ssp_rcv: &Receiver<SspRequest>, ssp_gui_snd: &Sender<SspResponse>) {
-    while let Ok(request) = gui_ssp_rcv.recv_blocking() {
+/// Serves the requests of the GUI. `device` is `None` while the port is closed after a failed
+/// reconnect.
+fn ssp_main_loop(mut device: Option<Ssp3>, device_path: &str, gui_ssp_rcv: &Receiver<SspRequest>, ssp_gui_snd: &Sender<SspResponse>) {
+    // A request that arrived while counting, other than the cancellation of the count
+    let mut deferred: Option<SspRequest> = None;
+    while let Some(request) = deferred.take().or_else(|| gui_ssp_rcv.recv_blocking().ok()) {
         match request {
             SspRequest::Init() => {
-                match device.init() {
+                let result = match device.as_mut() {
+                    Some(device) => { device.init() }
+                    None => { Err(ssp3::Error::not_connected()) }
+                };
+                match result {
                     Ok(_) => {
                         if let Err(_) = ssp_gui_snd.send_blocking(SspResponse::Ok()) {
                             eprintln!("Error sending init OK");
@@ -388,7 +1301,19 @@ fn ssp_main_loop(device: &mut Ssp3, gui_ssp_rcv: &Receiver<SspRequest>, ssp_gui_
                 }
             }
             Measure(filter, time) => {
-                match device.measure(filter, time) {
+                let cancel = || match gui_ssp_rcv.try_recv() {
+                    Ok(SspRequest::Cancel()) => { true }
+                    Ok(request) => {
+                        deferred = Some(request);
+                        false
+                    }
+                    Err(_) => { false }
+                };
+                let result = match device.as_mut() {
+                    Some(device) => { device.measure(filter, time, cancel) }
+                    None => { Err(ssp3::Error::not_connected()) }
+                };
+                match result {
                     Ok(counts) => {
                         if let Err(_) = ssp_gui_snd.send_blocking(SspResponse::Counts(counts)) {
                             eprintln!("Error sending counts.");
@@ -404,6 +1329,30 @@ fn ssp_main_loop(device: &mut Ssp3, gui_ssp_rcv: &Receiver<SspRequest>, ssp_gui_
                     }
                 }
             }
+            SspRequest::Reconnect() => {
+                // Close the old port first, it may not be opened a second time. Then bring the
+                // SSP3 back into the state expected by measure.
+                device = None;
+                let result = Ssp3::new(device_path).and_then(|mut ssp3| {
+                    ssp3.init()?;
+                    Ok(ssp3)
+                });
+                let response = match result {
+                    Ok(ssp3) => {
+                        device = Some(ssp3);
+                        SspResponse::Ok()
+                    }
+                    Err(e) => {
+                        SspResponse::Error(e)
+                    }
+                };
+                if ssp_gui_snd.send_blocking(response).is_err() {
+                    break;
+                }
+            }
+            SspRequest::Cancel() => {
+                // Arrived after the count it was meant for had already completed.
+            }
             Finish() => {
                 if let Err(_) = ssp_gui_snd.send_blocking(SspResponse::Ok()) {
                     eprintln!("Error sending finish OK");
@@ -413,7 +1362,7 @@ fn ssp_main_loop(device: &mut Ssp3, gui_ssp_rcv: &Receiver<SspRequest>, ssp_gui_
         }
     }
 
-    if let Err(e) = device.finish() {
+    if let Some(Err(e)) = device.as_mut().map(|device| device.finish()) {
         eprintln!("Error finishing device: {}", e);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,195 @@
+//! Telescope mounts slewing to the stars of a run.
+
+use crate::alpaca::Alpaca;
+use crate::astro::Equatorial;
+use crate::indi::Indi;
+use crate::lx200::Lx200;
+use chrono::Utc;
+use gtk::gio;
+use serde::Serialize;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{fmt, io, thread};
+
+/// Interval for checking whether a slew has finished.
+const SLEW_POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// Longest slew we wait for.
+const SLEW_TIMEOUT: Duration = Duration::from_secs(300);
+
+#[derive(Debug)]
+pub enum Error {
+    Serial(serial::Error),
+    Io(io::Error),
+    Http(String),
+    Protocol(String),
+    /// The mount refused a command
+    Rejected(String),
+    Timeout,
+    Aborted,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Serial(e) => format!("Mount: serial error: {}", e).fmt(f),
+            Error::Io(e) => format!("Mount: I/O error: {}", e).fmt(f),
+            Error::Http(e) => format!("Mount: HTTP error: {}", e).fmt(f),
+            Error::Protocol(e) => format!("Mount: protocol error: {}", e).fmt(f),
+            Error::Rejected(e) => format!("Mount: {}", e).fmt(f),
+            Error::Timeout => format!("Mount: slew did not finish within {} s", SLEW_TIMEOUT.as_secs()).fmt(f),
+            Error::Aborted => "Mount: slew aborted".fmt(f),
+        }
+    }
+}
+
+impl From<serial::Error> for Error {
+    fn from(e: serial::Error) -> Self {
+        Error::Serial(e)
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(e: ureq::Error) -> Self {
+        Error::Http(e.to_string())
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Side of the pier the telescope is on in a German equatorial mount.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum PierSide {
+    East,
+    West,
+}
+
+/// Where the mount is pointing. The pier side is unknown for mounts not reporting it.
+#[derive(Debug, Clone, Copy)]
+pub struct Pointing {
+    pub position: Equatorial,
+    pub pier_side: Option<PierSide>,
+    /// Position of the focuser, for mounts controlled together with one
+    pub focus_position: Option<f64>,
+}
+
+/// Equinox of the coordinates a mount works with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Epoch {
+    J2000,
+    /// The current epoch, most mounts use it
+    OfDate,
+}
+
+impl Epoch {
+    /// Converts a J2000 position to the coordinates of the mount.
+    pub fn convert_from_j2000(self, position: &Equatorial) -> Equatorial {
+        match self {
+            Epoch::J2000 => { *position }
+            Epoch::OfDate => { position.precessed_from_j2000(Utc::now()) }
+        }
+    }
+
+    /// Converts a position reported by the mount to J2000.
+    pub fn convert_to_j2000(self, position: &Equatorial) -> Equatorial {
+        match self {
+            Epoch::J2000 => { *position }
+            Epoch::OfDate => { position.precessed_to_j2000(Utc::now()) }
+        }
+    }
+}
+
+/// A mount driver. Positions are for the epoch of the mount.
+pub trait Mount: Send {
+    /// Starts a slew, returning once the mount has accepted the target.
+    fn slew_to(&mut self, target: &Equatorial) -> Result<(), Error>;
+    fn is_slewing(&mut self) -> Result<bool, Error>;
+    fn abort(&mut self) -> Result<(), Error>;
+    fn pointing(&mut self) -> Result<Pointing, Error>;
+    /// Tells the mount that it is pointing at `position`, correcting its pointing model.
+    fn sync(&mut self, position: &Equatorial) -> Result<(), Error>;
+    fn epoch(&self) -> Epoch {
+        Epoch::OfDate
+    }
+}
+
+/// How to reach the mount, as configured.
+#[derive(Debug, Clone)]
+pub enum MountSettings {
+    Lx200 { device: String },
+    /// `address` is `host` or `host:port` of the indiserver, `device` and `focuser` the driver names
+    Indi { address: String, device: String, focuser: Option<String> },
+    /// `address` is `host:port` of the Alpaca server, `number` that of the telescope device
+    Alpaca { address: String, number: u32 },
+}
+
+/// Shares a mount between the GUI and the thread waiting for slews.
+#[derive(Clone)]
+pub struct MountHandle {
+    mount: Arc<Mutex<Box<dyn Mount>>>,
+    cancel: Arc<AtomicBool>,
+}
+
+impl MountHandle {
+    pub fn connect(settings: &MountSettings) -> Result<MountHandle, Error> {
+        let mount: Box<dyn Mount> = match settings {
+            MountSettings::Lx200 { device } => { Box::new(Lx200::new(device)?) }
+            MountSettings::Indi { address, device, focuser } => { Box::new(Indi::new(address, device, focuser.as_deref())?) }
+            MountSettings::Alpaca { address, number } => { Box::new(Alpaca::new(address, *number)?) }
+        };
+        Ok(MountHandle {
+            mount: Arc::new(Mutex::new(mount)),
+            cancel: Arc::new(AtomicBool::new(false)),
+        })
+    }
+
+    /// Slews to the J2000 target and waits until the mount has stopped.
+    pub async fn slew(&self, target: Equatorial) -> Result<(), Error> {
+        self.cancel.store(false, Ordering::SeqCst);
+        let mount = self.mount.clone();
+        let cancel = self.cancel.clone();
+        gio::spawn_blocking(move || {
+            let mut mount = mount.lock().unwrap();
+            let target = mount.epoch().convert_from_j2000(&target);
+            mount.slew_to(&target)?;
+            let deadline = Instant::now() + SLEW_TIMEOUT;
+            loop {
+                if cancel.load(Ordering::SeqCst) {
+                    mount.abort()?;
+                    return Err(Error::Aborted);
+                }
+                if !mount.is_slewing()? {
+                    return Ok(());
+                }
+                if Instant::now() >= deadline {
+                    mount.abort()?;
+                    return Err(Error::Timeout);
+                }
+                thread::sleep(SLEW_POLL_INTERVAL);
+            }
+        }).await.unwrap_or_else(|_| Err(Error::Protocol("mount thread panicked".to_string())))
+    }
+
+    /// Where the mount is pointing now, for J2000.
+    pub fn pointing(&self) -> Result<Pointing, Error> {
+        let mut mount = self.mount.lock().unwrap();
+        let pointing = mount.pointing()?;
+        Ok(Pointing { position: mount.epoch().convert_to_j2000(&pointing.position), ..pointing })
+    }
+
+    /// Syncs the mount to the J2000 position.
+    pub fn sync(&self, position: &Equatorial) -> Result<(), Error> {
+        let mut mount = self.mount.lock().unwrap();
+        let position = mount.epoch().convert_from_j2000(position);
+        mount.sync(&position)
+    }
+
+    /// Stops a slew in progress.
+    pub fn cancel(&self) {
+        self.cancel.store(true, Ordering::SeqCst);
+    }
+}
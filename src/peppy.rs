@@ -0,0 +1,182 @@
+//! Exchange of run definitions and data files with PepPy. PepPy reads and writes a run as JSON
+//! object with the filter indices and the stars, each with its `star_type` and `star_id`, and
+//! nothing else. Its data files have the columns of the first PEP Runner run logs, without row
+//! kinds and notes. The run log reader still accepts those, so PepPy data is read like a run log.
+
+use crate::common::{PepRun, RowKind, SkyStrategy, StarData};
+use crate::reduction::LogRecord;
+use crate::run_file::{self, RunFileError};
+use chrono::{DateTime, Utc};
+use csv::Writer;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PepPyRun {
+    filters: Vec<u8>,
+    items: Vec<PepPyStar>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PepPyStar {
+    star_type: String,
+    star_id: String,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct PepPyRecord<'a> {
+    timestamp: DateTime<Utc>,
+    index: u16,
+    star_id: &'a str,
+    star_type: &'a str,
+    is_star: bool,
+    filter: &'a str,
+    integration_time: u16,
+    count1: u16,
+    count2: u16,
+    count3: u16,
+}
+
+/// Writes a run in PepPy's format. Settings PepPy doesn't know are dropped, the returned
+/// warnings tell which.
+pub fn run_to_string(run: &PepRun) -> Result<(String, Vec<String>), serde_json::Error> {
+    let mut warnings = Vec::new();
+    if run.sky_strategy != SkyStrategy::default() {
+        warnings.push("PepPy takes a sky after each star, the sky strategy of the run is lost.".to_string());
+    }
+    if run.items.iter().any(|star| star.sky.is_some()) {
+        warnings.push("PepPy has no sky positions, those of the stars are lost.".to_string());
+    }
+    if run.items.iter().any(|star| !star.i_times.is_empty()) {
+        warnings.push("PepPy calibrates all integration times, the fixed times of the stars are lost.".to_string());
+    }
+    let peppy_run = PepPyRun {
+        filters: run.filters.clone(),
+        items: run.items.iter()
+            .map(|star| PepPyStar { star_type: star.star_type.clone(), star_id: star.name.clone() })
+            .collect(),
+    };
+    Ok((serde_json::to_string(&peppy_run)?, warnings))
+}
+
+/// Reads a run written by PepPy.
+pub fn run_from_str(text: &str) -> Result<PepRun, RunFileError> {
+    let peppy_run: PepPyRun = serde_json::from_str(text).map_err(|e| {
+        if e.is_syntax() || e.is_eof() {
+            RunFileError::Syntax(e)
+        } else {
+            RunFileError::Structure(e.to_string())
+        }
+    })?;
+    let items = peppy_run.items.into_iter()
+        .map(|star| StarData::new(&star.star_type, &star.star_id))
+        .collect();
+    let run = PepRun::new(peppy_run.filters, items);
+    run_file::validate(&run)?;
+    Ok(run)
+}
+
+/// Writes the star and sky rows of a run log as PepPy data file. PepPy knows no other rows,
+/// the returned warnings tell what is lost.
+pub fn log_to_string(records: &[LogRecord]) -> Result<(String, Vec<String>), csv::Error> {
+    let mut warnings = Vec::new();
+    let dropped = records.iter()
+        .filter(|r| !matches!(r.kind(), RowKind::Star | RowKind::Sky))
+        .count();
+    if dropped > 0 {
+        warnings.push(format!("PepPy has only star and sky rows, {} dark, pause and sequence rows are lost.", dropped));
+    }
+    if records.iter().any(|r| !r.note.is_empty()) {
+        warnings.push("PepPy has no notes, those of the rows are lost.".to_string());
+    }
+    let mut writer = Writer::from_writer(Vec::new());
+    for record in records.iter().filter(|r| matches!(r.kind(), RowKind::Star | RowKind::Sky)) {
+        writer.serialize(PepPyRecord {
+            timestamp: record.timestamp,
+            index: record.index,
+            star_id: &record.star_id,
+            star_type: &record.star_type,
+            is_star: record.kind() == RowKind::Star,
+            filter: &record.filter,
+            integration_time: record.integration_time,
+            count1: record.count1,
+            count2: record.count2,
+            count3: record.count3,
+        })?;
+    }
+    let data = writer.into_inner().map_err(|e| csv::Error::from(e.into_error()))?;
+    Ok((String::from_utf8(data).expect("CSV of strings should be UTF-8"), warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::SkyPosition;
+    use crate::reduction::read_log;
+    use chrono::TimeZone;
+    use std::{env, fs};
+
+    fn record(seconds: i64, star_id: &str, kind: RowKind, counts: u16) -> LogRecord {
+        LogRecord {
+            timestamp: Utc.with_ymd_and_hms(2024, 10, 1, 22, 0, 0).unwrap() + chrono::TimeDelta::seconds(seconds),
+            index: 0,
+            star_id: star_id.to_string(),
+            star_type: "PGM".to_string(),
+            is_star: kind == RowKind::Star,
+            filter: "B".to_string(),
+            integration_time: 1000,
+            count1: counts,
+            count2: counts + 1,
+            count3: counts + 2,
+            kind: Some(kind),
+            note: String::new(),
+        }
+    }
+
+    #[test]
+    fn round_trips_run() {
+        let stars = vec![StarData::new(&"CMP".to_string(), &"HD 1".to_string()),
+                         StarData::new(&"PGM".to_string(), &"V1 Cyg".to_string())];
+        let run = PepRun::new(vec![1, 2], stars);
+        let (text, warnings) = run_to_string(&run).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(text, r#"{"filters":[1,2],"items":[{"star_type":"CMP","star_id":"HD 1"},{"star_type":"PGM","star_id":"V1 Cyg"}]}"#);
+        let read = run_from_str(&text).unwrap();
+        assert_eq!(read.filters, run.filters);
+        assert_eq!(read.items.iter().map(|s| s.name.as_str()).collect::<Vec<_>>(), ["HD 1", "V1 Cyg"]);
+    }
+
+    #[test]
+    fn warns_about_lost_settings() {
+        let mut run = run_from_str(r#"{"filters":[2],"items":[{"star_type":"PGM","star_id":"V1 Cyg"}]}"#).unwrap();
+        run.sky_strategy = SkyStrategy::PerPair;
+        run.items[0].sky = SkyPosition::parse("5' N");
+        run.items[0].i_times.insert(2, 500);
+        let (text, warnings) = run_to_string(&run).unwrap();
+        assert_eq!(warnings.len(), 3);
+        assert!(!text.contains("sky"));
+        assert!(matches!(run_from_str(r#"{"filters":[],"items":[]}"#), Err(RunFileError::Invalid(_))));
+    }
+
+    #[test]
+    fn round_trips_data_file() {
+        let mut star = record(0, "V1 Cyg", RowKind::Star, 5000);
+        star.note = "haze".to_string();
+        let records = vec![record(-60, "", RowKind::Dark, 100), star, record(60, "V1 Cyg", RowKind::Sky, 800)];
+        let (text, warnings) = log_to_string(&records).unwrap();
+        assert_eq!(warnings.len(), 2);
+        assert!(text.starts_with("Timestamp,Index,StarId,StarType,IsStar,Filter,IntegrationTime,Count1,Count2,Count3\n"));
+
+        let path = env::temp_dir().join(format!("peprunner-peppy-{}.csv", std::process::id()));
+        fs::write(&path, text).unwrap();
+        let read = read_log(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(read.len(), 2);
+        for (read, written) in read.iter().zip(&records[1..]) {
+            assert_eq!(read.timestamp, written.timestamp);
+            assert_eq!(read.kind(), written.kind());
+            assert_eq!((read.count1, read.count2, read.count3), (written.count1, written.count2, written.count3));
+            assert_eq!((&read.star_id, &read.filter, read.integration_time), (&written.star_id, &written.filter, written.integration_time));
+        }
+    }
+}
@@ -0,0 +1,190 @@
+//! Period search in unevenly sampled magnitudes.
+
+/// Number of phase bins for the phase dispersion minimization
+const PDM_BINS: usize = 10;
+/// Limits the search time for long baselines with short trial periods
+const MAX_FREQUENCIES: usize = 50_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Observation {
+    /// Julian date
+    pub time: f64,
+    pub magnitude: f64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    LombScargle,
+    PhaseDispersion,
+}
+
+impl Method {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Method::LombScargle => { "Lomb-Scargle" }
+            Method::PhaseDispersion => { "PDM" }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Candidate {
+    pub method: Method,
+    /// Period in days
+    pub period: f64,
+    /// Normalized power for Lomb-Scargle, Θ for PDM
+    pub statistic: f64,
+}
+
+/// Result of a period search. The statistics are given for each trial frequency.
+#[derive(Debug, Clone)]
+pub struct Periodogram {
+    pub frequencies: Vec<f64>,
+    pub power: Vec<f64>,
+    pub theta: Vec<f64>,
+}
+
+impl Periodogram {
+    pub fn compute(observations: &[Observation], min_period: f64, max_period: f64) -> Periodogram {
+        let frequencies = frequency_grid(observations, min_period, max_period);
+        let power = lomb_scargle(observations, &frequencies);
+        let theta = phase_dispersion(observations, &frequencies);
+        Periodogram { frequencies, power, theta }
+    }
+
+    /// The strongest peaks of Lomb-Scargle and the deepest minima of PDM, best first.
+    pub fn candidates(&self, count: usize) -> Vec<Candidate> {
+        let mut result = self.extrema(Method::LombScargle, &self.power, count);
+        result.extend(self.extrema(Method::PhaseDispersion, &self.theta, count));
+        result
+    }
+
+    fn extrema(&self, method: Method, statistic: &[f64], count: usize) -> Vec<Candidate> {
+        // Maxima for the power, minima for Θ
+        let sign = if method == Method::LombScargle { 1.0 } else { -1.0 };
+        let mut peaks = (1..statistic.len().saturating_sub(1))
+            .filter(|i| sign * statistic[*i] > sign * statistic[i - 1] && sign * statistic[*i] >= sign * statistic[i + 1])
+            .map(|i| Candidate { method, period: 1.0 / self.frequencies[i], statistic: statistic[i] })
+            .collect::<Vec<_>>();
+        peaks.sort_by(|a, b| (sign * b.statistic).total_cmp(&(sign * a.statistic)));
+        peaks.truncate(count);
+        peaks
+    }
+}
+
+/// Trial frequencies in 1/d, spaced to resolve peaks over the time covered by the observations.
+fn frequency_grid(observations: &[Observation], min_period: f64, max_period: f64) -> Vec<f64> {
+    let (first, last) = observations.iter().fold((f64::MAX, f64::MIN), |(lo, hi), o| (lo.min(o.time), hi.max(o.time)));
+    let baseline = (last - first).max(max_period);
+    let f_min = 1.0 / max_period;
+    let f_max = 1.0 / min_period;
+    if f_max <= f_min || !baseline.is_finite() {
+        return Vec::new();
+    }
+    let step = (1.0 / (5.0 * baseline)).max((f_max - f_min) / MAX_FREQUENCIES as f64);
+    let count = ((f_max - f_min) / step) as usize + 1;
+    (0..count).map(|i| f_min + i as f64 * step).collect()
+}
+
+fn mean_and_variance(observations: &[Observation]) -> (f64, f64) {
+    let n = observations.len() as f64;
+    let mean = observations.iter().map(|o| o.magnitude).sum::<f64>() / n;
+    let variance = observations.iter().map(|o| (o.magnitude - mean).powi(2)).sum::<f64>() / (n - 1.0);
+    (mean, variance)
+}
+
+/// Normalized Lomb-Scargle power between 0 and 1.
+fn lomb_scargle(observations: &[Observation], frequencies: &[f64]) -> Vec<f64> {
+    if observations.len() < 3 {
+        return vec![0.0; frequencies.len()];
+    }
+    let (mean, _) = mean_and_variance(observations);
+    let sum_squares = observations.iter().map(|o| (o.magnitude - mean).powi(2)).sum::<f64>();
+    frequencies.iter()
+        .map(|f| {
+            let omega = 2.0 * std::f64::consts::PI * f;
+            let (s2, c2) = observations.iter()
+                .fold((0.0, 0.0), |(s, c), o| (s + (2.0 * omega * o.time).sin(), c + (2.0 * omega * o.time).cos()));
+            let tau = s2.atan2(c2) / (2.0 * omega);
+            let (mut yc, mut ys, mut cc, mut ss) = (0.0, 0.0, 0.0, 0.0);
+            for o in observations {
+                let (sin, cos) = (omega * (o.time - tau)).sin_cos();
+                let y = o.magnitude - mean;
+                yc += y * cos;
+                ys += y * sin;
+                cc += cos * cos;
+                ss += sin * sin;
+            }
+            if sum_squares <= 0.0 || cc <= 0.0 || ss <= 0.0 {
+                return 0.0;
+            }
+            (yc * yc / cc + ys * ys / ss) / sum_squares
+        })
+        .collect()
+}
+
+/// Stellingwerf's Θ, the pooled variance in phase bins relative to the total variance.
+fn phase_dispersion(observations: &[Observation], frequencies: &[f64]) -> Vec<f64> {
+    if observations.len() < PDM_BINS + 1 {
+        return vec![1.0; frequencies.len()];
+    }
+    let (_, variance) = mean_and_variance(observations);
+    frequencies.iter()
+        .map(|f| {
+            let mut bins = [(0usize, 0.0f64, 0.0f64); PDM_BINS];
+            for o in observations {
+                let bin = (((o.time * f).rem_euclid(1.0) * PDM_BINS as f64) as usize).min(PDM_BINS - 1);
+                bins[bin].0 += 1;
+                bins[bin].1 += o.magnitude;
+                bins[bin].2 += o.magnitude * o.magnitude;
+            }
+            let (mut sum, mut dof) = (0.0, 0.0);
+            for (n, s, s2) in bins.iter().filter(|b| b.0 > 1) {
+                sum += s2 - s * s / *n as f64;
+                dof += *n as f64 - 1.0;
+            }
+            if dof <= 0.0 || variance <= 0.0 {
+                return 1.0;
+            }
+            sum / dof / variance
+        })
+        .collect()
+}
+
+/// Phase between 0 and 1 of a time for the given period and epoch.
+pub fn phase(time: f64, period: f64, epoch: f64) -> f64 {
+    ((time - epoch) / period).rem_euclid(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Unevenly sampled sinusoid with a period of 2.5 d, observed once or twice per night.
+    fn sinusoid() -> Vec<Observation> {
+        (0..120)
+            .map(|i| {
+                let time = 2460000.0 + i as f64 * 0.37 + 0.05 * (i as f64 * 1.7).sin();
+                let magnitude = 8.0 + 0.3 * (2.0 * std::f64::consts::PI * time / 2.5).sin();
+                Observation { time, magnitude }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_period_of_sinusoid() {
+        let periodogram = Periodogram::compute(&sinusoid(), 0.5, 10.0);
+        let candidates = periodogram.candidates(3);
+        let best = |method| candidates.iter().find(|c| c.method == method).unwrap();
+        assert!((best(Method::LombScargle).period - 2.5).abs() < 0.01, "{:?}", candidates);
+        assert!(best(Method::LombScargle).statistic > 0.9);
+        assert!((best(Method::PhaseDispersion).period - 2.5).abs() < 0.01, "{:?}", candidates);
+        assert!(best(Method::PhaseDispersion).statistic < 0.1);
+    }
+
+    #[test]
+    fn folds_into_unit_interval() {
+        assert!((phase(2460001.25, 2.5, 2460000.0) - 0.5).abs() < 1e-9);
+        assert!((phase(2459999.5, 2.5, 2460000.0) - 0.8).abs() < 1e-9);
+    }
+}
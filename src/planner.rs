@@ -0,0 +1,160 @@
+//! Visibility of the stars during a night.
+
+use crate::astro::{airmass, moon_position, sun_position, Equatorial, Site};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
+use std::collections::HashSet;
+
+/// Altitude of the sun at the end of nautical twilight, darker skies count as night.
+const NIGHT_SUN_ALTITUDE: f64 = -12.0;
+/// Sampling interval of the altitude curves.
+const STEP: TimeDelta = TimeDelta::minutes(5);
+
+/// The dark part of a night.
+#[derive(Debug, Clone, Copy)]
+pub struct Night {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Visibility {
+    pub name: String,
+    pub position: Equatorial,
+    pub transit: DateTime<Utc>,
+    /// Highest altitude during the night in degrees
+    pub max_altitude: f64,
+    /// Part of the night the star is above the minimum altitude
+    pub window: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    /// Distance to the moon in degrees at the middle of the night
+    pub moon_separation: f64,
+    /// Altitude in degrees sampled over the night
+    pub altitudes: Vec<(DateTime<Utc>, f64)>,
+}
+
+impl Visibility {
+    /// Lowest airmass during the night.
+    pub fn min_airmass(&self) -> Option<f64> {
+        airmass(self.max_altitude)
+    }
+
+    /// First time in `from..to` the star is below `min_altitude`, if any.
+    pub fn first_below(&self, site: &Site, min_altitude: f64, from: DateTime<Utc>, to: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        first_below(&self.position, site, min_altitude, from, to)
+    }
+}
+
+fn first_below(position: &Equatorial, site: &Site, min_altitude: f64, from: DateTime<Utc>, to: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    samples(from, to).find(|t| position.altitude(site, *t) < min_altitude)
+}
+
+/// The named stars that are below `min_altitude` at some time in `from..to`, each once with the
+/// first such time.
+pub fn below_altitude(stars: &[(String, Equatorial)], site: &Site, min_altitude: f64,
+                      from: DateTime<Utc>, to: DateTime<Utc>) -> Vec<(String, DateTime<Utc>)> {
+    let mut seen = HashSet::new();
+    stars.iter()
+        .filter(|(name, _)| seen.insert(name.to_lowercase()))
+        .filter_map(|(name, position)| first_below(position, site, min_altitude, from, to).map(|t| (name.clone(), t)))
+        .collect()
+}
+
+fn samples(from: DateTime<Utc>, to: DateTime<Utc>) -> impl Iterator<Item = DateTime<Utc>> {
+    let count = ((to - from).num_seconds() / STEP.num_seconds()).max(0);
+    (0..=count).map(move |k| from + STEP * k as i32)
+}
+
+/// The night starting in the evening of `date` at the site. `None` during polar day; during
+/// polar night it spans noon to noon.
+pub fn night(site: &Site, date: NaiveDate) -> Option<Night> {
+    // Local noon, roughly
+    let noon = date.and_time(NaiveTime::from_hms_opt(12, 0, 0).unwrap()).and_utc()
+        - TimeDelta::seconds((site.longitude / 15.0 * 3600.0) as i64);
+    let dark = samples(noon, noon + TimeDelta::days(1))
+        .filter(|t| sun_position(*t).altitude(site, *t) < NIGHT_SUN_ALTITUDE)
+        .collect::<Vec<_>>();
+    Some(Night { start: *dark.first()?, end: *dark.last()? })
+}
+
+/// Visibility of the named stars during `night`.
+pub fn plan(stars: &[(String, Equatorial)], site: &Site, night: &Night, min_altitude: f64) -> Vec<Visibility> {
+    let middle = night.start + (night.end - night.start) / 2;
+    let moon = moon_position(middle);
+    stars.iter()
+        .map(|(name, position)| {
+            let altitudes = samples(night.start, night.end)
+                .map(|t| (t, position.altitude(site, t)))
+                .collect::<Vec<_>>();
+            let mut above = altitudes.iter().filter(|(_, altitude)| *altitude >= min_altitude).map(|(t, _)| *t);
+            let window = above.clone().next().zip(above.next_back());
+            Visibility {
+                name: name.clone(),
+                position: *position,
+                transit: position.transit(site, middle),
+                max_altitude: altitudes.iter().map(|(_, altitude)| *altitude).fold(f64::NEG_INFINITY, f64::max),
+                window,
+                moon_separation: position.separation(&moon),
+                altitudes,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Timelike;
+
+    const FRANKFURT: Site = Site { latitude: 50.1, longitude: 8.7 };
+
+    fn night_of(date: &str) -> Option<Night> {
+        night(&FRANKFURT, NaiveDate::parse_from_str(date, "%Y-%m-%d").unwrap())
+    }
+
+    #[test]
+    fn finds_dark_part_of_night() {
+        let winter = night_of("2024-12-21").unwrap();
+        assert_eq!(winter.start.date_naive().to_string(), "2024-12-21");
+        assert!((16..18).contains(&winter.start.hour()), "{:?}", winter);
+        assert!((5..7).contains(&winter.end.hour()), "{:?}", winter);
+        let summer = night_of("2024-06-21").unwrap();
+        assert!(summer.end - summer.start < TimeDelta::hours(5), "{:?}", summer);
+        // Further north the sun stays above -12° around the summer solstice
+        let oslo = Site { latitude: 60.0, longitude: 10.7 };
+        assert!(night(&oslo, NaiveDate::from_ymd_opt(2024, 6, 21).unwrap()).is_none());
+        let spring = night_of("2024-03-20").unwrap();
+        assert!(spring.end - spring.start < winter.end - winter.start);
+    }
+
+    #[test]
+    fn plans_visibility() {
+        let night = night_of("2024-12-21").unwrap();
+        // Betelgeuse culminates around midnight, Vega sets in the evening
+        let stars = [("Betelgeuse".to_string(), Equatorial { ra_hours: 5.919529, dec_degrees: 7.407064 }),
+            ("Vega".to_string(), Equatorial { ra_hours: 18.615649, dec_degrees: 38.783689 })];
+        let plan = plan(&stars, &FRANKFURT, &night, 30.0);
+        let betelgeuse = &plan[0];
+        assert!((betelgeuse.max_altitude - (90.0 - 50.1 + 7.407)).abs() < 0.5, "{}", betelgeuse.max_altitude);
+        assert!(betelgeuse.transit > night.start && betelgeuse.transit < night.end);
+        let (from, to) = betelgeuse.window.unwrap();
+        assert!(from > night.start && to < night.end && from < betelgeuse.transit && betelgeuse.transit < to);
+        assert!((betelgeuse.min_airmass().unwrap() - 1.0 / betelgeuse.max_altitude.to_radians().sin()).abs() < 0.01);
+        let vega = &plan[1];
+        assert_eq!(vega.window.unwrap().0, night.start);
+        assert!(vega.window.unwrap().1 < betelgeuse.window.unwrap().0);
+        assert_eq!(plan.iter().map(|v| v.altitudes.len()).collect::<Vec<_>>(), [betelgeuse.altitudes.len(); 2]);
+    }
+
+    #[test]
+    fn finds_stars_below_altitude() {
+        let night = night_of("2024-12-21").unwrap();
+        let vega = Equatorial { ra_hours: 18.615649, dec_degrees: 38.783689 };
+        let stars = [("Vega".to_string(), vega), ("Polaris".to_string(), Equatorial { ra_hours: 2.530301, dec_degrees: 89.264109 }),
+            ("VEGA".to_string(), vega)];
+        let low = below_altitude(&stars, &FRANKFURT, 30.0, night.start, night.end);
+        assert_eq!(low.len(), 1);
+        assert_eq!(low[0].0, "Vega");
+        assert!(vega.altitude(&FRANKFURT, low[0].1) < 30.0);
+        assert!(vega.altitude(&FRANKFURT, low[0].1 - STEP) >= 30.0);
+        assert!(below_altitude(&stars, &FRANKFURT, 30.0, night.start, night.start + STEP).is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+use crate::archive::{self, Archive};
+use crate::catalog::Catalog;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+/// Check star result of a run for one CHK/CMP pair in one filter.
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub check_id: String,
+    pub comparison_id: String,
+    pub filter: String,
+    /// Mean CHK−CMP of the run
+    pub measured: f64,
+    pub measured_error: f64,
+    /// CHK−CMP according to the catalog
+    pub catalog: Option<f64>,
+    /// Mean CHK−CMP of all other archived runs
+    pub historical: Option<f64>,
+    pub flagged: bool,
+}
+
+impl CheckResult {
+    pub fn describe(&self) -> String {
+        let mut text = format!("{} − {} in {}: {:.3} ± {:.3}",
+                               self.check_id, self.comparison_id, self.filter, self.measured, self.measured_error);
+        if let Some(catalog) = self.catalog {
+            let _ = write!(text, ", catalog {:.3} ({:+.3})", catalog, self.measured - catalog);
+        }
+        if let Some(historical) = self.historical {
+            let _ = write!(text, ", history {:.3} ({:+.3})", historical, self.measured - historical);
+        }
+        if self.flagged {
+            text.push_str(" — out of tolerance");
+        }
+        text
+    }
+}
+
+/// Compares the check star measurements of an archived run with the catalog and with
+/// earlier runs and stores the results with the run.
+pub fn check_run(archive: &Archive, run_id: i64, catalog: Option<&Catalog>, tolerance: f64) -> Result<Vec<CheckResult>, archive::Error> {
+    let checks = archive.run_differentials(run_id)?
+        .into_iter()
+        .filter(|m| m.star_type == "CHK")
+        .collect::<Vec<_>>();
+    let mut pairs = checks.iter()
+        .map(|m| (m.star_id.as_str(), m.comparison_id.as_str(), m.filter.as_str()))
+        .collect::<Vec<_>>();
+    pairs.sort();
+    pairs.dedup();
+
+    // Each check star's history is read once, however many comparison stars and filters it has
+    let mut histories = BTreeMap::new();
+    let mut results = Vec::with_capacity(pairs.len());
+    for (check_id, comparison_id, filter) in pairs {
+        let measurements = checks.iter()
+            .filter(|m| m.star_id == check_id && m.comparison_id == comparison_id && m.filter == filter)
+            .collect::<Vec<_>>();
+        let n = measurements.len() as f64;
+        let measured = measurements.iter().map(|m| m.magnitude).sum::<f64>() / n;
+        let measured_error = measurements.iter().map(|m| m.magnitude_error.powi(2)).sum::<f64>().sqrt() / n;
+        let catalog = catalog.and_then(|catalog| {
+            Some(catalog.magnitude(check_id, filter)? - catalog.magnitude(comparison_id, filter)?)
+        });
+        if !histories.contains_key(check_id) {
+            histories.insert(check_id, archive.differentials_of(check_id, "CHK")?);
+        }
+        let history = histories[check_id]
+            .iter()
+            .filter(|(id, m)| *id != run_id && m.comparison_id == comparison_id && m.filter == filter)
+            .map(|(_, m)| m.magnitude)
+            .collect::<Vec<_>>();
+        let historical = if history.is_empty() {
+            None
+        } else {
+            Some(history.iter().sum::<f64>() / history.len() as f64)
+        };
+        let flagged = [catalog, historical].into_iter()
+            .flatten()
+            .any(|reference| (measured - reference).abs() > tolerance);
+        results.push(CheckResult {
+            check_id: check_id.to_string(),
+            comparison_id: comparison_id.to_string(),
+            filter: filter.to_string(),
+            measured,
+            measured_error,
+            catalog,
+            historical,
+            flagged,
+        });
+    }
+    archive.store_check_results(run_id, &results)?;
+    Ok(results)
+}
+
+pub fn report(results: &[CheckResult]) -> String {
+    results.iter()
+        .map(|r| r.describe())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::{Path, PathBuf};
+    use std::{env, fs};
+
+    /// A run with the CHK star C and the PGM star P between measurements of the CMP star A.
+    /// The net counts of A are 10000, those of C are `check_counts` less 1000 for the sky.
+    fn log(date: &str, check_counts: u16) -> String {
+        let rows = [("0,A,CMP", 11000), ("1,C,CHK", check_counts), ("2,P,PGM", 3000), ("3,A,CMP", 11000)];
+        let mut log = "Timestamp,Index,StarId,StarType,IsStar,Filter,IntegrationTime,Count1,Count2,Count3,Kind,Note\n".to_string();
+        for (k, (star, counts)) in rows.iter().enumerate() {
+            log.push_str(&format!("{}T22:{:02}:00Z,{},true,V,1000,{},{},{},STAR,\n", date, 2 * k, star, counts, counts, counts));
+            log.push_str(&format!("{}T22:{:02}:30Z,{},false,V,1000,1000,1000,1000,SKY,\n", date, 2 * k, star));
+        }
+        log
+    }
+
+    /// Empty directory for the files of a test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("peprunner-qc-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn ingest(archive: &mut Archive, dir: &Path, date: &str, check_counts: u16) -> i64 {
+        let path = dir.join(format!("{}.csv", date));
+        fs::write(&path, log(date, check_counts)).unwrap();
+        archive.ingest_log(&path, "SSP3", "Completed").unwrap().unwrap()
+    }
+
+    #[test]
+    fn flags_check_stars_out_of_tolerance() {
+        let dir = test_dir("flags");
+        fs::write(dir.join("catalog.csv"), "Name,U,B,V,R,I,RA,Dec\nA,,,5.0,,,,\nC,,,7.5,,,,\n").unwrap();
+        let catalog = Catalog::load(&dir.join("catalog.csv")).unwrap();
+        let mut archive = Archive::open(Path::new(":memory:")).unwrap();
+
+        // C − A is 2.5 mag, which nothing can be compared with yet
+        let first = ingest(&mut archive, &dir, "2024-10-01", 2000);
+        let results = check_run(&archive, first, None, 0.05).unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!((results[0].check_id.as_str(), results[0].comparison_id.as_str()), ("C", "A"));
+        assert!((results[0].measured - 2.5).abs() < 1e-9, "{:?}", results[0]);
+        assert_eq!((results[0].catalog, results[0].historical), (None, None));
+        assert!(!results[0].flagged);
+
+        // 0.114 mag fainter than both the catalog and the first run
+        let second = ingest(&mut archive, &dir, "2024-10-02", 1900);
+        let results = check_run(&archive, second, Some(&catalog), 0.05).unwrap();
+        assert!((results[0].catalog.unwrap() - 2.5).abs() < 1e-9);
+        assert!((results[0].historical.unwrap() - 2.5).abs() < 1e-9);
+        assert!(results[0].flagged);
+        assert!(report(&results).contains("out of tolerance"));
+        assert!(!check_run(&archive, second, Some(&catalog), 0.2).unwrap()[0].flagged);
+        // The history alone is enough
+        assert!(check_run(&archive, second, None, 0.05).unwrap()[0].flagged);
+        // The first run only has the second one as history
+        let results = check_run(&archive, first, Some(&catalog), 0.05).unwrap();
+        assert!((results[0].historical.unwrap() - (2.5 + 2.5 * (1000.0f64 / 900.0).log10())).abs() < 1e-9);
+        assert!(results[0].flagged);
+        assert_eq!(archive.check_results_for_log(&dir.join("2024-10-01.csv")).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,402 @@
+use crate::common::RowKind;
+use chrono::{DateTime, Utc};
+use csv::{Reader, Writer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::Path;
+
+/// A row of a run log as written by the measurement.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct LogRecord {
+    pub timestamp: DateTime<Utc>,
+    pub index: u16,
+    pub star_id: String,
+    pub star_type: String,
+    pub is_star: bool,
+    pub filter: String,
+    pub integration_time: u16,
+    pub count1: u16,
+    pub count2: u16,
+    pub count3: u16,
+    // Missing in logs written before dark counts were introduced
+    #[serde(default)]
+    pub kind: Option<RowKind>,
+    #[serde(default)]
+    pub note: String,
+}
+
+impl LogRecord {
+    pub fn kind(&self) -> RowKind {
+        match self.kind {
+            Some(kind) => { kind }
+            None if self.is_star => { RowKind::Star }
+            None => { RowKind::Sky }
+        }
+    }
+
+    fn counts(&self) -> [f64; 3] {
+        [self.count1 as f64, self.count2 as f64, self.count3 as f64]
+    }
+
+    fn seconds(&self) -> f64 {
+        self.integration_time as f64 / 100.0
+    }
+
+    /// Mean count rate in counts per second, `None` for rows without counts such as pauses.
+    pub fn rate(&self) -> Option<f64> {
+        if self.integration_time == 0 {
+            return None;
+        }
+        Some(self.counts().iter().sum::<f64>() / 3.0 / self.seconds())
+    }
+
+    /// Standard deviation of the three counts relative to the photon noise expected for
+    /// their mean. Values well above one hint at clouds, guiding errors or interference.
+    pub fn spread(&self) -> f64 {
+        let counts = self.counts();
+        let mean = counts.iter().sum::<f64>() / 3.0;
+        if mean <= 0.0 {
+            return 0.0;
+        }
+        let variance = counts.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / 2.0;
+        variance.sqrt() / mean.sqrt()
+    }
+
+    /// Standard error of the mean count rate, `None` for rows without counts.
+    pub fn rate_error(&self) -> Option<f64> {
+        if self.integration_time == 0 {
+            return None;
+        }
+        let counts = self.counts();
+        let mean = counts.iter().sum::<f64>() / 3.0;
+        let variance = counts.iter().map(|c| (c - mean).powi(2)).sum::<f64>() / 2.0;
+        Some((variance / 3.0).sqrt() / self.seconds())
+    }
+}
+
+/// Sky and dark corrected instrumental magnitude of a single star measurement.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ReducedMeasurement {
+    pub timestamp: DateTime<Utc>,
+    pub index: u16,
+    pub star_id: String,
+    pub star_type: String,
+    pub filter: String,
+    pub net_rate: f64,
+    pub magnitude: f64,
+    pub magnitude_error: f64,
+}
+
+/// Magnitude of a program or check star relative to the comparison star.
+#[derive(Debug, Clone)]
+pub struct DifferentialMagnitude {
+    pub timestamp: DateTime<Utc>,
+    pub star_id: String,
+    pub star_type: String,
+    /// The CMP nearest in time
+    pub comparison_id: String,
+    pub filter: String,
+    pub magnitude: f64,
+    pub magnitude_error: f64,
+}
+
+/// A star measurement that could not be reduced.
+#[derive(Debug, Clone)]
+pub struct Unreduced {
+    pub timestamp: DateTime<Utc>,
+    pub star_id: String,
+    pub filter: String,
+    pub reason: &'static str,
+}
+
+impl Unreduced {
+    fn new(star: &LogRecord, reason: &'static str) -> Self {
+        Unreduced { timestamp: star.timestamp, star_id: star.star_id.clone(), filter: star.filter.clone(), reason }
+    }
+}
+
+impl fmt::Display for Unreduced {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} in {} at {} UT: {}", self.star_id, self.filter, self.timestamp.format("%H:%M:%S"), self.reason)
+    }
+}
+
+pub fn read_log(path: &Path) -> Result<Vec<LogRecord>, csv::Error> {
+    let mut reader = Reader::from_path(path)?;
+    reader.deserialize().collect()
+}
+
+pub fn write_reduced(path: &Path, reduced: &[ReducedMeasurement]) -> Result<(), csv::Error> {
+    let mut writer = Writer::from_path(path)?;
+    for measurement in reduced {
+        writer.serialize(measurement)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reduces the star measurements of a run log. Dark counts are interpolated in time and
+/// subtracted from star and sky before the sky, interpolated to the time of the star
+/// measurement, is subtracted from the star. Measurements without a sky or not above it are
+/// returned separately.
+pub fn reduce(records: &[LogRecord]) -> (Vec<ReducedMeasurement>, Vec<Unreduced>) {
+    let darks = records.iter()
+        .filter(|r| r.kind() == RowKind::Dark)
+        .filter_map(|r| r.rate().map(|rate| (r.timestamp, rate)))
+        .collect::<Vec<_>>();
+
+    let mut reduced = Vec::new();
+    let mut unreduced = Vec::new();
+    for star in records.iter().filter(|r| r.kind() == RowKind::Star) {
+        let (Some(rate), Some(star_error)) = (star.rate(), star.rate_error()) else {
+            unreduced.push(Unreduced::new(star, "no integration time"));
+            continue;
+        };
+        let Some((sky_rate, sky_error)) = sky_for(records, star, &darks) else {
+            unreduced.push(Unreduced::new(star, "no sky"));
+            continue;
+        };
+        let star_rate = rate - interpolate(&darks, star.timestamp);
+        let net_rate = star_rate - sky_rate;
+        if net_rate <= 0.0 {
+            unreduced.push(Unreduced::new(star, "not above sky"));
+            continue;
+        }
+        let rate_error = (star_error.powi(2) + sky_error.powi(2)).sqrt();
+        reduced.push(ReducedMeasurement {
+            timestamp: star.timestamp,
+            index: star.index,
+            star_id: star.star_id.clone(),
+            star_type: star.star_type.clone(),
+            filter: star.filter.clone(),
+            net_rate,
+            magnitude: -2.5 * net_rate.log10(),
+            magnitude_error: 2.5 / 10f64.ln() * rate_error / net_rate,
+        });
+    }
+    (reduced, unreduced)
+}
+
+/// Differential magnitudes of the PGM and CHK measurements of a reduced run. The CMP
+/// magnitude is interpolated to the time of the measurement, its error is that of the
+/// nearest CMP measurement.
+pub fn differential(reduced: &[ReducedMeasurement]) -> Vec<DifferentialMagnitude> {
+    reduced.iter()
+        .filter(|m| m.star_type == "PGM" || m.star_type == "CHK")
+        .filter_map(|m| {
+            let comparisons = reduced.iter()
+                .filter(|c| c.star_type == "CMP" && c.filter == m.filter)
+                .collect::<Vec<_>>();
+            let nearest = comparisons.iter().min_by_key(|c| (c.timestamp - m.timestamp).abs())?;
+            let series = comparisons.iter().map(|c| (c.timestamp, c.magnitude)).collect::<Vec<_>>();
+            Some(DifferentialMagnitude {
+                timestamp: m.timestamp,
+                star_id: m.star_id.clone(),
+                star_type: m.star_type.clone(),
+                comparison_id: nearest.star_id.clone(),
+                filter: m.filter.clone(),
+                magnitude: m.magnitude - interpolate(&series, m.timestamp),
+                magnitude_error: (m.magnitude_error.powi(2) + nearest.magnitude_error.powi(2)).sqrt(),
+            })
+        })
+        .collect()
+}
+
+/// Dark corrected sky rate and its error at the time of a star measurement. Skies taken for
+/// the same run item are used. A sky shared by several stars, as with one sky per pair, is
+/// logged for the last star sharing it, so a star without any sky of its own uses the next
+/// sky logged after it. A star whose sky was skipped in this filter has no sky. Skies on both
+/// sides of the star are interpolated.
+fn sky_for(records: &[LogRecord], star: &LogRecord, darks: &[(DateTime<Utc>, f64)]) -> Option<(f64, f64)> {
+    let all_skies = records.iter()
+        .filter(|r| r.kind() == RowKind::Sky && r.rate().is_some())
+        .collect::<Vec<_>>();
+    let sky_index = if all_skies.iter().any(|r| r.index == star.index) {
+        star.index
+    } else {
+        all_skies.iter()
+            .filter(|r| r.timestamp > star.timestamp)
+            .min_by_key(|r| r.timestamp)
+            .map(|r| r.index)
+            .filter(|index| *index > star.index)?
+    };
+    let candidates = all_skies.into_iter()
+        .filter(|r| r.index == sky_index && r.filter == star.filter)
+        .collect::<Vec<_>>();
+
+    let before = candidates.iter()
+        .filter(|r| r.timestamp <= star.timestamp)
+        .max_by_key(|r| r.timestamp);
+    let after = candidates.iter()
+        .filter(|r| r.timestamp > star.timestamp)
+        .min_by_key(|r| r.timestamp);
+    let corrected = |sky: &LogRecord| Some((sky.rate()? - interpolate(darks, sky.timestamp), sky.rate_error()?));
+    match (before, after) {
+        (Some(before), Some(after)) => {
+            let (rate0, error0) = corrected(before)?;
+            let (rate1, error1) = corrected(after)?;
+            let series = [(before.timestamp, rate0), (after.timestamp, rate1)];
+            Some((interpolate(&series, star.timestamp), error0.max(error1)))
+        }
+        (Some(sky), None) | (None, Some(sky)) => {
+            corrected(sky)
+        }
+        (None, None) => { None }
+    }
+}
+
+/// Linear interpolation in time of a series sorted by time. Outside the series the
+/// nearest value is used, an empty series yields zero.
+fn interpolate(series: &[(DateTime<Utc>, f64)], timestamp: DateTime<Utc>) -> f64 {
+    let Some(first) = series.first() else {
+        return 0.0;
+    };
+    if timestamp <= first.0 {
+        return first.1;
+    }
+    for pair in series.windows(2) {
+        let (t0, v0) = pair[0];
+        let (t1, v1) = pair[1];
+        if timestamp <= t1 {
+            let span = (t1 - t0).num_milliseconds() as f64;
+            if span <= 0.0 {
+                return v1;
+            }
+            let fraction = (timestamp - t0).num_milliseconds() as f64 / span;
+            return v0 + fraction * (v1 - v0);
+        }
+    }
+    series.last().unwrap().1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn record(seconds: i64, index: u16, star_id: &str, star_type: &str, kind: RowKind, counts: u16) -> LogRecord {
+        LogRecord {
+            timestamp: Utc.with_ymd_and_hms(2024, 10, 1, 22, 0, 0).unwrap() + chrono::TimeDelta::seconds(seconds),
+            index,
+            star_id: star_id.to_string(),
+            star_type: star_type.to_string(),
+            is_star: kind == RowKind::Star,
+            filter: "V".to_string(),
+            integration_time: 1000,
+            count1: counts,
+            count2: counts,
+            count3: counts,
+            kind: Some(kind),
+            note: String::new(),
+        }
+    }
+
+    #[test]
+    fn subtracts_dark_and_sky() {
+        let records = [
+            record(0, 0, "DARK", "DRK", RowKind::Dark, 100),
+            record(60, 0, "A", "CMP", RowKind::Star, 10000),
+            record(120, 0, "A", "CMP", RowKind::Sky, 1000),
+            record(180, 1, "DARK", "DRK", RowKind::Dark, 100),
+        ];
+        let (reduced, unreduced) = reduce(&records);
+        assert!(unreduced.is_empty());
+        assert_eq!(reduced.len(), 1);
+        assert!((reduced[0].net_rate - 900.0).abs() < 1e-9);
+        assert!((reduced[0].magnitude + 2.5 * 900f64.log10()).abs() < 1e-9);
+    }
+
+    #[test]
+    fn reports_unreduced_stars() {
+        let (reduced, unreduced) = reduce(&[record(0, 0, "A", "CMP", RowKind::Star, 10000)]);
+        assert!(reduced.is_empty());
+        assert_eq!(unreduced.len(), 1);
+        assert_eq!(unreduced[0].reason, "no sky");
+
+        let records = [
+            record(0, 0, "B", "PGM", RowKind::Star, 500),
+            record(60, 0, "B", "PGM", RowKind::Sky, 1000),
+        ];
+        let (reduced, unreduced) = reduce(&records);
+        assert!(reduced.is_empty());
+        assert_eq!(unreduced[0].reason, "not above sky");
+    }
+
+    #[test]
+    fn ignores_rows_without_counts() {
+        let mut pause = record(90, 0, "A", "CMP", RowKind::Pause, 0);
+        pause.filter = String::new();
+        pause.integration_time = 0;
+        assert_eq!(pause.rate(), None);
+        let records = [
+            record(0, 0, "A", "CMP", RowKind::Star, 10000),
+            pause,
+            record(120, 0, "A", "CMP", RowKind::Sky, 1000),
+        ];
+        let (reduced, unreduced) = reduce(&records);
+        assert!(unreduced.is_empty());
+        assert!((reduced[0].net_rate - 900.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn shares_sky_of_pair() {
+        let records = [
+            record(0, 0, "A", "CMP", RowKind::Star, 10000),
+            record(60, 1, "B", "PGM", RowKind::Star, 5000),
+            record(120, 1, "B", "PGM", RowKind::Sky, 1000),
+        ];
+        let (reduced, unreduced) = reduce(&records);
+        assert!(unreduced.is_empty());
+        assert!((reduced[0].net_rate - 900.0).abs() < 1e-9);
+        assert!((reduced[1].net_rate - 400.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn interpolates_skies_around_star() {
+        let records = [
+            record(0, 0, "A", "CMP", RowKind::Sky, 1000),
+            record(60, 0, "A", "CMP", RowKind::Star, 10000),
+            record(120, 0, "A", "CMP", RowKind::Sky, 2000),
+        ];
+        let (reduced, _) = reduce(&records);
+        assert!((reduced[0].net_rate - 850.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn skipped_sky_is_not_replaced() {
+        let mut sky_b = record(30, 0, "A", "CMP", RowKind::Sky, 1000);
+        sky_b.filter = "B".to_string();
+        let records = [
+            record(0, 0, "A", "CMP", RowKind::Star, 10000),
+            sky_b,
+            record(60, 1, "B", "PGM", RowKind::Star, 5000),
+            record(120, 1, "B", "PGM", RowKind::Sky, 1000),
+        ];
+        let (reduced, unreduced) = reduce(&records);
+        assert_eq!(reduced.len(), 1);
+        assert_eq!(reduced[0].star_id, "B");
+        assert_eq!(unreduced[0].star_id, "A");
+        assert_eq!(unreduced[0].reason, "no sky");
+    }
+
+    #[test]
+    fn interpolates_comparison() {
+        let reduced = [(0, "A", "CMP", 1.0), (60, "B", "PGM", 3.0), (120, "A", "CMP", 2.0)]
+            .map(|(seconds, star_id, star_type, magnitude)| ReducedMeasurement {
+                timestamp: Utc.with_ymd_and_hms(2024, 10, 1, 22, 0, 0).unwrap() + chrono::TimeDelta::seconds(seconds),
+                index: 0,
+                star_id: star_id.to_string(),
+                star_type: star_type.to_string(),
+                filter: "V".to_string(),
+                net_rate: 0.0,
+                magnitude,
+                magnitude_error: 0.01,
+            });
+        let differentials = differential(&reduced);
+        assert_eq!(differentials.len(), 1);
+        assert!((differentials[0].magnitude - 1.5).abs() < 1e-9);
+        assert_eq!(differentials[0].comparison_id, "A");
+    }
+}
@@ -0,0 +1,52 @@
+//! Rows of a run log rejected by the operator. They are kept in a file next to the log,
+//! so the log itself with the raw counts is never changed.
+
+use crate::reduction::LogRecord;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rejection {
+    /// Position of the row in the log, not counting the header
+    pub row: usize,
+    /// Guards against applying the rejection to an edited log
+    pub timestamp: DateTime<Utc>,
+    pub reason: String,
+}
+
+pub fn rejections_path(log_path: &Path) -> PathBuf {
+    log_path.with_extension("rejections.json")
+}
+
+/// Reads the rejections of a log, none if there is no rejections file.
+pub fn load(log_path: &Path) -> io::Result<Vec<Rejection>> {
+    match fs::read_to_string(rejections_path(log_path)) {
+        Ok(text) => { serde_json::from_str(&text).map_err(|e| io::Error::new(ErrorKind::InvalidData, e)) }
+        Err(e) if e.kind() == ErrorKind::NotFound => { Ok(Vec::new()) }
+        Err(e) => { Err(e) }
+    }
+}
+
+pub fn save(log_path: &Path, rejections: &[Rejection]) -> io::Result<()> {
+    let path = rejections_path(log_path);
+    if rejections.is_empty() {
+        return match fs::remove_file(path) {
+            Err(e) if e.kind() != ErrorKind::NotFound => { Err(e) }
+            _ => { Ok(()) }
+        };
+    }
+    let text = serde_json::to_string_pretty(rejections).map_err(|e| io::Error::new(ErrorKind::InvalidData, e))?;
+    fs::write(path, text)
+}
+
+/// Removes the rejected rows from a log.
+pub fn apply(records: Vec<LogRecord>, rejections: &[Rejection]) -> Vec<LogRecord> {
+    records.into_iter()
+        .enumerate()
+        .filter(|(row, record)| !rejections.iter().any(|r| r.row == *row && r.timestamp == record.timestamp))
+        .map(|(_, record)| record)
+        .collect()
+}
@@ -0,0 +1,206 @@
+//! Reading and writing of run files. Files written before the format got versioned count as
+//! version 0. Later versions only added optional fields, so older files read as they are.
+
+use crate::common::{PepRun, SkyPosition, MAX_SKY_OFFSET, STAR_TYPES};
+use crate::measurement::MAX_I_TIME;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fmt;
+
+pub const FORMAT_VERSION: u64 = 3;
+const VERSION_KEY: &str = "format_version";
+/// Number of filter positions of the photometer
+const FILTER_COUNT: u8 = 6;
+
+#[derive(Debug)]
+pub enum RunFileError {
+    /// The file isn't JSON
+    Syntax(serde_json::Error),
+    /// JSON, but not shaped like a run
+    Structure(String),
+    /// Written by a newer PEP Runner
+    UnsupportedVersion(u64),
+    /// A run with invalid contents, one message per problem
+    Invalid(Vec<String>),
+}
+
+impl fmt::Display for RunFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RunFileError::Syntax(e) => {
+                write!(f, "The file is not valid JSON (line {}, column {}): {}", e.line(), e.column(), e)
+            }
+            RunFileError::Structure(msg) => {
+                write!(f, "The file does not contain a PEP run: {}", msg)
+            }
+            RunFileError::UnsupportedVersion(version) => {
+                write!(f, "The run file has format version {}, this program only reads up to version {}. Please update PEP Runner.",
+                       version, FORMAT_VERSION)
+            }
+            RunFileError::Invalid(problems) => {
+                write!(f, "The run is invalid:\n{}", problems.join("\n"))
+            }
+        }
+    }
+}
+
+/// Writes a valid run, refusing runs that couldn't be read back.
+pub fn to_string(run: &PepRun) -> Result<String, RunFileError> {
+    validate(run)?;
+    let mut value = serde_json::to_value(run).map_err(|e| RunFileError::Structure(e.to_string()))?;
+    if let Value::Object(map) = &mut value {
+        map.insert(VERSION_KEY.to_string(), Value::from(FORMAT_VERSION));
+    }
+    serde_json::to_string(&value).map_err(|e| RunFileError::Structure(e.to_string()))
+}
+
+pub fn from_str(text: &str) -> Result<PepRun, RunFileError> {
+    let value: Value = serde_json::from_str(text).map_err(RunFileError::Syntax)?;
+    let Value::Object(mut map) = value else {
+        return Err(RunFileError::Structure("expected an object at the top level".to_string()));
+    };
+    let version = match map.get(VERSION_KEY) {
+        None => { 0 }
+        Some(version) => {
+            version.as_u64()
+                .ok_or_else(|| RunFileError::Structure(format!("{} is not a number: {}", VERSION_KEY, version)))?
+        }
+    };
+    if version > FORMAT_VERSION {
+        return Err(RunFileError::UnsupportedVersion(version));
+    }
+    map.remove(VERSION_KEY);
+    let run: PepRun = serde_json::from_value(Value::Object(map))
+        .map_err(|e| RunFileError::Structure(e.to_string()))?;
+    validate(&run)?;
+    Ok(run)
+}
+
+pub fn validate(run: &PepRun) -> Result<(), RunFileError> {
+    let mut problems = Vec::new();
+    if run.filters.is_empty() {
+        problems.push("No filters selected.".to_string());
+    }
+    let mut seen = HashSet::new();
+    for filter in run.filters.iter() {
+        if *filter >= FILTER_COUNT {
+            problems.push(format!("Filter index {} is out of range, allowed are 0 to {}.", filter, FILTER_COUNT - 1));
+        } else if !seen.insert(*filter) {
+            problems.push(format!("Filter index {} appears more than once.", filter));
+        }
+    }
+    if run.items.is_empty() {
+        problems.push("The run contains no stars.".to_string());
+    }
+    for (index, star) in run.items.iter().enumerate() {
+        if !STAR_TYPES.contains(&star.star_type.as_str()) {
+            problems.push(format!("Star {} ({}) has unknown type '{}', allowed are {}.",
+                                  index + 1, star.name, star.star_type, STAR_TYPES.join(", ")));
+        }
+        if star.name.trim().is_empty() {
+            problems.push(format!("Star {} has no name.", index + 1));
+        }
+        match star.sky {
+            Some(SkyPosition::Offset { arcmin, .. }) if !(arcmin > 0.0 && arcmin <= MAX_SKY_OFFSET) => {
+                problems.push(format!("Star {} ({}) has a sky offset of {}', allowed are up to {}'.",
+                                      index + 1, star.name, arcmin, MAX_SKY_OFFSET));
+            }
+            Some(SkyPosition::Coordinates { ra, dec }) if !(0.0..24.0).contains(&ra) || !(-90.0..=90.0).contains(&dec) => {
+                problems.push(format!("Star {} ({}) has invalid sky coordinates RA {} h, Dec {}°.",
+                                      index + 1, star.name, ra, dec));
+            }
+            _ => {}
+        }
+        for (filter, i_time) in star.i_times.iter() {
+            if *filter >= FILTER_COUNT {
+                problems.push(format!("Star {} ({}) has an integration time for filter index {}, allowed are 0 to {}.",
+                                      index + 1, star.name, filter, FILTER_COUNT - 1));
+            }
+            if *i_time == 0 || *i_time > MAX_I_TIME {
+                problems.push(format!("Star {} ({}) has an integration time of {:.2} s, allowed are up to {:.2} s.",
+                                      index + 1, star.name, *i_time as f64 / 100.0, MAX_I_TIME as f64 / 100.0));
+            }
+        }
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(RunFileError::Invalid(problems))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::{Direction, SkyStrategy, StarData};
+
+    fn star(star_type: &str, name: &str) -> StarData {
+        StarData::new(&star_type.to_string(), &name.to_string())
+    }
+
+    fn problems(result: Result<PepRun, RunFileError>) -> Vec<String> {
+        match result {
+            Err(RunFileError::Invalid(problems)) => { problems }
+            other => { panic!("expected an invalid run, got {:?}", other) }
+        }
+    }
+
+    #[test]
+    fn round_trips_run() {
+        let mut pgm = star("PGM", "V1 Cyg");
+        pgm.sky = Some(SkyPosition::Offset { direction: Direction::NE, arcmin: 5.0 });
+        pgm.i_times.insert(5, 1500);
+        let mut run = PepRun::new(vec![1, 2, 5], vec![star("CMP", "HD 1"), pgm]);
+        run.sky_strategy = SkyStrategy::PerPair;
+        let text = to_string(&run).unwrap();
+        assert!(text.contains(&format!("\"{}\":{}", VERSION_KEY, FORMAT_VERSION)));
+        let read = from_str(&text).unwrap();
+        assert_eq!(read.filters, run.filters);
+        assert_eq!(read.sky_strategy, SkyStrategy::PerPair);
+        assert_eq!(read.items[1].name, "V1 Cyg");
+        assert_eq!(read.items[1].sky, run.items[1].sky);
+        assert_eq!(read.items[1].i_times, run.items[1].i_times);
+    }
+
+    #[test]
+    fn reads_clear_filter() {
+        let run = from_str(r#"{"format_version":3,"filters":[2,5],"items":[{"star_type":"CMP","star_id":"HD 1"}]}"#).unwrap();
+        assert_eq!(run.filters, vec![2, 5]);
+        let problems = problems(from_str(r#"{"filters":[6],"items":[{"star_type":"CMP","star_id":"HD 1"}]}"#));
+        assert_eq!(problems.len(), 1, "{:?}", problems);
+    }
+
+    #[test]
+    fn reads_unversioned_run() {
+        let run = from_str(r#"{"filters":[2],"items":[{"star_type":"CMP","star_id":"HD 1"}]}"#).unwrap();
+        assert_eq!(run.filters, vec![2]);
+        assert_eq!(run.items[0].name, "HD 1");
+        assert!(run.items[0].sky.is_none());
+        assert!(run.items[0].i_times.is_empty());
+    }
+
+    #[test]
+    fn rejects_newer_version() {
+        let text = format!(r#"{{"{}":{},"filters":[2],"items":[]}}"#, VERSION_KEY, FORMAT_VERSION + 1);
+        assert!(matches!(from_str(&text), Err(RunFileError::UnsupportedVersion(v)) if v == FORMAT_VERSION + 1));
+        assert!(matches!(from_str("{\"filters\":"), Err(RunFileError::Syntax(_))));
+        assert!(matches!(from_str("[]"), Err(RunFileError::Structure(_))));
+    }
+
+    #[test]
+    fn reports_every_problem() {
+        let text = r#"{"filters":[2,2,6],"items":[
+            {"star_type":"XYZ","star_id":" "},
+            {"star_type":"PGM","star_id":"V1 Cyg","sky":{"kind":"offset","direction":"N","arcmin":500.0},"i_times":{"6":100,"2":0}}]}"#;
+        let problems = problems(from_str(text));
+        assert_eq!(problems.len(), 7, "{:?}", problems);
+        assert!(problems[0].contains("appears more than once"));
+        assert!(problems[1].contains("out of range, allowed are 0 to 5"));
+    }
+
+    #[test]
+    fn refuses_to_write_invalid_run() {
+        let run = PepRun::new(vec![], vec![star("CMP", "HD 1")]);
+        assert!(matches!(to_string(&run), Err(RunFileError::Invalid(_))));
+    }
+}
@@ -0,0 +1,202 @@
+//! Ordering of runs and of the stars of transform runs, keeping the targets close to the
+//! meridian and the slews between them short.
+
+use crate::astro::{airmass, Equatorial, Site};
+use crate::common::PepRun;
+use crate::estimate::{estimate, slew_time, Conditions};
+use chrono::{DateTime, TimeDelta, Utc};
+
+/// Weight of the airmass against the airmass lost by not measuring a target at its best time.
+const AIRMASS_WEIGHT: f64 = 0.1;
+/// Weight of a minute of slewing against a unit of airmass.
+const SLEW_WEIGHT: f64 = 0.02;
+/// Added to the airmass of targets below the altitude limit.
+const BELOW_LIMIT_PENALTY: f64 = 100.0;
+/// Sampling interval when looking for the best time of a target.
+const STEP: TimeDelta = TimeDelta::minutes(5);
+
+/// Something measured in one go: a run, or a star together with its repeats.
+#[derive(Debug, Clone)]
+pub struct Target {
+    /// Positions in the order they are visited, empty if none is known
+    pub positions: Vec<Equatorial>,
+    pub duration: TimeDelta,
+}
+
+/// Mean airmass of the positions at `timestamp`, penalized below the altitude limit.
+fn airmass_cost(positions: &[Equatorial], site: &Site, timestamp: DateTime<Utc>, min_altitude: f64) -> f64 {
+    let total = positions.iter()
+        .map(|position| {
+            let altitude = position.altitude(site, timestamp);
+            match airmass(altitude) {
+                Some(x) if altitude >= min_altitude => { x }
+                _ => { BELOW_LIMIT_PENALTY + (min_altitude - altitude).max(0.0) }
+            }
+        })
+        .sum::<f64>();
+    total / positions.len() as f64
+}
+
+/// Greedy order of `targets` when starting at `start`. Next is always the target losing the
+/// least by being measured now instead of at its best time before the schedule ends, with its
+/// airmass and the slew to it breaking ties. Targets without positions keep their relative
+/// order at the end.
+pub fn order(targets: &[Target], site: &Site, start: DateTime<Utc>, min_altitude: f64) -> Vec<usize> {
+    let end = start + targets.iter().map(|target| target.duration).sum::<TimeDelta>();
+    let (mut remaining, unknown): (Vec<usize>, Vec<usize>) = (0..targets.len())
+        .partition(|k| !targets[*k].positions.is_empty());
+    let mut order = Vec::with_capacity(targets.len());
+    let mut time = start;
+    let mut last: Option<Equatorial> = None;
+    while !remaining.is_empty() {
+        let costs = remaining.iter()
+            .map(|k| {
+                let target = &targets[*k];
+                let slew = last.map(|from| slew_time(&from, &target.positions[0])).unwrap_or_default();
+                let middle = time + slew + target.duration / 2;
+                let now = airmass_cost(&target.positions, site, middle, min_altitude);
+                let latest = end - target.duration / 2;
+                let mut best = now;
+                let mut t = middle + STEP;
+                while t <= latest {
+                    best = best.min(airmass_cost(&target.positions, site, t, min_altitude));
+                    t += STEP;
+                }
+                (now - best) + AIRMASS_WEIGHT * now + SLEW_WEIGHT * slew.num_seconds() as f64 / 60.0
+            })
+            .collect::<Vec<_>>();
+        let next = costs.iter()
+            .enumerate()
+            .min_by(|a, b| a.1.total_cmp(b.1))
+            .map(|(position, _)| position)
+            .unwrap();
+        let k = remaining.remove(next);
+        let target = &targets[k];
+        let slew = last.map(|from| slew_time(&from, &target.positions[0])).unwrap_or_default();
+        time += slew + target.duration;
+        last = target.positions.last().copied();
+        order.push(k);
+    }
+    order.extend(unknown);
+    order
+}
+
+/// Order in which to measure the runs of a session, using the positions and integration times
+/// of `conditions`.
+pub fn order_runs(runs: &[&PepRun], conditions: &Conditions, site: &Site, start: DateTime<Utc>, min_altitude: f64) -> Vec<usize> {
+    let targets = runs.iter()
+        .map(|run| Target {
+            positions: positions(run, conditions),
+            duration: estimate(run, conditions).total(),
+        })
+        .collect::<Vec<_>>();
+    order(&targets, site, start, min_altitude)
+}
+
+/// The run with its stars reordered. Consecutive items of the same star are repeats and stay
+/// together. Only meaningful for transform runs, other runs depend on the order of their
+/// comparison and program stars.
+pub fn order_stars(run: &PepRun, conditions: &Conditions, site: &Site, start: DateTime<Utc>, min_altitude: f64) -> PepRun {
+    let mut blocks: Vec<PepRun> = Vec::new();
+    for star in run.items.iter() {
+        match blocks.last_mut() {
+            Some(block) if block.items[0].name == star.name => { block.items.push(star.clone()); }
+            _ => {
+                let mut block = PepRun::new(run.filters.clone(), vec![star.clone()]);
+                block.sky_strategy = run.sky_strategy;
+                blocks.push(block);
+            }
+        }
+    }
+    let targets = blocks.iter()
+        .map(|block| {
+            // Dark counts belong to the whole run
+            let estimate = estimate(block, conditions);
+            Target { positions: positions(block, conditions), duration: estimate.measuring + estimate.pointing }
+        })
+        .collect::<Vec<_>>();
+    let mut ordered = run.clone();
+    ordered.items = order(&targets, site, start, min_altitude).into_iter()
+        .flat_map(|k| blocks[k].items.clone())
+        .collect();
+    ordered
+}
+
+fn positions(run: &PepRun, conditions: &Conditions) -> Vec<Equatorial> {
+    match conditions.catalog {
+        Some(catalog) => { run.items.iter().filter_map(|star| catalog.position(&star.name)).collect() }
+        None => { Vec::new() }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::astro::local_sidereal_time;
+    use crate::catalog::Catalog;
+    use crate::common::StarData;
+    use crate::measurement::IntegrationTimes;
+    use std::{env, fs};
+
+    const FRANKFURT: Site = Site { latitude: 50.1, longitude: 8.7 };
+
+    fn start() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-12-21T20:00:00Z").unwrap().to_utc()
+    }
+
+    /// Position `hours` east of the meridian at the start.
+    fn east_of_meridian(hours: f64) -> Equatorial {
+        Equatorial { ra_hours: (local_sidereal_time(start(), &FRANKFURT) + hours).rem_euclid(24.0), dec_degrees: 20.0 }
+    }
+
+    /// Catalog of a star setting in the west and one rising in the east.
+    fn catalog() -> Catalog {
+        let path = env::temp_dir().join(format!("peprunner-scheduler-{}.csv", std::process::id()));
+        let mut csv = "Name,U,B,V,R,I,RA,Dec\n".to_string();
+        for (name, hours) in [("West", -3.0), ("East", 3.0)] {
+            let position = east_of_meridian(hours);
+            csv.push_str(&format!("{},,,,,,{},{}\n", name, position.ra_hours, position.dec_degrees));
+        }
+        fs::write(&path, csv).unwrap();
+        let catalog = Catalog::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+        catalog
+    }
+
+    fn run(names: &[&str]) -> PepRun {
+        let items = names.iter().map(|name| StarData::new(&"PGM".to_string(), &name.to_string())).collect();
+        PepRun::new(vec![2], items)
+    }
+
+    #[test]
+    fn measures_setting_targets_first() {
+        let target = |hours| Target { positions: vec![east_of_meridian(hours)], duration: TimeDelta::hours(1) };
+        let unknown = || Target { positions: Vec::new(), duration: TimeDelta::hours(1) };
+        let targets = [unknown(), target(3.0), target(-3.0), unknown()];
+        assert_eq!(order(&targets, &FRANKFURT, start(), 30.0), [2, 1, 0, 3]);
+        assert!(order(&[], &FRANKFURT, start(), 30.0).is_empty());
+    }
+
+    #[test]
+    fn avoids_targets_below_limit() {
+        // Far in the east the target is still below the limit
+        let targets = [Target { positions: vec![east_of_meridian(7.0)], duration: TimeDelta::hours(2) },
+            Target { positions: vec![east_of_meridian(1.0)], duration: TimeDelta::hours(2) }];
+        assert_eq!(order(&targets, &FRANKFURT, start(), 30.0), [1, 0]);
+    }
+
+    #[test]
+    fn orders_runs_and_stars() {
+        let catalog = catalog();
+        let i_times = IntegrationTimes::new();
+        let conditions = Conditions { i_times: &i_times, catalog: Some(&catalog), mount: true, dark_schedule: None };
+        let runs = [run(&["East"]), run(&["Unknown"]), run(&["West", "West"])];
+        let order = order_runs(&runs.iter().collect::<Vec<_>>(), &conditions, &FRANKFURT, start(), 30.0);
+        assert_eq!(order, [2, 0, 1]);
+
+        let ordered = order_stars(&run(&["East", "West", "West"]), &conditions, &FRANKFURT, start(), 30.0);
+        let names = ordered.items.iter().map(|star| star.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["West", "West", "East"]);
+        assert_eq!(ordered.filters, [2]);
+    }
+}
@@ -6,19 +6,34 @@ use serial::prelude::*;
 use serial::SystemPort;
 use std::ffi::OsStr;
 use std::io::{Read, Write};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use std::{fmt, io, str};
 
+/// Granularity for checking whether a running count should be cancelled.
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Length of a count response, i.e. `C=nnnnn` followed by CR/LF.
+const COUNT_RESPONSE_LEN: usize = 9;
+
+/// How long the response of an abandoned count may arrive after its expected end.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Acknowledgement of a command.
+const ACK: [u8; 3] = [0x21, 0x0A, 0x0D];
+
 pub struct Ssp3 {
     port: Box<SystemPort>,
     filter: u8,
     time: u16,
+    // Deadline and number of outstanding bytes of a count that timed out
+    pending_count: Option<(Instant, usize)>,
 }
 
 #[derive(Debug)]
 pub enum ErrorKind {
     Serial(serial::Error),
     Protocol(String),
+    Cancelled,
 }
 
 impl From<serial::Error> for ErrorKind {
@@ -32,6 +47,7 @@ impl fmt::Display for ErrorKind {
         match self {
             ErrorKind::Serial(e) => format!("serial error: {}", e).fmt(f),
             Protocol(e) => format!("protocol error: {}", e).fmt(f),
+            ErrorKind::Cancelled => "count cancelled".fmt(f),
         }
     }
 }
@@ -47,6 +63,40 @@ impl fmt::Display for Error {
     }
 }
 
+impl Error {
+    /// No port is open, e.g. because re-opening it after a disconnect failed.
+    pub fn not_connected() -> Self {
+        Error { kind: ErrorKind::Serial(serial::Error::new(serial::ErrorKind::NoDevice, "port not open")) }
+    }
+
+    /// The SSP3 did not answer within the expected time.
+    pub fn is_timeout(&self) -> bool {
+        matches!(&self.kind, ErrorKind::Serial(e) if e.kind() == serial::ErrorKind::Io(io::ErrorKind::TimedOut))
+    }
+
+    /// The serial device has gone away, e.g. because the USB adapter was unplugged.
+    pub fn is_disconnect(&self) -> bool {
+        match &self.kind {
+            ErrorKind::Serial(e) => {
+                match e.kind() {
+                    serial::ErrorKind::NoDevice => true,
+                    serial::ErrorKind::Io(kind) => {
+                        matches!(kind, io::ErrorKind::BrokenPipe | io::ErrorKind::UnexpectedEof
+                            | io::ErrorKind::NotFound | io::ErrorKind::NotConnected)
+                    }
+                    serial::ErrorKind::InvalidInput => false,
+                }
+            }
+            Protocol(_) | ErrorKind::Cancelled => false,
+        }
+    }
+
+    /// The count was cancelled on request of the operator.
+    pub fn is_cancelled(&self) -> bool {
+        matches!(self.kind, ErrorKind::Cancelled)
+    }
+}
+
 impl From<serial::Error> for Error {
     fn from(e: serial::Error) -> Self {
         Error { kind: ErrorKind::Serial(e) }
@@ -70,7 +120,7 @@ impl Ssp3 {
             Ok(())
         }) {
             Ok(_) => {
-                Ok(Ssp3 { port: Box::new(port), filter: 0, time: 0 })
+                Ok(Ssp3 { port: Box::new(port), filter: 0, time: 0, pending_count: None })
             }
             Err(err) => {
                 return Err(Error::from(err));
@@ -85,7 +135,9 @@ impl Ssp3 {
         self.write_with_ack("SGAIN2", 2)
     }
 
-    pub fn measure(&mut self, filter: u8, time: u16) -> Result<u16, Error> {
+    /// Takes a single count. `cancel` is polled while waiting for the count and stops the count
+    /// if it returns `true`.
+    pub fn measure(&mut self, filter: u8, time: u16, mut cancel: impl FnMut() -> bool) -> Result<u16, Error> {
         if filter == 0 {
             return Err(Error { kind: Protocol("SSP3 not initialized".to_string())})
         }
@@ -107,11 +159,37 @@ impl Ssp3 {
         }
 
         self.write("SCOUNT")?;
+        let deadline = Instant::now() + Duration::from_secs((time / 100 + 2) as u64);
+        self.pending_count = Some((deadline, COUNT_RESPONSE_LEN));
         let ioport = self.port.as_mut();
-        SerialPort::set_timeout(ioport, Duration::from_secs((time / 100 + 2) as u64))?;
+        SerialPort::set_timeout(ioport, POLL_INTERVAL)?;
 
-        let mut buffer = [0u8; 9];
-        ioport.read_exact(&mut buffer)?;
+        let mut buffer = [0u8; COUNT_RESPONSE_LEN];
+        let mut pos = 0;
+        while pos < buffer.len() {
+            if cancel() {
+                self.reset()?;
+                return Err(Error { kind: ErrorKind::Cancelled });
+            }
+            match ioport.read(&mut buffer[pos..]) {
+                Ok(0) => {
+                    return Err(Error::from(io::Error::from(io::ErrorKind::UnexpectedEof)));
+                }
+                Ok(bytes_read) => {
+                    pos += bytes_read;
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    if Instant::now() >= deadline {
+                        self.pending_count = Some((deadline, buffer.len() - pos));
+                        return Err(Error::from(e));
+                    }
+                }
+                Err(e) => {
+                    return Err(Error::from(e));
+                }
+            }
+        }
+        self.pending_count = None;
 
         let response = str::from_utf8(&buffer).map_err(|e| Error { kind: ErrorKind::Protocol(e.to_string()) } )?;
         if !response.starts_with("C=") {
@@ -146,14 +224,72 @@ impl Ssp3 {
         let mut buffer = [0u8; 3];
         ioport.read_exact(&mut buffer)?;
 
-        if buffer[0] == 0x21 && buffer[1] == 0x0A && buffer[2] == 0x0D {
+        if buffer == ACK {
             return Ok(());
         }
 
         Err(Error { kind: ErrorKind::Protocol(format!("Received {:?} in response to {}", buffer, output)) })
     }
 
+    /// Stops a running count by leaving serial mode and entering it again, so that the next
+    /// command doesn't have to wait for the end of the integration.
+    fn reset(&mut self) -> Result<(), Error> {
+        self.pending_count = None;
+        self.send("SEND..")?;
+        // The response of the count may still come before the acknowledgement
+        let ioport = self.port.as_mut();
+        SerialPort::set_timeout(ioport, DRAIN_TIMEOUT)?;
+        let mut received = Vec::new();
+        let mut byte = [0u8; 1];
+        while !received.ends_with(&ACK) {
+            if received.len() == COUNT_RESPONSE_LEN + ACK.len() {
+                return Err(Error { kind: ErrorKind::Protocol(format!("Received {:?} in response to SEND..", received)) });
+            }
+            ioport.read_exact(&mut byte)?;
+            received.push(byte[0]);
+        }
+        self.time = 0;
+        self.init()
+    }
+
+    /// Swallows the response of a cancelled or timed-out count, so that it can't be mistaken
+    /// for the acknowledgement of the next command or the next count.
+    fn drain_pending_count(&mut self) -> Result<(), Error> {
+        let Some((deadline, mut remaining)) = self.pending_count.take() else {
+            return Ok(());
+        };
+        // The deadline of a timed-out count has already passed, but its response may be late
+        let deadline = deadline.max(Instant::now()) + DRAIN_TIMEOUT;
+        let ioport = self.port.as_mut();
+        SerialPort::set_timeout(ioport, POLL_INTERVAL)?;
+        let mut buffer = [0u8; COUNT_RESPONSE_LEN];
+        while remaining > 0 {
+            match ioport.read(&mut buffer[..remaining]) {
+                Ok(0) => {
+                    break;
+                }
+                Ok(bytes_read) => {
+                    remaining -= bytes_read;
+                }
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => {
+                    if Instant::now() >= deadline {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    return Err(Error::from(e));
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn write(&mut self, output: &str) -> Result<(), Error> {
+        self.drain_pending_count()?;
+        self.send(output)
+    }
+
+    fn send(&mut self, output: &str) -> Result<(), Error> {
         let ioport = self.port.as_mut();
         let mut pos = 0;
         let out_buffer = output.as_bytes();
@@ -164,4 +300,24 @@ impl Ssp3 {
         ioport.flush()?;
         Ok(())
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn io_error(kind: io::ErrorKind) -> Error {
+        Error::from(io::Error::from(kind))
+    }
+
+    #[test]
+    fn classifies_errors() {
+        assert!(io_error(io::ErrorKind::TimedOut).is_timeout());
+        assert!(!io_error(io::ErrorKind::TimedOut).is_disconnect());
+        assert!(io_error(io::ErrorKind::BrokenPipe).is_disconnect());
+        assert!(io_error(io::ErrorKind::UnexpectedEof).is_disconnect());
+        assert!(Error::not_connected().is_disconnect());
+        assert!(!io_error(io::ErrorKind::Other).is_disconnect());
+        assert!(!Error { kind: Protocol("invalid response".to_string()) }.is_disconnect());
+    }
+}
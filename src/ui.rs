@@ -1,22 +1,47 @@
 mod imp;
 mod config;
 mod generate_run;
-
-use crate::common::{PepRun, StarData};
-use crate::{util, APP_ID};
+mod archive;
+mod light_curve;
+mod log_view;
+mod period;
+mod plot;
+mod planner;
+mod integration_times;
+
+use crate::archive::Archive;
+use crate::astro::{Offset, Site};
+use crate::catalog::Catalog;
+use crate::common::{PepRun, SkyPosition, SkyStrategy, StarData, MAX_SKY_OFFSET};
+use crate::{estimate, peppy, qc, reduction, rejection, run_file, scheduler, util, APP_ID};
 use glib::{clone, Object};
 use gtk::gdk::Key;
 use gtk::gio::{ActionEntry, Cancellable, File, FileCreateFlags, FileQueryInfoFlags, Settings, FILE_ATTRIBUTE_STANDARD_SIZE};
 use gtk::glib::Propagation;
-use gtk::prelude::{ActionMapExtManual, ButtonExt, Cast, CastNone, CheckButtonExt, DialogExt, EntryBufferExtManual, EntryExt, FileChooserExt, FileChooserExtManual, FileExt, GtkWindowExt, InputStreamExtManual, ListItemExt, ListModelExt, NativeDialogExt, ObjectExt, OutputStreamExt, SettingsExt, SettingsExtManual, WidgetExt};
+use gtk::prelude::{ActionMapExtManual, SelectionModelExt, StaticType, ToValue, ListModelExtManual, ButtonExt, Cast, CastNone, CheckButtonExt, DialogExt, EntryBufferExtManual, EntryExt, FileChooserExt, FileChooserExtManual, FileExt, GtkWindowExt, InputStreamExtManual, ListItemExt, ListModelExt, NativeDialogExt, ObjectExt, OutputStreamExt, SettingsExt, SettingsExtManual, WidgetExt};
 use gtk::subclass::prelude::ObjectSubclassIsExt;
-use gtk::{gio, glib, Application, EventControllerKey, FileChooserAction, FileChooserNative, ListItem, ResponseType, SignalListItemFactory, SingleSelection, StringList, StringObject, INVALID_LIST_POSITION};
+use gtk::gdk::{ContentProvider, DragAction};
+use gtk::{gio, glib, Application, DragSource, DropTarget, EventControllerKey, MultiSelection, FileChooserAction, FileChooserNative, Label, ListItem, NamedAction, ResponseType, Shortcut, ShortcutController, ShortcutTrigger, SignalListItemFactory, SingleSelection, StringList, StringObject, INVALID_LIST_POSITION};
+use chrono::{DateTime, TimeDelta, Utc};
+use std::collections::BTreeMap;
 use std::env::var;
+use std::fs;
 use std::path::Path;
-use crate::measurement::execute_run;
+use crate::estimate::{format_duration, Conditions, Estimate};
+use crate::measurement::{execute_run, execute_session, filter_name, DarkSchedule, IntegrationTimes, QueuedRun, RunOptions};
+use crate::mount::MountSettings;
+use crate::ui::archive::ArchiveWindow;
 use crate::ui::config::ConfigDialog;
+use crate::ui::light_curve::LightCurveWindow;
+use crate::ui::log_view::LogWindow;
+use crate::ui::period::PeriodWindow;
+use crate::ui::planner::PlannerWindow;
 use crate::ui::generate_run::GenerateRunDialog;
-use crate::util::show_error;
+use crate::ui::integration_times::IntegrationTimesDialog;
+use crate::util::{show_error, show_warning};
+
+/// Number of edits that can be undone
+const MAX_UNDO: usize = 100;
 
 glib::wrapper! {
     pub struct MainWindow(ObjectSubclass<imp::MainWindow>)
@@ -42,9 +67,7 @@ impl MainWindow {
         let model = gio::ListStore::new::<StarObject>();
         self.imp().stars.replace(Some(model));
 
-        let selection_model = SingleSelection::new(Some(self.stars()));
-        selection_model.set_autoselect(false);
-        selection_model.set_can_unselect(true);
+        let selection_model = MultiSelection::new(Some(self.stars()));
         self.imp().star_list_vw.set_model(Some(&selection_model));
     }
 
@@ -60,6 +83,31 @@ impl MainWindow {
         self.imp().filter_v.set_active(settings.get("filter-v"));
         self.imp().filter_r.set_active(settings.get("filter-r"));
         self.imp().filter_i.set_active(settings.get("filter-i"));
+        self.imp().filter_c.set_active(settings.get("filter-c"));
+        self.load_i_times();
+        settings.connect_changed(Some("instrument"), clone!(
+            #[weak(rename_to = main_window)]
+            self,
+            move |_, _| {
+                main_window.load_i_times()
+            }
+        ));
+        self.load_catalog();
+        settings.connect_changed(Some("catalog-file"), clone!(
+            #[weak(rename_to = main_window)]
+            self,
+            move |_, _| {
+                main_window.load_catalog()
+            }
+        ));
+        // The estimate depends on the mount, the catalog and the dark counts
+        settings.connect_changed(None, clone!(
+            #[weak(rename_to = main_window)]
+            self,
+            move |_, _| {
+                main_window.update_estimate()
+            }
+        ));
     }
 
     fn settings(&self) -> &Settings {
@@ -71,7 +119,8 @@ impl MainWindow {
 
     fn new_star(&self) {
         if let Some(star_data) = self.extract_star() {
-            let star = StarObject::new(star_data.star_type, star_data.name);
+            self.record_undo();
+            let star = StarObject::from_data(star_data);
             self.stars().append(&star);
         }
     }
@@ -90,13 +139,36 @@ impl MainWindow {
         if star_name.is_empty() {
             return None;
         }
+        let sky_buffer = self.imp().star_sky_entry.buffer();
+        let sky_text = sky_buffer.text().to_string();
+        let sky = if sky_text.trim().is_empty() {
+            None
+        } else {
+            match SkyPosition::parse(&sky_text) {
+                Some(SkyPosition::Offset { arcmin, .. }) if arcmin > MAX_SKY_OFFSET => {
+                    show_error(Some(self), Some("Invalid Sky Position"),
+                               format!("The sky offset of {}' is too large, allowed are up to {}'.", arcmin, MAX_SKY_OFFSET));
+                    return None;
+                }
+                Some(sky) => { Some(sky) }
+                None => {
+                    show_error(Some(self), Some("Invalid Sky Position"),
+                               format!("'{}' is neither an offset like 5' N nor coordinates like 12:30:00 +45:10:00.", sky_text.trim()));
+                    return None;
+                }
+            }
+        };
         name_buffer.set_text("");
+        sky_buffer.set_text("");
 
-        Some(StarData {star_type, name: star_name })
+        Some(StarData {star_type, name: star_name, sky, i_times: BTreeMap::new() })
     }
 
-    fn update_star(&self, pos: u32) {
+    /// Replaces the star at `pos` with the one from the entries. False if they don't hold a
+    /// valid star.
+    fn update_star(&self, pos: u32) -> bool {
         if let Some(star_data) = self.extract_star() {
+            self.record_undo();
             let star_object = self.imp().star_list_vw
                 .model()
                 .unwrap()
@@ -106,12 +178,19 @@ impl MainWindow {
 
             star_object.set_property("star-type", &star_data.star_type);
             star_object.set_property("name", &star_data.name);
+            star_object.set_sky_position(star_data.sky);
+            self.show_i_times(&star_object);
+            self.update_estimate();
+            true
+        } else {
+            false
         }
     }
 
     fn select_star(&self, star: &StarData) {
         self.imp().star_name_entry.buffer().set_text(&star.name);
         self.imp().star_name_entry.set_placeholder_text(None);
+        self.imp().star_sky_entry.buffer().set_text(star.sky.map(|sky| sky.to_string()).unwrap_or_default());
         let type_list = self.imp().star_type_dd
             .model()
             .unwrap()
@@ -128,8 +207,14 @@ impl MainWindow {
     }
 
     fn handle_escape(&self) {
-        if let Some(_) = self.imp().editing.replace(None) {
+        self.stop_editing();
+    }
+
+    /// Forgets the star being edited, whose position is about to change.
+    fn stop_editing(&self) {
+        if self.imp().editing.replace(None).is_some() {
             self.imp().star_name_entry.buffer().set_text("");
+            self.imp().star_sky_entry.buffer().set_text("");
         }
     }
 
@@ -137,22 +222,159 @@ impl MainWindow {
         if *key != Key::Delete && *key != Key::BackSpace {
             return Propagation::Proceed
         }
-        let selection = self.imp().star_list_vw
+        if self.selected_stars().is_empty() {
+            return Propagation::Proceed
+        }
+        self.delete_selected();
+        Propagation::Stop
+    }
+
+    fn star_selection(&self) -> MultiSelection {
+        self.imp().star_list_vw
             .model()
             .unwrap()
-            .downcast::<SingleSelection>()
-            .expect("model should be a SingleSelection");
-        let selected_idx = selection.selected();
-        if selected_idx != INVALID_LIST_POSITION {
-            selection.set_selected(INVALID_LIST_POSITION);
-            self.stars().remove(selected_idx);
-            Propagation::Stop
-        } else {
-            Propagation::Proceed
+            .downcast::<MultiSelection>()
+            .expect("model should be a MultiSelection")
+    }
+
+    /// Positions of the selected stars in ascending order.
+    fn selected_stars(&self) -> Vec<u32> {
+        let selection = self.star_selection();
+        (0..selection.n_items())
+            .filter(|pos| selection.is_selected(*pos))
+            .collect()
+    }
+
+    fn star_at(&self, pos: u32) -> StarObject {
+        self.stars()
+            .item(pos)
+            .and_downcast::<StarObject>()
+            .expect("item should be a StarObject")
+    }
+
+    fn delete_selected(&self) {
+        let selected = self.selected_stars();
+        if selected.is_empty() {
+            return;
+        }
+        self.stop_editing();
+        self.record_undo();
+        self.star_selection().unselect_all();
+        for pos in selected.into_iter().rev() {
+            self.stars().remove(pos);
+        }
+    }
+
+    /// Inserts copies of the selected stars after the last selected one.
+    fn duplicate_selected(&self) {
+        let selected = self.selected_stars();
+        let Some(last) = selected.last() else {
+            return;
+        };
+        self.stop_editing();
+        self.record_undo();
+        let copies = selected.iter()
+            .map(|pos| {
+                let data = self.star_at(*pos).imp().data.borrow().clone();
+                StarObject::from_data(data)
+            })
+            .collect::<Vec<_>>();
+        self.stars().splice(last + 1, 0, &copies);
+    }
+
+    /// Gives the selected stars the type chosen in the type drop down.
+    fn change_type_of_selected(&self) {
+        let selected = self.selected_stars();
+        if selected.is_empty() {
+            return;
+        }
+        let star_type = self.imp()
+            .star_type_dd
+            .selected_item()
+            .and_downcast::<StringObject>()
+            .expect("selected should be a StringObject")
+            .string()
+            .to_string();
+        self.record_undo();
+        for pos in selected {
+            self.star_at(pos).set_property("star-type", &star_type);
+        }
+        // Pairs of CMP and PGM may share a sky
+        self.update_estimate();
+    }
+
+    /// Inserts the star from the entry before the first or after the last selected star.
+    fn insert_star(&self, after: bool) {
+        let selected = self.selected_stars();
+        let pos = match (after, selected.first(), selected.last()) {
+            (false, Some(first), _) => { *first }
+            (true, _, Some(last)) => { last + 1 }
+            (false, None, _) => { 0 }
+            (true, _, None) => { self.stars().n_items() }
+        };
+        if let Some(star_data) = self.extract_star() {
+            // The entries held the new star, not changes to the edited one
+            self.imp().editing.replace(None);
+            self.record_undo();
+            self.stars().insert(pos, &StarObject::from_data(star_data));
+        }
+    }
+
+    /// Moves a star to the position of another one.
+    fn move_star(&self, from: u32, to: u32) {
+        if from == to || from >= self.stars().n_items() || to >= self.stars().n_items() {
+            return;
+        }
+        self.stop_editing();
+        self.record_undo();
+        let star = self.star_at(from);
+        self.star_selection().unselect_all();
+        self.stars().remove(from);
+        self.stars().insert(to, &star);
+    }
+
+    /// Orders the stars of a transform run by their position on the sky.
+    fn handle_order_stars(&self) {
+        let run = self.snapshot();
+        if run.items.is_empty() {
+            return;
+        }
+        if run.items.iter().any(|star| star.star_type != "EXT") {
+            show_error(Some(self), Some("Not a Transform Run"),
+                       "Only runs of EXT stars can be ordered, other runs keep their program stars bracketed by comparison stars.");
+            return;
+        }
+        let Some(catalog) = self.catalog() else {
+            show_error(Some(self), Some("No Catalog"), "Please configure a catalog with the coordinates of the stars.");
+            return;
+        };
+        let dark_schedule = self.dark_schedule();
+        let conditions = Conditions {
+            i_times: &self.imp().i_times.borrow(),
+            catalog: Some(&catalog),
+            mount: self.mount_settings().is_some(),
+            dark_schedule: dark_schedule.as_ref(),
+        };
+        let ordered = scheduler::order_stars(&run, &conditions, &self.site(), Utc::now(), self.settings().double("min-altitude"));
+        let mut unknown = run.items.iter()
+            .filter(|star| catalog.position(&star.name).is_none())
+            .map(|star| star.name.as_str())
+            .collect::<Vec<_>>();
+        unknown.dedup();
+        self.replace_run(ordered);
+        if !unknown.is_empty() {
+            show_warning(Some(self), Some("Unknown Positions"),
+                         format!("No coordinates in the catalog for {}, these stars come last.", unknown.join(", ")));
         }
     }
 
+    /// Star list changes are blocked while a run executes.
+    fn can_edit(&self) -> bool {
+        self.imp().execute_button.get_sensitive()
+    }
+
     fn handle_new_action(&self) {
+        self.record_undo();
         self.stars().remove_all();
         self.imp().current_file.replace(None);
     }
@@ -178,9 +400,16 @@ impl MainWindow {
                 // File is None, if user hits escape
                 if let Some(file) = dlg.file() {
                     if let Some(run) = main_window.extract_run() {
+                        let data_str = match run_file::to_string(&run) {
+                            Ok(data_str) => { data_str }
+                            Err(e) => {
+                                util::show_error(Some(&main_window), Some("Cannot Save Run"), e);
+                                main_window.imp().file_dialog.replace(None);
+                                return;
+                            }
+                        };
                         match file.replace(None, false, FileCreateFlags::NONE, None::<&Cancellable>) {
                             Ok(stream) => {
-                                let data_str = serde_json::to_string(&run).unwrap();
                                 stream.write(data_str.as_bytes(), None::<&Cancellable>).expect("expected write to succeed");
                                 stream.close(None::<&Cancellable>).expect("expected close to succeed");
                                 let file_dir = file.path().unwrap().parent().unwrap().to_str().unwrap().to_string();
@@ -202,9 +431,10 @@ impl MainWindow {
                                         4 => {
                                             main_window.settings().set("filter-i", true).expect("expected setting filter to succeed");
                                         }
-                                        _ => {
-                                            panic!("invalid filter index")
+                                        5 => {
+                                            main_window.settings().set("filter-c", true).expect("expected setting filter to succeed");
                                         }
+                                        _ => {}
                                     }
                                 })
                             }
@@ -235,9 +465,10 @@ impl MainWindow {
         let main_window = self.clone();
         dialog.connect_response(move |dlg: &GenerateRunDialog, response| {
            if response == ResponseType::Ok {
-               if let Some(run) = dlg.get_run() {
+               if let Some(mut run) = dlg.get_run() {
                    dlg.hide();
                    dlg.destroy();
+                   run.sky_strategy = main_window.sky_strategy();
                    main_window.replace_run(run)
                }
            } else {
@@ -267,6 +498,9 @@ impl MainWindow {
         if mw_imp.filter_i.is_active() {
             filters.push(4);
         }
+        if mw_imp.filter_c.is_active() {
+            filters.push(5);
+        }
         if filters.is_empty() {
             return None;
         }
@@ -279,13 +513,20 @@ impl MainWindow {
                     .expect("object should be a StarObject")
                     .imp()
                     .data.borrow();
-                StarData::new(&star.star_type, &star.name)
+                star.clone()
             })
             .collect::<Vec<_>>();
         if stars.is_empty() {
             return None;
         }
-        Some(PepRun::new(filters, stars))
+        let mut run = PepRun::new(filters, stars);
+        run.sky_strategy = self.sky_strategy();
+        Some(run)
+    }
+
+    fn sky_strategy(&self) -> SkyStrategy {
+        let selected = self.imp().sky_strategy_dd.selected() as usize;
+        SkyStrategy::ALL.get(selected).copied().unwrap_or_default()
     }
 
     fn handle_open_action(&self) {
@@ -303,52 +544,302 @@ impl MainWindow {
         dialog.connect_response(move |dlg: &FileChooserNative, response| {
             if response != ResponseType::Cancel {
                 let file = dlg.file().unwrap();
+                if let Some(run) = main_window.read_run(&file) {
+                    main_window.replace_run(run);
+                    main_window.imp().current_file.replace(file.path());
+                    let file_dir = file.path().unwrap().parent().unwrap().to_str().unwrap().to_string();
+                    main_window.settings().set("last-dir", file_dir).expect("expected setting last dir to succeed");
+                }
+            }
+            main_window.imp().file_dialog.replace(None);
+        }
+        );
+        dialog.show();
+        self.imp().file_dialog.replace(Some(dialog));
+    }
 
-                match file.query_info(FILE_ATTRIBUTE_STANDARD_SIZE, FileQueryInfoFlags::NONE, None::<&Cancellable>) {
-                    Ok(info) => {
-                        let size= info.size() as usize;
-                        match file.read(None::<&Cancellable>) {
-                            Ok(stream) => {
-                                let mut buf_vec = vec![0u8; size];
-                                stream.read(buf_vec.as_mut_slice(), None::<&Cancellable>).expect("expected read to succeed");
-                                match String::from_utf8(buf_vec) {
-                                    Ok(str) => {
-                                        let serde_result : Result<PepRun, serde_json::Error> = serde_json::from_str(&str);
-                                        match serde_result {
-                                            Ok(run) => {
-                                                main_window.replace_run(run);
-                                                main_window.imp().current_file.replace(file.path());
-                                                let file_dir = file.path().unwrap().parent().unwrap().to_str().unwrap().to_string();
-                                                main_window.settings().set("last-dir", file_dir).expect("expected setting last dir to succeed");
-                                            }
-                                            Err(e) => {
-                                                show_error(Some(&main_window), Some("No PEP Run in File"), e);
-                                            }
-                                        }
+    fn handle_import_peppy_action(&self) {
+        if self.imp().file_dialog.borrow().is_some() {
+            return;
+        }
+        let dialog = FileChooserNative::new(Some("Import PepPy Run"),
+                                            Some(self),
+                                            FileChooserAction::Open,
+                                            None, None);
+        dialog.set_current_folder(Some(&self.get_last_dir())).expect("expected setting folder to succeed");
+
+        let main_window = self.clone();
+        dialog.connect_response(move |dlg: &FileChooserNative, response| {
+            main_window.imp().file_dialog.replace(None);
+            if response == ResponseType::Cancel {
+                return;
+            }
+            let Some(path) = dlg.file().and_then(|file| file.path()) else {
+                return;
+            };
+            let result = fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|text| peppy::run_from_str(&text).map_err(|e| e.to_string()));
+            match result {
+                Ok(run) => {
+                    main_window.replace_run(run);
+                    // Saving writes our own format, so don't overwrite the PepPy file
+                    main_window.imp().current_file.replace(None);
+                }
+                Err(e) => {
+                    show_error(Some(&main_window), Some("Error Importing PepPy Run"), e);
+                }
+            }
+        });
+        dialog.show();
+        self.imp().file_dialog.replace(Some(dialog));
+    }
+
+    fn handle_export_peppy_action(&self) {
+        if self.imp().file_dialog.borrow().is_some() {
+            return;
+        }
+        let Some(run) = self.extract_run() else {
+            show_error(Some(self), Some("No Run"), "Please enter or load a run.");
+            return;
+        };
+        let dialog = FileChooserNative::new(Some("Export PepPy Run"), Some(self), FileChooserAction::Save, None, None);
+        dialog.set_current_folder(Some(&self.get_last_dir())).expect("expected setting folder to succeed");
+
+        let main_window = self.clone();
+        dialog.connect_response(move |dlg: &FileChooserNative, response| {
+            main_window.imp().file_dialog.replace(None);
+            if response == ResponseType::Cancel {
+                return;
+            }
+            let Some(path) = dlg.file().and_then(|file| file.path()) else {
+                return;
+            };
+            let (data_str, warnings) = match peppy::run_to_string(&run) {
+                Ok(result) => { result }
+                Err(e) => {
+                    show_error(Some(&main_window), Some("Error Exporting PepPy Run"), e);
+                    return;
+                }
+            };
+            if let Err(e) = fs::write(&path, data_str) {
+                show_error(Some(&main_window), Some("Error Exporting PepPy Run"), e);
+            } else if !warnings.is_empty() {
+                util::show_warning(Some(&main_window), Some("PepPy Run Exported"), warnings.join("\n"));
+            }
+        });
+        dialog.show();
+        self.imp().file_dialog.replace(Some(dialog));
+    }
+
+    /// Reads a run from file, showing an error if that fails.
+    fn read_run(&self, file: &File) -> Option<PepRun> {
+        match file.query_info(FILE_ATTRIBUTE_STANDARD_SIZE, FileQueryInfoFlags::NONE, None::<&Cancellable>) {
+            Ok(info) => {
+                let size= info.size() as usize;
+                match file.read(None::<&Cancellable>) {
+                    Ok(stream) => {
+                        let mut buf_vec = vec![0u8; size];
+                        stream.read(buf_vec.as_mut_slice(), None::<&Cancellable>).expect("expected read to succeed");
+                        match String::from_utf8(buf_vec) {
+                            Ok(str) => {
+                                match run_file::from_str(&str) {
+                                    Ok(run) => {
+                                        return Some(run);
                                     }
                                     Err(e) => {
-                                        show_error(Some(&main_window), Some("Error Converting File to String"), e);
+                                        let name = file.basename().map(|n| n.display().to_string()).unwrap_or_default();
+                                        show_error(Some(self), Some(&format!("Cannot Load {}", name)), e);
                                     }
                                 }
-
                             }
                             Err(e) => {
-                                show_error(Some(&main_window), Some("Error Reading Run"), e);
+                                show_error(Some(self), Some("Error Converting File to String"), e);
                             }
                         }
+
                     }
                     Err(e) => {
-                       show_error(Some(&main_window), Some("Error Getting Run Size"), e);
+                        show_error(Some(self), Some("Error Reading Run"), e);
                     }
                 }
             }
+            Err(e) => {
+               show_error(Some(self), Some("Error Getting Run Size"), e);
+            }
+        }
+        None
+    }
+
+    fn handle_view_log_action(&self) {
+        if self.imp().file_dialog.borrow().is_some() {
+            return;
+        }
+        let dialog = FileChooserNative::new(Some("View Run Log"),
+                                            Some(self),
+                                            FileChooserAction::Open,
+                                            None, None);
+        dialog.set_current_folder(Some(&self.get_last_dir())).expect("expected setting folder to succeed");
+
+        let main_window = self.clone();
+        dialog.connect_response(move |dlg: &FileChooserNative, response| {
             main_window.imp().file_dialog.replace(None);
+            if response == ResponseType::Cancel {
+                return;
+            }
+            if let Some(path) = dlg.file().and_then(|file| file.path()) {
+                if let Some(log_window) = LogWindow::new(&main_window.application().unwrap(), &main_window, &path) {
+                    log_window.show();
+                }
+            }
+        });
+        dialog.show();
+        self.imp().file_dialog.replace(Some(dialog));
+    }
+
+    fn handle_export_peppy_data_action(&self) {
+        if self.imp().file_dialog.borrow().is_some() {
+            return;
         }
-        );
+        let dialog = FileChooserNative::new(Some("Export Run Log for PepPy"),
+                                            Some(self),
+                                            FileChooserAction::Open,
+                                            None, None);
+        dialog.set_current_folder(Some(&self.get_last_dir())).expect("expected setting folder to succeed");
+
+        let main_window = self.clone();
+        dialog.connect_response(move |dlg: &FileChooserNative, response| {
+            if response != ResponseType::Cancel {
+                if let Some(path) = dlg.file().and_then(|file| file.path()) {
+                    main_window.confirm_check_stars(&path, MainWindow::write_peppy_data);
+                }
+            }
+            main_window.imp().file_dialog.replace(None);
+        });
+        dialog.show();
+        self.imp().file_dialog.replace(Some(dialog));
+    }
+
+    /// Writes a run log without its rejected rows as PepPy data file next to it.
+    fn write_peppy_data(&self, path: &Path) {
+        let records = match reduction::read_log(path) {
+            Ok(records) => { records }
+            Err(e) => {
+                show_error(Some(self), Some("Error Reading Run Log"), e);
+                return;
+            }
+        };
+        let records = match rejection::load(path) {
+            Ok(rejections) => { rejection::apply(records, &rejections) }
+            Err(e) => {
+                show_error(Some(self), Some("Error Reading Rejections"), e);
+                return;
+            }
+        };
+        let (data_str, warnings) = match peppy::log_to_string(&records) {
+            Ok(result) => { result }
+            Err(e) => {
+                show_error(Some(self), Some("Error Exporting PepPy Data"), e);
+                return;
+            }
+        };
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        let peppy_path = path.with_file_name(format!("{}-peppy.csv", stem));
+        match fs::write(&peppy_path, data_str) {
+            Ok(_) if warnings.is_empty() => {
+                util::show_info(Some(self), Some("PepPy Data Exported"), format!("Wrote {}", peppy_path.display()));
+            }
+            Ok(_) => {
+                util::show_warning(Some(self), Some("PepPy Data Exported"),
+                                   format!("Wrote {}\n{}", peppy_path.display(), warnings.join("\n")));
+            }
+            Err(e) => {
+                show_error(Some(self), Some("Error Exporting PepPy Data"), e);
+            }
+        }
+    }
+
+    fn handle_reduce_action(&self) {
+        if self.imp().file_dialog.borrow().is_some() {
+            return;
+        }
+        let dialog = FileChooserNative::new(Some("Reduce Run Log"),
+                                            Some(self),
+                                            FileChooserAction::Open,
+                                            None, None);
+        dialog.set_current_folder(Some(&self.get_last_dir())).expect("expected setting folder to succeed");
+
+        let main_window = self.clone();
+        dialog.connect_response(move |dlg: &FileChooserNative, response| {
+            if response != ResponseType::Cancel {
+                if let Some(path) = dlg.file().and_then(|file| file.path()) {
+                    main_window.confirm_check_stars(&path, MainWindow::write_reduced_log);
+                }
+            }
+            main_window.imp().file_dialog.replace(None);
+        });
         dialog.show();
         self.imp().file_dialog.replace(Some(dialog));
     }
 
+    /// Exports the run log at `path` with `export`, asking first if its check stars failed the QC.
+    fn confirm_check_stars<F>(&self, path: &Path, export: F)
+    where F: Fn(&MainWindow, &Path) + 'static {
+        let flagged = match Archive::open_default().and_then(|archive| archive.check_results_for_log(path)) {
+            Ok(results) => { results.into_iter().filter(|r| r.flagged).collect::<Vec<_>>() }
+            Err(e) => {
+                show_error(Some(self), Some("Error Reading Archive"), e);
+                Vec::new()
+            }
+        };
+        if flagged.is_empty() {
+            export(self, path);
+            return;
+        }
+        let message = format!("The check stars of this run are out of tolerance:\n{}\n\nExport anyway?", qc::report(&flagged));
+        let main_window = self.clone();
+        let path = path.to_path_buf();
+        util::confirm(self, "Check Star QC Failed", &message, move |confirmed| {
+            if confirmed {
+                export(&main_window, &path);
+            }
+        });
+    }
+
+    fn write_reduced_log(&self, path: &Path) {
+        let records = match reduction::read_log(path) {
+            Ok(records) => { records }
+            Err(e) => {
+                show_error(Some(self), Some("Error Reading Run Log"), e);
+                return;
+            }
+        };
+        let records = match rejection::load(path) {
+            Ok(rejections) => { rejection::apply(records, &rejections) }
+            Err(e) => {
+                show_error(Some(self), Some("Error Reading Rejections"), e);
+                return;
+            }
+        };
+        let (reduced, unreduced) = reduction::reduce(&records);
+        let stem = path.file_stem().unwrap().to_string_lossy();
+        let reduced_path = path.with_file_name(format!("{}-reduced.csv", stem));
+        match reduction::write_reduced(&reduced_path, &reduced) {
+            Ok(_) if unreduced.is_empty() => {
+                util::show_info(Some(self), Some("Run Log Reduced"),
+                                format!("Wrote {} measurements to {}", reduced.len(), reduced_path.display()));
+            }
+            Ok(_) => {
+                let lines = unreduced.iter().map(|u| u.to_string()).collect::<Vec<_>>();
+                util::show_warning(Some(self), Some("Run Log Partly Reduced"),
+                                   format!("Wrote {} measurements to {}. Not reduced:\n{}", reduced.len(), reduced_path.display(), lines.join("\n")));
+            }
+            Err(e) => {
+                show_error(Some(self), Some("Error Writing Reduced Log"), e);
+            }
+        }
+    }
+
     fn get_last_dir(&self) -> File {
         let settings_dir = self.settings().get::<String>("last-dir");
         if !settings_dir.is_empty() {
@@ -361,13 +852,22 @@ impl MainWindow {
     }
 
     pub fn replace_run(&self, run: PepRun) {
+        self.stop_editing();
+        self.record_undo();
+        self.apply_run(run);
+    }
+
+    /// Shows a run in the window without recording an undo step.
+    fn apply_run(&self, run: PepRun) {
         let imp = self.imp();
         imp.filter_u.set_active(false);
         imp.filter_b.set_active(false);
         imp.filter_v.set_active(false);
         imp.filter_r.set_active(false);
         imp.filter_i.set_active(false);
+        imp.filter_c.set_active(false);
 
+        let mut unknown = Vec::new();
         run.filters.into_iter().for_each(|filter| {
             match filter {
                 0 => { imp.filter_u.set_active(true) }
@@ -375,64 +875,617 @@ impl MainWindow {
                 2 => { imp.filter_v.set_active(true) }
                 3 => { imp.filter_r.set_active(true) }
                 4 => { imp.filter_i.set_active(true) }
-                _ => { panic!("invalid filter"); }
+                5 => { imp.filter_c.set_active(true) }
+                _ => { unknown.push(filter.to_string()) }
             }
         });
+        if !unknown.is_empty() {
+            show_error(Some(self), Some("Unknown Filters"),
+                       format!("The run uses the unknown filter positions {}, they are left out.", unknown.join(", ")));
+        }
+
+        let strategy_pos = SkyStrategy::ALL.iter().position(|s| *s == run.sky_strategy).unwrap_or(0);
+        imp.sky_strategy_dd.set_selected(strategy_pos as u32);
 
         self.stars().remove_all();
 
         run.items.into_iter().for_each(|item| {
-            let star = StarObject::new(item.star_type, item.name);
+            let star = StarObject::from_data(item);
             self.stars().append(&star);
         })
     }
 
-    fn start_execution(&self) {
+    /// Current contents of the window, including stars without filters or vice versa.
+    fn snapshot(&self) -> PepRun {
         let imp = self.imp();
-        let device = imp.settings.get().unwrap().string("device").as_str().trim().to_string();
-        if device.is_empty() {
-            show_error(Some(self), Some("No Device"), "Please configure a device.");
-            return;
+        let filters = [&imp.filter_u, &imp.filter_b, &imp.filter_v, &imp.filter_r, &imp.filter_i, &imp.filter_c]
+            .iter()
+            .enumerate()
+            .filter(|(_, filter)| filter.is_active())
+            .map(|(index, _)| index as u8)
+            .collect();
+        let stars = self.stars()
+            .into_iter()
+            .flatten()
+            .map(|obj| {
+                let star = obj.downcast_ref::<StarObject>()
+                    .expect("object should be a StarObject")
+                    .imp()
+                    .data.borrow();
+                star.clone()
+            })
+            .collect();
+        let mut run = PepRun::new(filters, stars);
+        run.sky_strategy = self.sky_strategy();
+        run
+    }
+
+    /// Remembers the current contents before they get changed.
+    fn record_undo(&self) {
+        let snapshot = self.snapshot();
+        let mut undo_stack = self.imp().undo_stack.borrow_mut();
+        if undo_stack.len() == MAX_UNDO {
+            undo_stack.pop_front();
         }
+        undo_stack.push_back(snapshot);
+        self.imp().redo_stack.borrow_mut().clear();
+    }
 
-        if let Some(run) = self.extract_run() {
-            imp.main_menu_mb.set_sensitive(false);
-            imp.execute_button.set_sensitive(false);
-            execute_run(&device, run, self.get_last_dir(), self.clone(), clone!(
-                #[weak(rename_to = main_window)]
-                self,
-                move || {
-                    main_window.end_execution()
-                }
-            ));
-        } else {
-            show_error(Some(self), Some("No Run"), "Please enter or load a run.");
+    fn handle_undo(&self) {
+        let Some(previous) = self.imp().undo_stack.borrow_mut().pop_back() else {
             return;
-        }
+        };
+        self.imp().redo_stack.borrow_mut().push(self.snapshot());
+        self.restore(previous);
     }
 
-    fn end_execution(&self) {
-        let imp = self.imp();
-        imp.main_menu_mb.set_sensitive(true);
-        imp.execute_button.set_sensitive(true);
+    fn handle_redo(&self) {
+        let Some(next) = self.imp().redo_stack.borrow_mut().pop() else {
+            return;
+        };
+        self.imp().undo_stack.borrow_mut().push_back(self.snapshot());
+        self.restore(next);
     }
 
-    fn setup_callbacks(&self) {
-        self.imp().star_name_entry.connect_activate(clone!(
+    fn restore(&self, run: PepRun) {
+        self.stop_editing();
+        self.apply_run(run);
+    }
+
+    fn device(&self) -> Option<String> {
+        let device = self.settings().string("device").as_str().trim().to_string();
+        if device.is_empty() {
+            show_error(Some(self), Some("No Device"), "Please configure a device.");
+            return None;
+        }
+        Some(device)
+    }
+
+    fn run_options(&self) -> RunOptions {
+        let settings = self.settings();
+        RunOptions {
+            dark_schedule: self.dark_schedule(),
+            instrument: settings.string("instrument").to_string(),
+            catalog: self.catalog(),
+            check_tolerance: settings.double("check-tolerance"),
+            mount: self.mount_settings(),
+            sky_offset: Offset {
+                east: settings.double("sky-offset-east"),
+                north: settings.double("sky-offset-north"),
+            },
+            sync_mount: settings.boolean("sync-mount"),
+            i_times: self.imp().i_times.borrow().clone(),
+        }
+    }
+
+    fn site(&self) -> Site {
+        Site {
+            latitude: self.settings().double("site-latitude"),
+            longitude: self.settings().double("site-longitude"),
+        }
+    }
+
+    fn dark_schedule(&self) -> Option<DarkSchedule> {
+        let settings = self.settings();
+        if settings.boolean("dark-counts") {
+            Some(DarkSchedule { interval: TimeDelta::minutes(settings.int("dark-interval") as i64) })
+        } else {
+            None
+        }
+    }
+
+    fn mount_settings(&self) -> Option<MountSettings> {
+        let settings = self.settings();
+        match settings.string("mount-type").as_str() {
+            "lx200" => {
+                Some(MountSettings::Lx200 { device: settings.string("mount-device").to_string() })
+            }
+            "indi" => {
+                Some(MountSettings::Indi {
+                    address: settings.string("mount-address").to_string(),
+                    device: settings.string("indi-driver").to_string(),
+                    focuser: Some(settings.string("indi-focuser").to_string()).filter(|f| !f.is_empty()),
+                })
+            }
+            "alpaca" => {
+                Some(MountSettings::Alpaca {
+                    address: settings.string("mount-address").to_string(),
+                    number: settings.int("alpaca-device").max(0) as u32,
+                })
+            }
+            _ => { None }
+        }
+    }
+
+    /// Reads the configured catalog, reporting if it cannot be read.
+    fn load_catalog(&self) {
+        let path = self.settings().string("catalog-file");
+        let catalog = if path.is_empty() {
+            None
+        } else {
+            Some(Catalog::load(Path::new(path.as_str())).map_err(|e| format!("{}: {}", path, e)))
+        };
+        if let Some(Err(message)) = &catalog {
+            show_error(Some(self), Some("Error Reading Catalog"), message);
+        }
+        self.imp().catalog.replace(catalog);
+    }
+
+    /// The configured catalog. A catalog that cannot be read is reported again and ignored.
+    fn catalog(&self) -> Option<Catalog> {
+        let catalog = self.imp().catalog.borrow().clone();
+        match catalog {
+            Some(Ok(catalog)) => { Some(catalog) }
+            Some(Err(message)) => {
+                show_error(Some(self), Some("Error Reading Catalog"), message);
+                None
+            }
+            None => { None }
+        }
+    }
+
+    /// Shows how long the current run is expected to take.
+    fn update_estimate(&self) {
+        let run = self.snapshot();
+        if run.items.is_empty() || run.filters.is_empty() {
+            self.imp().estimate_label.set_label("");
+            return;
+        }
+        // Errors in the catalog have been reported when reading it
+        let catalog = self.imp().catalog.borrow();
+        let estimate = self.run_estimate(&run, catalog.as_ref().and_then(|catalog| catalog.as_ref().ok()));
+        self.imp().estimate_label.set_label(&format!("About {}", format_duration(estimate.total())));
+        self.imp().estimate_label.set_tooltip_text(Some(&format!("Measuring {}, pointing {}, dark counts {}",
+                                                                 format_duration(estimate.measuring),
+                                                                 format_duration(estimate.pointing),
+                                                                 format_duration(estimate.darks))));
+    }
+
+    fn run_estimate(&self, run: &PepRun, catalog: Option<&Catalog>) -> Estimate {
+        let dark_schedule = self.dark_schedule();
+        let conditions = Conditions {
+            i_times: &self.imp().i_times.borrow(),
+            catalog,
+            mount: self.mount_settings().is_some(),
+            dark_schedule: dark_schedule.as_ref(),
+        };
+        estimate::estimate(run, &conditions)
+    }
+
+    /// Warns if stars of `runs`, executed one after the other from now on, get below the
+    /// altitude limit before their run is expected to end.
+    fn warn_low_stars(&self, runs: &[&PepRun]) {
+        let Some(catalog) = self.catalog() else {
+            return;
+        };
+        let site = self.site();
+        let min_altitude = self.settings().double("min-altitude");
+        let start = Utc::now();
+        let mut end = start;
+        let mut low: Vec<(String, DateTime<Utc>)> = Vec::new();
+        for run in runs {
+            let run_start = end;
+            end += self.run_estimate(run, Some(&catalog)).total();
+            let stars = run.items.iter()
+                .filter_map(|star| catalog.position(&star.name).map(|position| (star.name.clone(), position)))
+                .collect::<Vec<_>>();
+            for (name, time) in crate::planner::below_altitude(&stars, &site, min_altitude, run_start, end) {
+                if !low.iter().any(|(known, _)| known.eq_ignore_ascii_case(&name)) {
+                    low.push((name, time));
+                }
+            }
+        }
+        if !low.is_empty() {
+            let lines = low.iter().map(|(name, time)| format!("{} from {} UT", name, time.format("%H:%M"))).collect::<Vec<_>>();
+            show_warning(Some(self), Some("Stars Below Altitude Limit"),
+                         format!("Until the expected end at {} UT these stars get below {:.0}°:\n{}",
+                                 end.format("%H:%M"), min_altitude, lines.join("\n")));
+        }
+    }
+
+    fn start_execution(&self) {
+        let Some(device) = self.device() else {
+            return;
+        };
+
+        if let Some(run) = self.extract_run() {
+            self.warn_low_stars(&[&run]);
+            self.begin_execution();
+            execute_run(&device, run, self.run_options(), self.get_last_dir(), self.clone(), clone!(
+                #[weak(rename_to = main_window)]
+                self,
+                move || {
+                    main_window.end_execution()
+                }
+            ));
+        } else {
+            show_error(Some(self), Some("No Run"), "Please enter or load a run.");
+            return;
+        }
+    }
+
+    fn begin_execution(&self) {
+        let imp = self.imp();
+        imp.main_menu_mb.set_sensitive(false);
+        imp.execute_button.set_sensitive(false);
+        imp.run_session_button.set_sensitive(false);
+    }
+
+    fn end_execution(&self) {
+        let imp = self.imp();
+        imp.main_menu_mb.set_sensitive(true);
+        imp.execute_button.set_sensitive(true);
+        imp.run_session_button.set_sensitive(true);
+        // The run may have calibrated stars
+        self.load_i_times();
+        self.update_estimate();
+    }
+
+    /// Reads the integration times remembered for the configured instrument.
+    fn load_i_times(&self) {
+        let instrument = self.settings().string("instrument").to_string();
+        let i_times = match Archive::open_default().and_then(|archive| archive.integration_times(&instrument)) {
+            Ok(i_times) => { i_times }
+            Err(e) => {
+                eprintln!("Cannot read remembered integration times: {}", e);
+                IntegrationTimes::new()
+            }
+        };
+        self.imp().i_times.replace(i_times);
+        for pos in 0..self.stars().n_items() {
+            self.show_i_times(&self.star_at(pos));
+        }
+    }
+
+    /// Shows the fixed integration times of a star and the remembered ones of the other filters.
+    fn show_i_times(&self, star: &StarObject) {
+        let fixed = star.imp().data.borrow().i_times.clone();
+        let remembered: BTreeMap<u8, u16> = self.imp().i_times.borrow()
+            .get(&star.name())
+            .map(|times| times.iter()
+                .filter(|(slot, _)| !fixed.contains_key(slot))
+                .map(|(slot, time)| (*slot, *time))
+                .collect())
+            .unwrap_or_default();
+        let text = match (fixed.is_empty(), remembered.is_empty()) {
+            (true, _) => { format_i_times(&remembered) }
+            (false, true) => { format!("fixed {}", format_i_times(&fixed)) }
+            (false, false) => { format!("fixed {} · {}", format_i_times(&fixed), format_i_times(&remembered)) }
+        };
+        star.set_i_times(text);
+    }
+
+    /// Lets the operator fix the integration times of the selected stars in the run.
+    fn edit_fixed_i_times(&self) {
+        let selected = self.selected_stars();
+        let Some(first) = selected.first() else {
+            return;
+        };
+        let times = self.star_at(*first).imp().data.borrow().i_times.clone();
+        let description = "Used without calibration for the selected stars and their sky, e.g. to avoid saturating bright stars";
+        let dialog = IntegrationTimesDialog::new(self, "Fixed Integration Times", description, &times);
+        let main_window = self.clone();
+        dialog.connect_response(move |dlg: &IntegrationTimesDialog, response| {
+            dlg.hide();
+            dlg.destroy();
+            if response != ResponseType::Ok || !main_window.can_edit() {
+                return;
+            }
+            let times = dlg.times();
+            main_window.record_undo();
+            for pos in selected.iter() {
+                let star = main_window.star_at(*pos);
+                star.imp().data.borrow_mut().i_times = times.clone();
+                main_window.show_i_times(&star);
+            }
+            main_window.update_estimate();
+        });
+        dialog.show();
+    }
+
+    /// Lets the operator change the integration times remembered for the selected stars.
+    fn edit_remembered_i_times(&self) {
+        let mut names = self.selected_stars().into_iter()
+            .map(|pos| self.star_at(pos).name())
+            .collect::<Vec<_>>();
+        names.sort();
+        names.dedup();
+        if names.is_empty() {
+            return;
+        }
+        let instrument = self.settings().string("instrument").to_string();
+        let remembered = names.iter()
+            .map(|name| self.imp().i_times.borrow()
+                .get(name)
+                .map(|times| times.iter().map(|(slot, time)| (*slot, *time)).collect::<BTreeMap<_, _>>())
+                .unwrap_or_default())
+            .collect::<Vec<_>>();
+        let mut description = format!("Starting values for calibrating {} with {}", names.join(", "), instrument);
+        // Saving replaces the times of all stars, so only times they share are shown
+        let times = if remembered.iter().all(|times| *times == remembered[0]) {
+            remembered[0].clone()
+        } else {
+            description.push_str(". The stars have different times, which are replaced by the ones entered here.");
+            BTreeMap::new()
+        };
+        let dialog = IntegrationTimesDialog::new(self, "Remembered Integration Times", &description, &times);
+        let main_window = self.clone();
+        dialog.connect_response(move |dlg: &IntegrationTimesDialog, response| {
+            dlg.hide();
+            dlg.destroy();
+            if response != ResponseType::Ok {
+                return;
+            }
+            let times = dlg.times();
+            let result = Archive::open_default().and_then(|mut archive| {
+                names.iter().try_for_each(|name| archive.replace_integration_times(&instrument, name, &times))
+            });
+            if let Err(e) = result {
+                show_error(Some(&main_window), Some("Error Storing Integration Times"), e);
+            }
+            main_window.load_i_times();
+            main_window.update_estimate();
+        });
+        dialog.show();
+    }
+
+    fn setup_session(&self) {
+        let names = StringList::new(&[]);
+        self.imp().session_names.set(names.clone()).expect("session names shouldn't be set yet");
+
+        let factory = SignalListItemFactory::new();
+        factory.connect_setup(move |_, list_item| {
+            let label = Label::builder()
+                .xalign(0.0)
+                .margin_top(3)
+                .margin_bottom(3)
+                .margin_start(12)
+                .margin_end(12)
+                .build();
+            list_item
+                .downcast_ref::<ListItem>()
+                .expect("should be a ListItem")
+                .set_child(Some(&label));
+        });
+        factory.connect_bind(move |_, list_item| {
+            let list_item = list_item
+                .downcast_ref::<ListItem>()
+                .expect("should be a ListItem");
+            let name = list_item
+                .item()
+                .and_downcast::<StringObject>()
+                .expect("item should be a StringObject")
+                .string();
+            list_item
+                .child()
+                .and_downcast::<Label>()
+                .expect("child should be a Label")
+                .set_label(&name);
+        });
+
+        let selection_model = SingleSelection::new(Some(names));
+        selection_model.set_autoselect(false);
+        selection_model.set_can_unselect(true);
+        self.imp().session_list_vw.set_model(Some(&selection_model));
+        self.imp().session_list_vw.set_factory(Some(&factory));
+    }
+
+    fn session_names(&self) -> &StringList {
+        self.imp()
+            .session_names
+            .get()
+            .expect("session names should already be initialized")
+    }
+
+    fn queue_run(&self, name: String, run: PepRun) {
+        self.session_names().append(&name);
+        self.imp().session_runs.borrow_mut().push(QueuedRun { name, run });
+    }
+
+    fn handle_queue_current(&self) {
+        let Some(run) = self.extract_run() else {
+            show_error(Some(self), Some("No Run"), "Please enter or load a run.");
+            return;
+        };
+        let name = match self.imp().current_file.borrow().as_ref().and_then(|p| p.file_stem()) {
+            Some(stem) => { stem.to_string_lossy().to_string() }
+            None => { format!("Run {}", self.session_names().n_items() + 1) }
+        };
+        self.queue_run(name, run);
+    }
+
+    fn handle_queue_files(&self) {
+        if self.imp().file_dialog.borrow().is_some() {
+            return;
+        }
+        let dialog = FileChooserNative::new(Some("Add Runs to Session"),
+                                            Some(self),
+                                            FileChooserAction::Open,
+                                            None, None);
+        dialog.set_select_multiple(true);
+        dialog.set_current_folder(Some(&self.get_last_dir())).expect("expected setting folder to succeed");
+
+        let main_window = self.clone();
+        dialog.connect_response(move |dlg: &FileChooserNative, response| {
+            if response != ResponseType::Cancel {
+                for file in dlg.files().iter::<File>().flatten() {
+                    if let Some(run) = main_window.read_run(&file) {
+                        let name = file.path()
+                            .and_then(|p| p.file_stem().map(|stem| stem.to_string_lossy().to_string()))
+                            .unwrap_or_else(|| format!("Run {}", main_window.session_names().n_items() + 1));
+                        main_window.queue_run(name, run);
+                    }
+                }
+            }
+            main_window.imp().file_dialog.replace(None);
+        });
+        dialog.show();
+        self.imp().file_dialog.replace(Some(dialog));
+    }
+
+    fn handle_unqueue(&self) {
+        let selection = self.imp().session_list_vw
+            .model()
+            .unwrap()
+            .downcast::<SingleSelection>()
+            .expect("model should be a SingleSelection");
+        let selected_idx = selection.selected();
+        if selected_idx != INVALID_LIST_POSITION {
+            selection.set_selected(INVALID_LIST_POSITION);
+            self.session_names().remove(selected_idx);
+            self.imp().session_runs.borrow_mut().remove(selected_idx as usize);
+        }
+    }
+
+    /// Orders the queued runs by the positions of their stars on the sky.
+    fn handle_order_session(&self) {
+        if self.imp().session_runs.borrow().len() < 2 {
+            return;
+        }
+        let Some(catalog) = self.catalog() else {
+            show_error(Some(self), Some("No Catalog"), "Please configure a catalog with the coordinates of the stars.");
+            return;
+        };
+        let dark_schedule = self.dark_schedule();
+        let conditions = Conditions {
+            i_times: &self.imp().i_times.borrow(),
+            catalog: Some(&catalog),
+            mount: self.mount_settings().is_some(),
+            dark_schedule: dark_schedule.as_ref(),
+        };
+        let runs = self.imp().session_runs.take();
+        let order = scheduler::order_runs(&runs.iter().map(|queued| &queued.run).collect::<Vec<_>>(),
+                                          &conditions, &self.site(), Utc::now(), self.settings().double("min-altitude"));
+        let unknown = runs.iter()
+            .filter(|queued| queued.run.items.iter().all(|star| catalog.position(&star.name).is_none()))
+            .map(|queued| queued.name.clone())
+            .collect::<Vec<_>>();
+        let mut runs = runs.into_iter().map(Some).collect::<Vec<_>>();
+        let ordered = order.into_iter()
+            .map(|k| runs[k].take().expect("order should contain each run once"))
+            .collect::<Vec<_>>();
+        let names = ordered.iter().map(|queued| queued.name.as_str()).collect::<Vec<_>>();
+        self.session_names().splice(0, self.session_names().n_items(), &names);
+        self.imp().session_runs.replace(ordered);
+        if !unknown.is_empty() {
+            show_warning(Some(self), Some("Unknown Positions"),
+                         format!("No coordinates in the catalog for the stars of {}, these runs come last.", unknown.join(", ")));
+        }
+    }
+
+    fn handle_run_session(&self) {
+        if self.imp().session_runs.borrow().is_empty() {
+            show_error(Some(self), Some("Empty Session"), "Please add runs to the session queue.");
+            return;
+        }
+        if self.imp().file_dialog.borrow().is_some() {
+            return;
+        }
+        let Some(device) = self.device() else {
+            return;
+        };
+
+        let dialog = FileChooserNative::new(Some("Folder for Session Logs"),
+                                            Some(self),
+                                            FileChooserAction::SelectFolder,
+                                            None, None);
+        dialog.set_current_folder(Some(&self.get_last_dir())).expect("expected setting folder to succeed");
+
+        let main_window = self.clone();
+        dialog.connect_response(move |dlg: &FileChooserNative, response| {
+            main_window.imp().file_dialog.replace(None);
+            if response == ResponseType::Cancel {
+                return;
+            }
+            let Some(base_dir) = dlg.file().and_then(|f| f.path()) else {
+                return;
+            };
+            let session_dir = base_dir.join(format!("session-{}", Utc::now().format("%Y%m%d-%H%M")));
+            let runs = main_window.imp().session_runs.borrow().clone();
+            main_window.warn_low_stars(&runs.iter().map(|queued| &queued.run).collect::<Vec<_>>());
+            main_window.begin_execution();
+            execute_session(&device, runs, main_window.run_options(), session_dir, main_window.clone(), clone!(
+                #[weak]
+                main_window,
+                move || {
+                    main_window.end_execution()
+                }
+            ));
+        });
+        dialog.show();
+        self.imp().file_dialog.replace(Some(dialog));
+    }
+
+    fn setup_callbacks(&self) {
+        self.stars().connect_items_changed(clone!(
             #[weak(rename_to = main_window)]
             self,
-            move |_| {
-                match main_window.imp().editing.replace(None) {
-                    Some(pos) => {
-                        main_window.update_star(pos);
-                    }
-                    None => {
-                        main_window.new_star();
-                    }
+            move |_, position, _, added| {
+                for pos in position..position + added {
+                    main_window.show_i_times(&main_window.star_at(pos));
                 }
+                main_window.update_estimate()
+            }
+        ));
+
+        let imp = self.imp();
+        for filter in [&imp.filter_u, &imp.filter_b, &imp.filter_v, &imp.filter_r, &imp.filter_i, &imp.filter_c] {
+            filter.connect_toggled(clone!(
+                #[weak(rename_to = main_window)]
+                self,
+                move |_| {
+                    main_window.update_estimate()
+                }
+            ));
+        }
+
+        imp.sky_strategy_dd.connect_selected_notify(clone!(
+            #[weak(rename_to = main_window)]
+            self,
+            move |_| {
+                main_window.update_estimate()
             }
         ));
 
+        for entry in [self.imp().star_name_entry.get(), self.imp().star_sky_entry.get()] {
+            entry.connect_activate(clone!(
+                #[weak(rename_to = main_window)]
+                self,
+                move |_| {
+                    match main_window.imp().editing.replace(None) {
+                        Some(pos) => {
+                            if !main_window.update_star(pos) {
+                                main_window.imp().editing.replace(Some(pos));
+                            }
+                        }
+                        None => {
+                            main_window.new_star();
+                        }
+                    }
+                }
+            ));
+        }
+
         let evt_ctrl = EventControllerKey::new();
         // Need to manually clone here because the macro expects unit result.
         let main_window = self.clone();
@@ -452,7 +1505,7 @@ impl MainWindow {
         let main_window = self.clone();
         evt_ctrl.connect_key_pressed(
             move |_, key, _, _| {
-                if main_window.imp().execute_button.get_sensitive() {
+                if main_window.can_edit() {
                     main_window.remove_star(&key)
                 } else {
                     Propagation::Proceed
@@ -461,11 +1514,18 @@ impl MainWindow {
         );
         self.imp().star_list_vw.add_controller(evt_ctrl);
 
+        // Only in the star list, the name entry keeps its own undo
+        let shortcuts = ShortcutController::new();
+        for (trigger, action) in [("<Control>z", "win.undo"), ("<Control><Shift>z", "win.redo")] {
+            shortcuts.add_shortcut(Shortcut::new(ShortcutTrigger::parse_string(trigger), Some(NamedAction::new(action))));
+        }
+        self.imp().star_list_vw.add_controller(shortcuts);
+
         self.imp().star_list_vw.connect_activate(clone!(
             #[weak(rename_to = main_window)]
             self,
             move |list_view, pos| {
-                if main_window.imp().execute_button.get_sensitive() {
+                if main_window.can_edit() {
                     let star_object = list_view
                         .model()
                         .unwrap()
@@ -487,18 +1547,95 @@ impl MainWindow {
                 main_window.start_execution()
             }
         ));
+
+        self.imp().queue_current_button.connect_clicked(clone!(
+            #[weak(rename_to = main_window)]
+            self,
+            move |_| {
+                main_window.handle_queue_current()
+            }
+        ));
+
+        self.imp().queue_files_button.connect_clicked(clone!(
+            #[weak(rename_to = main_window)]
+            self,
+            move |_| {
+                main_window.handle_queue_files()
+            }
+        ));
+
+        self.imp().order_session_button.connect_clicked(clone!(
+            #[weak(rename_to = main_window)]
+            self,
+            move |_| {
+                main_window.handle_order_session()
+            }
+        ));
+
+        self.imp().unqueue_button.connect_clicked(clone!(
+            #[weak(rename_to = main_window)]
+            self,
+            move |_| {
+                main_window.handle_unqueue()
+            }
+        ));
+
+        self.imp().run_session_button.connect_clicked(clone!(
+            #[weak(rename_to = main_window)]
+            self,
+            move |_| {
+                main_window.handle_run_session()
+            }
+        ));
     }
 
     fn setup_factory(&self) {
         let factory = SignalListItemFactory::new();
 
+        let main_window = self.downgrade();
         factory.connect_setup(move |_, list_item| {
-            let star_row = StarObjectRow::new();
-            list_item
+            let list_item = list_item
                 .downcast_ref::<ListItem>()
-                .expect("should be a ListItem")
-                .set_child(Some(&star_row));
-
+                .expect("should be a ListItem");
+            let star_row = StarObjectRow::new();
+            list_item.set_child(Some(&star_row));
+
+            // Rows are dragged by their position and dropped onto the row whose place they take
+            let drag_source = DragSource::new();
+            drag_source.set_actions(DragAction::MOVE);
+            drag_source.connect_prepare(clone!(
+                #[weak]
+                list_item,
+                #[strong]
+                main_window,
+                #[upgrade_or]
+                None,
+                move |_, _, _| {
+                    if !main_window.upgrade()?.can_edit() {
+                        return None;
+                    }
+                    Some(ContentProvider::for_value(&list_item.position().to_value()))
+                }
+            ));
+            star_row.add_controller(drag_source);
+
+            let drop_target = DropTarget::new(u32::static_type(), DragAction::MOVE);
+            drop_target.connect_drop(clone!(
+                #[weak]
+                list_item,
+                #[strong]
+                main_window,
+                #[upgrade_or]
+                false,
+                move |_, value, _, _| {
+                    let (Some(main_window), Ok(from)) = (main_window.upgrade(), value.get::<u32>()) else {
+                        return false;
+                    };
+                    main_window.move_star(from, list_item.position());
+                    true
+                }
+            ));
+            star_row.add_controller(drop_target);
         });
 
         factory.connect_bind(move |_, list_item| {
@@ -571,7 +1708,192 @@ impl MainWindow {
             )
             .build();
 
-        self.add_action_entries([action_new, action_save, action_open, action_configure, action_gen_run]);
+        let action_undo = ActionEntry::builder("undo")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    if window.can_edit() {
+                        window.handle_undo();
+                    }
+                }
+            )
+            .build();
+
+        let action_redo = ActionEntry::builder("redo")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    if window.can_edit() {
+                        window.handle_redo();
+                    }
+                }
+            )
+            .build();
+
+        let action_delete_stars = ActionEntry::builder("delete_stars")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    if window.can_edit() {
+                        window.delete_selected();
+                    }
+                }
+            )
+            .build();
+
+        let action_duplicate_stars = ActionEntry::builder("duplicate_stars")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    if window.can_edit() {
+                        window.duplicate_selected();
+                    }
+                }
+            )
+            .build();
+
+        let action_order_stars = ActionEntry::builder("order_stars")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    if window.can_edit() {
+                        window.handle_order_stars();
+                    }
+                }
+            )
+            .build();
+
+        let action_edit_i_times = ActionEntry::builder("edit_i_times")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    window.edit_remembered_i_times();
+                }
+            )
+            .build();
+
+        let action_edit_fixed_i_times = ActionEntry::builder("edit_fixed_i_times")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    if window.can_edit() {
+                        window.edit_fixed_i_times();
+                    }
+                }
+            )
+            .build();
+
+        let action_change_type = ActionEntry::builder("change_type")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    if window.can_edit() {
+                        window.change_type_of_selected();
+                    }
+                }
+            )
+            .build();
+
+        let action_insert_before = ActionEntry::builder("insert_before")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    if window.can_edit() {
+                        window.insert_star(false);
+                    }
+                }
+            )
+            .build();
+
+        let action_insert_after = ActionEntry::builder("insert_after")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    if window.can_edit() {
+                        window.insert_star(true);
+                    }
+                }
+            )
+            .build();
+
+        let action_view_log = ActionEntry::builder("view_log")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    window.handle_view_log_action();
+                }
+            )
+            .build();
+
+        let action_reduce_log = ActionEntry::builder("reduce_log")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    window.handle_reduce_action();
+                }
+            )
+            .build();
+
+        let action_archive = ActionEntry::builder("archive")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    let instrument = window.settings().string("instrument").to_string();
+                    let archive_window = ArchiveWindow::new(&window.application().unwrap(), window, instrument);
+                    archive_window.show();
+                }
+            )
+            .build();
+
+        let action_light_curve = ActionEntry::builder("light_curve")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    let light_curve_window = LightCurveWindow::new(&window.application().unwrap(), window);
+                    light_curve_window.show();
+                }
+            )
+            .build();
+
+        let action_period_search = ActionEntry::builder("period_search")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    let period_window = PeriodWindow::new(&window.application().unwrap(), window);
+                    period_window.show();
+                }
+            )
+            .build();
+        let action_planner = ActionEntry::builder("planner")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    let run = window.snapshot();
+                    let catalog = window.catalog();
+                    let duration = window.run_estimate(&run, catalog.as_ref()).total();
+                    let planner_window = PlannerWindow::new(&window.application().unwrap(), window, run, catalog,
+                                                            duration, window.settings().clone());
+                    planner_window.show();
+                }
+            )
+            .build();
+
+        let action_import_peppy = ActionEntry::builder("import_peppy")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    window.handle_import_peppy_action();
+                }
+            )
+            .build();
+
+        let action_export_peppy = ActionEntry::builder("export_peppy")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    window.handle_export_peppy_action();
+                }
+            )
+            .build();
+
+        let action_export_peppy_data = ActionEntry::builder("export_peppy_data")
+            .activate(
+                move |window: &MainWindow, _, _| {
+                    window.handle_export_peppy_data_action();
+                }
+            )
+            .build();
+
+        self.add_action_entries([action_new, action_save, action_open, action_undo, action_redo,
+                                 action_delete_stars, action_duplicate_stars, action_change_type, action_order_stars,
+                                 action_edit_i_times, action_edit_fixed_i_times,
+                                 action_insert_before, action_insert_after,
+                                 action_import_peppy, action_export_peppy, action_export_peppy_data,
+                                 action_configure, action_gen_run, action_view_log, action_reduce_log,
+                                 action_archive, action_light_curve, action_period_search,
+                                 action_planner]);
     }
 }
 
@@ -580,11 +1902,20 @@ glib::wrapper! {
 }
 
 impl StarObject {
-    pub fn new(star_type: String, name: String) -> Self {
-        Object::builder()
-            .property("star-type", star_type)
-            .property("name", name)
-            .build()
+    pub fn from_data(data: StarData) -> Self {
+        let star: Self = Object::builder()
+            .property("star-type", data.star_type)
+            .property("name", data.name)
+            .build();
+        star.set_sky_position(data.sky);
+        star.imp().data.borrow_mut().i_times = data.i_times;
+        star
+    }
+
+    /// Sets the sky without the rounding of the sky property.
+    pub fn set_sky_position(&self, sky: Option<SkyPosition>) {
+        self.imp().data.borrow_mut().sky = sky;
+        self.notify("sky");
     }
 }
 
@@ -608,6 +1939,8 @@ impl StarObjectRow {
     pub fn bind(self: &Self, star_object: &StarObject) {
         let star_type_label = self.imp().type_label.get();
         let star_name_label = self.imp().name_label.get();
+        let star_sky_label = self.imp().sky_label.get();
+        let i_times_label = self.imp().i_times_label.get();
         let mut bindings = self.imp().bindings.borrow_mut();
 
         let star_type_binding = star_object
@@ -621,6 +1954,18 @@ impl StarObjectRow {
             .sync_create()
             .build();
         bindings.push(star_name_binding);
+
+        let star_sky_binding = star_object
+            .bind_property("sky", &star_sky_label, "label")
+            .sync_create()
+            .build();
+        bindings.push(star_sky_binding);
+
+        let i_times_binding = star_object
+            .bind_property("i-times", &i_times_label, "label")
+            .sync_create()
+            .build();
+        bindings.push(i_times_binding);
     }
 
     pub fn unbind(&self) {
@@ -630,6 +1975,14 @@ impl StarObjectRow {
     }
 }
 
+/// Integration times in seconds by filter, e.g. "B 20.0 V 10.0".
+fn format_i_times(times: &BTreeMap<u8, u16>) -> String {
+    times.iter()
+        .map(|(slot, time)| format!("{} {:.1}", filter_name(*slot), *time as f64 / 100.0))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
 pub struct FilterSettings {
     u: bool,
     b: bool,
@@ -0,0 +1,213 @@
+use crate::archive::{Archive, Query};
+use crate::ui::MainWindow;
+use crate::util::{show_error, show_info, show_warning};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Utc};
+use gtk::gio::File;
+use gtk::glib::{clone, Object};
+use gtk::prelude::{ButtonExt, Cast, CastNone, EntryBufferExtManual, EntryExt, FileChooserExt, GtkWindowExt, ListItemExt, FileExt, ListModelExt, ListModelExtManual, NativeDialogExt};
+use gtk::subclass::prelude::ObjectSubclassIsExt;
+use std::path::PathBuf;
+use gtk::{glib, Accessible, Application, Buildable, ConstraintTarget, FileChooserAction, FileChooserNative, Label, ListItem, Native, NoSelection, ResponseType, Root, ShortcutManager, SignalListItemFactory, StringList, StringObject, Widget, Window};
+
+mod imp;
+
+glib::wrapper! {
+    pub struct ArchiveWindow(ObjectSubclass<imp::ArchiveWindow>)
+    @extends Window, Widget,
+    @implements Accessible, Buildable, ConstraintTarget, Native, Root, ShortcutManager;
+}
+
+impl ArchiveWindow {
+    pub fn new(app: &Application, parent: &MainWindow, instrument: String) -> Self {
+        let result: Self = Object::builder()
+            .property("application", app)
+            .build();
+        result.set_transient_for(Some(parent));
+        result.imp().default_instrument.set(instrument).expect("instrument shouldn't be set yet");
+        result
+    }
+
+    fn setup_results(&self) {
+        let lines = StringList::new(&[]);
+        self.imp().result_lines.set(lines.clone()).expect("result lines shouldn't be set yet");
+
+        let factory = SignalListItemFactory::new();
+        factory.connect_setup(move |_, list_item| {
+            let label = Label::builder()
+                .xalign(0.0)
+                .margin_start(6)
+                .margin_end(6)
+                .css_classes(["monospace"])
+                .build();
+            list_item
+                .downcast_ref::<ListItem>()
+                .expect("should be a ListItem")
+                .set_child(Some(&label));
+        });
+        factory.connect_bind(move |_, list_item| {
+            let list_item = list_item
+                .downcast_ref::<ListItem>()
+                .expect("should be a ListItem");
+            let line = list_item
+                .item()
+                .and_downcast::<StringObject>()
+                .expect("item should be a StringObject")
+                .string();
+            list_item
+                .child()
+                .and_downcast::<Label>()
+                .expect("child should be a Label")
+                .set_label(&line);
+        });
+
+        self.imp().result_list_vw.set_model(Some(&NoSelection::new(Some(lines))));
+        self.imp().result_list_vw.set_factory(Some(&factory));
+    }
+
+    fn setup_callbacks(&self) {
+        self.imp().search_button.connect_clicked(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| {
+                window.search();
+            }
+        ));
+        self.imp().import_button.connect_clicked(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| {
+                window.handle_import();
+            }
+        ));
+    }
+
+    fn result_lines(&self) -> &StringList {
+        self.imp()
+            .result_lines
+            .get()
+            .expect("result lines should already be initialized")
+    }
+
+    fn extract_query(&self) -> Result<Query, String> {
+        let imp = self.imp();
+        let text = |entry: &gtk::Entry| {
+            let text = entry.buffer().text().trim().to_string();
+            if text.is_empty() { None } else { Some(text) }
+        };
+        let filter = imp.filter_dd
+            .selected_item()
+            .and_downcast::<StringObject>()
+            .map(|s| s.string().to_string())
+            .filter(|s| s != "Any");
+        let from = match text(&imp.from_entry) {
+            Some(date) => { Some(parse_date(&date)?) }
+            None => { None }
+        };
+        // The end date is inclusive
+        let to = match text(&imp.to_entry) {
+            Some(date) => { Some(parse_date(&date)? + TimeDelta::days(1)) }
+            None => { None }
+        };
+        Ok(Query {
+            star: text(&imp.star_entry),
+            filter,
+            from,
+            to,
+            instrument: text(&imp.instrument_entry),
+        })
+    }
+
+    fn search(&self) {
+        let query = match self.extract_query() {
+            Ok(query) => { query }
+            Err(e) => {
+                show_error(Some(self), Some("Invalid Query"), e);
+                return;
+            }
+        };
+        let results = match Archive::open_default().and_then(|archive| archive.query(&query)) {
+            Ok(results) => { results }
+            Err(e) => {
+                show_error(Some(self), Some("Error Querying Archive"), e);
+                return;
+            }
+        };
+
+        let lines = results.iter()
+            .map(|m| {
+                let r = &m.record;
+                format!("{}  {:<10} {:<12} {:<5} {}  {:>5} {:>6} {:>6} {:>6}  {}",
+                        r.timestamp.format("%Y-%m-%d %H:%M:%S"), m.instrument, r.star_id, r.kind().as_str(),
+                        r.filter, r.integration_time, r.count1, r.count2, r.count3, r.note)
+            })
+            .collect::<Vec<_>>();
+        let line_refs = lines.iter().map(|l| l.as_str()).collect::<Vec<_>>();
+        let result_lines = self.result_lines();
+        result_lines.splice(0, result_lines.n_items(), &line_refs);
+        self.imp().status_label.set_label(&format!("{} measurements", results.len()));
+        self.imp().results.replace(results);
+    }
+
+    fn handle_import(&self) {
+        if self.imp().file_dialog.borrow().is_some() {
+            return;
+        }
+        let dialog = FileChooserNative::new(Some("Import Run Logs"),
+                                            Some(self),
+                                            FileChooserAction::Open,
+                                            None, None);
+        dialog.set_select_multiple(true);
+
+        let window = self.clone();
+        dialog.connect_response(move |dlg: &FileChooserNative, response| {
+            window.imp().file_dialog.replace(None);
+            if response == ResponseType::Cancel {
+                return;
+            }
+            let paths = dlg.files().iter::<File>()
+                .flatten()
+                .filter_map(|file| file.path())
+                .collect::<Vec<_>>();
+            window.import(&paths);
+        });
+        dialog.show();
+        self.imp().file_dialog.replace(Some(dialog));
+    }
+
+    fn import(&self, paths: &[PathBuf]) {
+        let mut archive = match Archive::open_default() {
+            Ok(archive) => { archive }
+            Err(e) => {
+                show_error(Some(self), Some("Error Opening Archive"), e);
+                return;
+            }
+        };
+        let instrument = match self.imp().instrument_entry.buffer().text().trim() {
+            "" => { self.imp().default_instrument.get().expect("instrument should be set").clone() }
+            instrument => { instrument.to_string() }
+        };
+        let mut imported = 0;
+        let mut skipped = 0;
+        let mut errors = Vec::new();
+        for path in paths {
+            match archive.ingest_log(path, &instrument, "Imported") {
+                Ok(Some(_)) => { imported += 1; }
+                Ok(None) => { skipped += 1; }
+                Err(e) => { errors.push(format!("{}: {}", path.display(), e)); }
+            }
+        }
+        let message = format!("Imported {} run logs, skipped {} already archived or empty ones.", imported, skipped);
+        if errors.is_empty() {
+            show_info(Some(self), Some("Run Logs Imported"), message);
+        } else {
+            show_warning(Some(self), Some("Run Logs Partly Imported"),
+                         format!("{} These could not be imported:\n{}", message, errors.join("\n")));
+        }
+    }
+}
+
+fn parse_date(text: &str) -> Result<DateTime<Utc>, String> {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .map(|date| date.and_time(NaiveTime::MIN).and_utc())
+        .map_err(|_| format!("'{}' is not a date of the form YYYY-MM-DD.", text))
+}
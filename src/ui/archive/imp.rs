@@ -0,0 +1,61 @@
+use crate::archive::ArchivedMeasurement;
+use gtk::subclass::prelude::*;
+use gtk::{glib, CompositeTemplate, FileChooserNative, StringList, TemplateChild, Window};
+use gtk::glib::subclass::InitializingObject;
+use std::cell::{OnceCell, RefCell};
+
+#[derive(CompositeTemplate, Default)]
+#[template(resource = "/de/geselle_ffm/peprunner/archive_window.ui")]
+pub struct ArchiveWindow {
+    #[template_child]
+    pub star_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub filter_dd: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub from_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub to_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub instrument_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub search_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub result_list_vw: TemplateChild<gtk::ListView>,
+    #[template_child]
+    pub status_label: TemplateChild<gtk::Label>,
+    #[template_child]
+    pub import_button: TemplateChild<gtk::Button>,
+    pub results: RefCell<Vec<ArchivedMeasurement>>,
+    pub result_lines: OnceCell<StringList>,
+    pub default_instrument: OnceCell<String>,
+    pub file_dialog: RefCell<Option<FileChooserNative>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for ArchiveWindow {
+    const NAME: &'static str = "ArchiveWindow";
+    type Type = super::ArchiveWindow;
+    type ParentType = Window;
+
+    fn class_init(klass: &mut Self::Class) {
+        klass.bind_template();
+    }
+
+    fn instance_init(obj: &InitializingObject<Self>) {
+        obj.init_template();
+    }
+}
+
+impl ObjectImpl for ArchiveWindow {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.setup_results();
+        obj.setup_callbacks();
+    }
+}
+
+impl WidgetImpl for ArchiveWindow {}
+
+impl WindowImpl for ArchiveWindow {}
@@ -1,12 +1,21 @@
+use crate::alpaca;
 use crate::ui::MainWindow;
+use crate::util::{show_error, show_info};
 use gtk::gio::Settings;
-use gtk::glib::Object;
-use gtk::prelude::{DialogExt, EntryBufferExtManual, EntryExt, GtkWindowExt, SettingsExt, WidgetExt};
+use gtk::glib::{clone, Object};
+use gtk::prelude::{ButtonExt, CheckButtonExt, DialogExt, EntryBufferExtManual, EntryExt, GtkWindowExt, SettingsExt, WidgetExt};
 use gtk::subclass::prelude::ObjectSubclassIsExt;
-use gtk::{glib, Accessible, Application, Buildable, ConstraintTarget, Dialog, Native, ResponseType, Root, ShortcutManager, Widget, Window};
+use gtk::{gio, glib, Accessible, Application, Buildable, ConstraintTarget, Dialog, Native, ResponseType, Root, ShortcutManager, Widget, Window};
+use std::time::Duration;
 
 mod imp;
 
+/// How long to wait for answers to an Alpaca discovery broadcast.
+const DISCOVERY_WAIT: Duration = Duration::from_secs(2);
+
+/// Values of the mount-type setting in the order of the mount drop down.
+const MOUNT_TYPES: [&str; 4] = ["none", "lx200", "indi", "alpaca"];
+
 glib::wrapper! {
     pub struct ConfigDialog(ObjectSubclass<imp::ConfigDialog>)
     @extends Dialog, Window, Widget,
@@ -24,6 +33,24 @@ impl ConfigDialog {
         if !device.is_empty() {
             result.imp().device_entry.buffer().set_text(device.as_str());
         }
+        result.imp().instrument_entry.buffer().set_text(settings.string("instrument").as_str());
+        result.imp().catalog_entry.buffer().set_text(settings.string("catalog-file").as_str());
+        result.imp().check_tolerance_sb.set_value(settings.double("check-tolerance"));
+        result.imp().dark_counts_cb.set_active(settings.boolean("dark-counts"));
+        result.imp().dark_interval_sb.set_value(settings.int("dark-interval") as f64);
+        let mount_index = MOUNT_TYPES.iter().position(|t| *t == settings.string("mount-type").as_str()).unwrap_or(0);
+        result.imp().mount_dd.set_selected(mount_index as u32);
+        result.imp().mount_device_entry.buffer().set_text(settings.string("mount-device").as_str());
+        result.imp().mount_address_entry.buffer().set_text(settings.string("mount-address").as_str());
+        result.imp().indi_driver_entry.buffer().set_text(settings.string("indi-driver").as_str());
+        result.imp().indi_focuser_entry.buffer().set_text(settings.string("indi-focuser").as_str());
+        result.imp().alpaca_device_sb.set_value(settings.int("alpaca-device") as f64);
+        result.imp().sync_mount_cb.set_active(settings.boolean("sync-mount"));
+        result.imp().sky_offset_east_sb.set_value(settings.double("sky-offset-east"));
+        result.imp().sky_offset_north_sb.set_value(settings.double("sky-offset-north"));
+        result.imp().site_latitude_sb.set_value(settings.double("site-latitude"));
+        result.imp().site_longitude_sb.set_value(settings.double("site-longitude"));
+        result.imp().min_altitude_sb.set_value(settings.double("min-altitude"));
         result.imp().settings.set(settings).expect("Failed to set settings");
         result
     }
@@ -35,8 +62,75 @@ impl ConfigDialog {
                let settings = dialog.imp().settings.get().expect("Failed to get settings");
                let device = dialog.imp().device_entry.buffer().text().as_str().to_string();
                settings.set_string("device", &device).expect("Failed to set settings");
+               let instrument = dialog.imp().instrument_entry.buffer().text().as_str().trim().to_string();
+               settings.set_string("instrument", &instrument).expect("Failed to set settings");
+               let catalog = dialog.imp().catalog_entry.buffer().text().as_str().trim().to_string();
+               settings.set_string("catalog-file", &catalog).expect("Failed to set settings");
+               settings.set_double("check-tolerance", dialog.imp().check_tolerance_sb.value()).expect("Failed to set settings");
+               settings.set_boolean("dark-counts", dialog.imp().dark_counts_cb.is_active()).expect("Failed to set settings");
+               settings.set_int("dark-interval", dialog.imp().dark_interval_sb.value_as_int()).expect("Failed to set settings");
+               let mount_type = MOUNT_TYPES.get(dialog.imp().mount_dd.selected() as usize).unwrap_or(&MOUNT_TYPES[0]);
+               settings.set_string("mount-type", mount_type).expect("Failed to set settings");
+               let mount_device = dialog.imp().mount_device_entry.buffer().text().as_str().trim().to_string();
+               settings.set_string("mount-device", &mount_device).expect("Failed to set settings");
+               let mount_address = dialog.imp().mount_address_entry.buffer().text().as_str().trim().to_string();
+               settings.set_string("mount-address", &mount_address).expect("Failed to set settings");
+               let indi_driver = dialog.imp().indi_driver_entry.buffer().text().as_str().trim().to_string();
+               settings.set_string("indi-driver", &indi_driver).expect("Failed to set settings");
+               let indi_focuser = dialog.imp().indi_focuser_entry.buffer().text().as_str().trim().to_string();
+               settings.set_string("indi-focuser", &indi_focuser).expect("Failed to set settings");
+               settings.set_int("alpaca-device", dialog.imp().alpaca_device_sb.value_as_int()).expect("Failed to set settings");
+               settings.set_boolean("sync-mount", dialog.imp().sync_mount_cb.is_active()).expect("Failed to set settings");
+               settings.set_double("sky-offset-east", dialog.imp().sky_offset_east_sb.value()).expect("Failed to set settings");
+               settings.set_double("sky-offset-north", dialog.imp().sky_offset_north_sb.value()).expect("Failed to set settings");
+               settings.set_double("site-latitude", dialog.imp().site_latitude_sb.value()).expect("Failed to set settings");
+               settings.set_double("site-longitude", dialog.imp().site_longitude_sb.value()).expect("Failed to set settings");
+               settings.set_double("min-altitude", dialog.imp().min_altitude_sb.value()).expect("Failed to set settings");
            }
             dialog.destroy();
         });
+        self.imp().discover_button.connect_clicked(clone!(
+            #[weak(rename_to = dialog)]
+            self,
+            move |_| {
+                dialog.discover_telescopes();
+            }
+        ));
+    }
+
+    /// Fills in the first Alpaca telescope answering on the local network.
+    fn discover_telescopes(&self) {
+        let button = self.imp().discover_button.get();
+        button.set_sensitive(false);
+        glib::spawn_future_local(clone!(
+            #[weak(rename_to = dialog)]
+            self,
+            async move {
+                let result = gio::spawn_blocking(|| alpaca::discover(DISCOVERY_WAIT)).await
+                    .unwrap_or_else(|_| Ok(Vec::new()));
+                dialog.imp().discover_button.set_sensitive(true);
+                match result {
+                    Ok(telescopes) if telescopes.is_empty() => {
+                        show_info(Some(&dialog), Some("Alpaca Discovery"), "No Alpaca telescopes found.");
+                    }
+                    Ok(telescopes) => {
+                        let first = &telescopes[0];
+                        dialog.imp().mount_dd.set_selected(MOUNT_TYPES.iter().position(|t| *t == "alpaca").unwrap() as u32);
+                        dialog.imp().mount_address_entry.buffer().set_text(first.address.as_str());
+                        dialog.imp().alpaca_device_sb.set_value(first.number as f64);
+                        if telescopes.len() > 1 {
+                            let found = telescopes.iter()
+                                .map(|t| format!("{} (device {} at {})", t.name, t.number, t.address))
+                                .collect::<Vec<_>>()
+                                .join("\n");
+                            show_info(Some(&dialog), Some("Alpaca Discovery"), format!("Using the first of these telescopes:\n{}", found));
+                        }
+                    }
+                    Err(e) => {
+                        show_error(Some(&dialog), Some("Alpaca Discovery"), e);
+                    }
+                }
+            }
+        ));
     }
 }
\ No newline at end of file
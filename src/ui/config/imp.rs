@@ -9,6 +9,42 @@ use gtk::glib::subclass::InitializingObject;
 pub struct ConfigDialog {
     #[template_child]
     pub device_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub instrument_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub catalog_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub check_tolerance_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub dark_counts_cb: TemplateChild<gtk::CheckButton>,
+    #[template_child]
+    pub dark_interval_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub mount_dd: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub mount_device_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub mount_address_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub indi_driver_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub indi_focuser_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub discover_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub alpaca_device_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub sync_mount_cb: TemplateChild<gtk::CheckButton>,
+    #[template_child]
+    pub sky_offset_east_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub sky_offset_north_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub site_latitude_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub site_longitude_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub min_altitude_sb: TemplateChild<gtk::SpinButton>,
     pub settings: OnceCell<Settings>,
 }
 
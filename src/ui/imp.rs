@@ -1,11 +1,14 @@
-use crate::common::StarData;
+use crate::catalog::Catalog;
+use crate::common::{PepRun, SkyPosition, StarData};
+use crate::measurement::{IntegrationTimes, QueuedRun};
 use glib::subclass::InitializingObject;
 use glib::Properties;
 use gtk::glib::Binding;
 use gtk::prelude::*;
 use gtk::subclass::prelude::*;
-use gtk::{gio, glib, ApplicationWindow, CompositeTemplate, FileChooserNative, Label};
+use gtk::{gio, glib, ApplicationWindow, CompositeTemplate, FileChooserNative, Label, StringList};
 use std::cell::{OnceCell, RefCell};
+use std::collections::VecDeque;
 use std::path::PathBuf;
 use gtk::gio::Settings;
 
@@ -25,15 +28,43 @@ pub struct MainWindow {
     #[template_child]
     pub filter_i: TemplateChild<gtk::CheckButton>,
     #[template_child]
+    pub filter_c: TemplateChild<gtk::CheckButton>,
+    #[template_child]
+    pub sky_strategy_dd: TemplateChild<gtk::DropDown>,
+    #[template_child]
     pub star_type_dd: TemplateChild<gtk::DropDown>,
     #[template_child]
     pub star_name_entry: TemplateChild<gtk::Entry>,
     #[template_child]
+    pub star_sky_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
     pub star_list_vw: TemplateChild<gtk::ListView>,
     #[template_child]
+    pub session_list_vw: TemplateChild<gtk::ListView>,
+    #[template_child]
+    pub queue_current_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub queue_files_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub unqueue_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub order_session_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub run_session_button: TemplateChild<gtk::Button>,
+    #[template_child]
     pub execute_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub estimate_label: TemplateChild<gtk::Label>,
     pub stars: RefCell<Option<gio::ListStore>>,
+    pub session_runs: RefCell<Vec<QueuedRun>>,
+    /// Integration times remembered for the configured instrument
+    pub i_times: RefCell<IntegrationTimes>,
+    /// The configured catalog, or why it cannot be read
+    pub catalog: RefCell<Option<Result<Catalog, String>>>,
+    pub session_names: OnceCell<StringList>,
     pub editing: RefCell<Option<u32>>,
+    pub undo_stack: RefCell<VecDeque<PepRun>>,
+    pub redo_stack: RefCell<Vec<PepRun>>,
     pub current_file: RefCell<Option<PathBuf>>,
     pub file_dialog: RefCell<Option<FileChooserNative>>,
     pub settings: OnceCell<Settings>,
@@ -61,6 +92,7 @@ impl ObjectImpl for MainWindow {
 
         let obj = self.obj();
         obj.setup_stars();
+        obj.setup_session();
         obj.setup_callbacks();
         obj.setup_factory();
         obj.setup_actions();
@@ -79,7 +111,22 @@ impl ApplicationWindowImpl for MainWindow {}
 pub struct StarObject {
     #[property(name = "star-type", get, set, type = String, member = star_type)]
     #[property(name = "name", get, set, type = String, member = name)]
+    #[property(name = "sky", get = Self::sky, set = Self::set_sky, type = String)]
     pub data: RefCell<StarData>,
+    /// Integration times as shown in the star list
+    #[property(name = "i-times", get, set)]
+    pub i_times: RefCell<String>,
+}
+
+impl StarObject {
+    /// Sky position as shown in the star list, empty for the configured offset.
+    fn sky(&self) -> String {
+        self.data.borrow().sky.map(|sky| sky.to_string()).unwrap_or_default()
+    }
+
+    fn set_sky(&self, sky: String) {
+        self.data.borrow_mut().sky = SkyPosition::parse(&sky);
+    }
 }
 
 #[glib::object_subclass]
@@ -98,6 +145,10 @@ pub struct StarObjectRow {
     pub type_label: TemplateChild<Label>,
     #[template_child]
     pub name_label: TemplateChild<Label>,
+    #[template_child]
+    pub sky_label: TemplateChild<Label>,
+    #[template_child]
+    pub i_times_label: TemplateChild<Label>,
     pub bindings: RefCell<Vec<Binding>>,
 }
 
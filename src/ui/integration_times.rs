@@ -0,0 +1,44 @@
+use gtk::{glib, Accessible, Buildable, ConstraintTarget, Dialog, Native, Root, ShortcutManager, SpinButton, Widget, Window};
+use gtk::glib::Object;
+use gtk::prelude::GtkWindowExt;
+use gtk::subclass::prelude::ObjectSubclassIsExt;
+use std::collections::BTreeMap;
+use crate::ui::MainWindow;
+
+mod imp;
+
+glib::wrapper! {
+    pub struct IntegrationTimesDialog(ObjectSubclass<imp::IntegrationTimesDialog>)
+    @extends Dialog, Window, Widget,
+    @implements Accessible, Buildable, ConstraintTarget, Native, Root, ShortcutManager;
+}
+
+impl IntegrationTimesDialog {
+    /// Dialog for integration times in 1/100 s by filter slot.
+    pub fn new(parent: &MainWindow, title: &str, description: &str, times: &BTreeMap<u8, u16>) -> Self {
+        let result: Self = Object::builder().build();
+        result.set_transient_for(Some(parent));
+        result.set_modal(true);
+        result.set_title(Some(title));
+        result.imp().description_label.set_label(description);
+        for (slot, spin_button) in result.spin_buttons().iter().enumerate() {
+            let time = times.get(&(slot as u8)).copied().unwrap_or(0);
+            spin_button.set_value(time as f64 / 100.0);
+        }
+        result
+    }
+
+    fn spin_buttons(&self) -> [SpinButton; 5] {
+        let imp = self.imp();
+        [imp.u_sb.get(), imp.b_sb.get(), imp.v_sb.get(), imp.r_sb.get(), imp.i_sb.get()]
+    }
+
+    /// The entered times, without the filters left at zero.
+    pub fn times(&self) -> BTreeMap<u8, u16> {
+        self.spin_buttons().iter()
+            .enumerate()
+            .map(|(slot, spin_button)| (slot as u8, (spin_button.value() * 100.0).round() as u16))
+            .filter(|(_, time)| *time > 0)
+            .collect()
+    }
+}
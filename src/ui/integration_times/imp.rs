@@ -0,0 +1,47 @@
+use gtk::{glib, CompositeTemplate, Dialog, TemplateChild};
+use gtk::subclass::prelude::*;
+
+#[derive(CompositeTemplate, Default)]
+#[template(resource = "/de/geselle_ffm/peprunner/integration_times_dialog.ui")]
+pub struct IntegrationTimesDialog {
+    #[template_child]
+    pub description_label: TemplateChild<gtk::Label>,
+    #[template_child]
+    pub u_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub b_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub v_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub r_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub i_sb: TemplateChild<gtk::SpinButton>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for IntegrationTimesDialog {
+    const NAME: &'static str = "IntegrationTimesDialog";
+
+    type Type = super::IntegrationTimesDialog;
+    type ParentType = Dialog;
+
+    fn class_init(klass: &mut Self::Class) {
+        klass.bind_template();
+    }
+
+    fn instance_init(obj: &glib::subclass::InitializingObject<Self>) {
+        obj.init_template();
+    }
+}
+
+impl ObjectImpl for IntegrationTimesDialog {
+    fn constructed(&self) {
+        self.parent_constructed();
+    }
+}
+
+impl WidgetImpl for IntegrationTimesDialog {}
+
+impl WindowImpl for IntegrationTimesDialog {}
+
+impl DialogImpl for IntegrationTimesDialog {}
@@ -0,0 +1,135 @@
+use crate::archive::Archive;
+use crate::astro::julian_date;
+use crate::reduction::DifferentialMagnitude;
+use crate::ui::plot::{PlotPoint, Series};
+use crate::ui::MainWindow;
+use crate::util::show_error;
+use gtk::glib::{clone, Object};
+use gtk::prelude::{ButtonExt, CastNone, GtkWindowExt, ListModelExt};
+use gtk::subclass::prelude::ObjectSubclassIsExt;
+use gtk::{glib, Accessible, Application, Buildable, ConstraintTarget, Native, Root, ShortcutManager, StringObject, Widget, Window};
+
+mod imp;
+
+/// Plot colors of the filters U, B, V, R, I and C.
+const FILTER_COLORS: [(&str, (f64, f64, f64)); 6] = [
+    ("U", (0.5, 0.0, 0.8)),
+    ("B", (0.0, 0.2, 0.9)),
+    ("V", (0.0, 0.6, 0.0)),
+    ("R", (0.9, 0.1, 0.0)),
+    ("I", (0.5, 0.2, 0.1)),
+    ("C", (0.4, 0.4, 0.4)),
+];
+
+glib::wrapper! {
+    pub struct LightCurveWindow(ObjectSubclass<imp::LightCurveWindow>)
+    @extends Window, Widget,
+    @implements Accessible, Buildable, ConstraintTarget, Native, Root, ShortcutManager;
+}
+
+impl LightCurveWindow {
+    pub fn new(app: &Application, parent: &MainWindow) -> Self {
+        let result: Self = Object::builder()
+            .property("application", app)
+            .build();
+        result.set_transient_for(Some(parent));
+        result.load_stars();
+        result
+    }
+
+    fn setup_callbacks(&self) {
+        let imp = self.imp();
+        for plot in [&imp.pgm_plot, &imp.chk_plot] {
+            plot.set_labels("JD", "Δmag");
+            plot.set_invert_y(true);
+        }
+        // Both plots always show the same time range
+        imp.pgm_plot.connect_view_changed(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |view| {
+                window.imp().chk_plot.set_x_view(view);
+            }
+        ));
+        imp.chk_plot.connect_view_changed(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |view| {
+                window.imp().pgm_plot.set_x_view(view);
+            }
+        ));
+        imp.reset_zoom_button.connect_clicked(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| {
+                window.imp().pgm_plot.set_x_view(None);
+                window.imp().chk_plot.set_x_view(None);
+            }
+        ));
+        imp.star_dd.connect_selected_notify(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| {
+                window.show_selected();
+            }
+        ));
+    }
+
+    fn load_stars(&self) {
+        let stars = match Archive::open_default().and_then(|archive| archive.program_stars()) {
+            Ok(stars) => { stars }
+            Err(e) => {
+                show_error(Some(self), Some("Error Reading Archive"), e);
+                return;
+            }
+        };
+        let names = &self.imp().star_names;
+        let star_refs = stars.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+        names.splice(0, names.n_items(), &star_refs);
+        if stars.is_empty() {
+            self.imp().status_label.set_label("No PGM stars in the archive");
+        }
+    }
+
+    fn show_selected(&self) {
+        let imp = self.imp();
+        let Some(star) = imp.star_dd.selected_item().and_downcast::<StringObject>() else {
+            return;
+        };
+        let magnitudes = match Archive::open_default().and_then(|archive| archive.light_curve(&star.string())) {
+            Ok(magnitudes) => { magnitudes }
+            Err(e) => {
+                show_error(Some(self), Some("Error Reading Archive"), e);
+                return;
+            }
+        };
+        let (pgm, chk): (Vec<_>, Vec<_>) = magnitudes.into_iter().partition(|m| m.star_type == "PGM");
+        imp.status_label.set_label(&format!("{} PGM and {} CHK measurements", pgm.len(), chk.len()));
+        imp.pgm_plot.set_series(series_by_filter(&pgm, false));
+        imp.chk_plot.set_series(series_by_filter(&chk, true));
+        imp.pgm_plot.set_x_view(None);
+        imp.chk_plot.set_x_view(None);
+    }
+}
+
+/// One series per filter and, for check stars, per star.
+fn series_by_filter(magnitudes: &[DifferentialMagnitude], per_star: bool) -> Vec<Series> {
+    let mut stars = magnitudes.iter().map(|m| m.star_id.as_str()).collect::<Vec<_>>();
+    stars.sort();
+    stars.dedup();
+    let mut result = Vec::new();
+    for (filter, color) in FILTER_COLORS {
+        for star in stars.iter() {
+            let points = magnitudes.iter()
+                .filter(|m| m.filter == filter && m.star_id == *star)
+                .map(|m| PlotPoint { x: julian_date(m.timestamp), y: m.magnitude, error: m.magnitude_error })
+                .collect::<Vec<_>>();
+            if points.is_empty() {
+                continue;
+            }
+            let label = if per_star { format!("{} {}", star, filter) } else { filter.to_string() };
+            result.push(Series { label, color, points, connected: false });
+        }
+    }
+    result
+}
@@ -0,0 +1,50 @@
+use crate::ui::plot::PlotArea;
+use gtk::prelude::StaticTypeExt;
+use gtk::subclass::prelude::*;
+use gtk::{glib, CompositeTemplate, StringList, TemplateChild, Window};
+use gtk::glib::subclass::InitializingObject;
+
+#[derive(CompositeTemplate, Default)]
+#[template(resource = "/de/geselle_ffm/peprunner/light_curve_window.ui")]
+pub struct LightCurveWindow {
+    #[template_child]
+    pub star_dd: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub star_names: TemplateChild<StringList>,
+    #[template_child]
+    pub status_label: TemplateChild<gtk::Label>,
+    #[template_child]
+    pub reset_zoom_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub pgm_plot: TemplateChild<PlotArea>,
+    #[template_child]
+    pub chk_plot: TemplateChild<PlotArea>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for LightCurveWindow {
+    const NAME: &'static str = "LightCurveWindow";
+    type Type = super::LightCurveWindow;
+    type ParentType = Window;
+
+    fn class_init(klass: &mut Self::Class) {
+        PlotArea::ensure_type();
+        klass.bind_template();
+    }
+
+    fn instance_init(obj: &InitializingObject<Self>) {
+        obj.init_template();
+    }
+}
+
+impl ObjectImpl for LightCurveWindow {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        self.obj().setup_callbacks();
+    }
+}
+
+impl WidgetImpl for LightCurveWindow {}
+
+impl WindowImpl for LightCurveWindow {}
@@ -0,0 +1,307 @@
+use crate::archive::{self, Archive};
+use crate::qc;
+use crate::reduction::{read_log, LogRecord};
+use crate::rejection::{self, Rejection};
+use crate::ui::MainWindow;
+use crate::util::{ask_text, confirm, show_error, show_warning};
+use gtk::glib::{clone, BoxedAnyObject, Object};
+use gtk::prelude::{ButtonExt, Cast, CastNone, GtkWindowExt, ListItemExt, ListModelExt, SelectionModelExt, SettingsExt, WidgetExt};
+use gtk::subclass::prelude::ObjectSubclassIsExt;
+use gtk::{gio, glib, Accessible, Application, Buildable, ColumnViewColumn, ConstraintTarget, CustomSorter, Label, ListItem, MultiSelection, Native, Root, ShortcutManager, SignalListItemFactory, SortListModel, Widget, Window};
+use std::cmp::Ordering;
+use std::path::Path;
+
+mod imp;
+
+/// Spread above which a count triple is highlighted
+const HIGH_SPREAD: f64 = 3.0;
+
+glib::wrapper! {
+    pub struct LogWindow(ObjectSubclass<imp::LogWindow>)
+    @extends Window, Widget,
+    @implements Accessible, Buildable, ConstraintTarget, Native, Root, ShortcutManager;
+}
+
+impl LogWindow {
+    pub fn new(app: &Application, parent: &MainWindow, path: &Path) -> Option<Self> {
+        let records = match read_log(path) {
+            Ok(records) => { records }
+            Err(e) => {
+                show_error(Some(parent), Some("Error Reading Run Log"), e);
+                return None;
+            }
+        };
+        let rejections = match rejection::load(path) {
+            Ok(rejections) => { rejections }
+            Err(e) => {
+                show_error(Some(parent), Some("Error Reading Rejections"), e);
+                return None;
+            }
+        };
+
+        let result: Self = Object::builder()
+            .property("application", app)
+            .build();
+        result.set_transient_for(Some(parent));
+        result.set_title(Some(&format!("Run Log {}", path.file_name().unwrap_or_default().to_string_lossy())));
+        let imp = result.imp();
+        imp.path.set(path.to_path_buf()).expect("path shouldn't be set yet");
+        imp.rejections.replace(rejections.into_iter()
+            .filter(|r| records.get(r.row).is_some_and(|record| record.timestamp == r.timestamp))
+            .map(|r| (r.row, r.reason))
+            .collect());
+        let rows = result.rows();
+        for row in 0..records.len() {
+            rows.append(&BoxedAnyObject::new(row));
+        }
+        imp.records.replace(records);
+        result.update_status();
+        Some(result)
+    }
+
+    fn rows(&self) -> &gio::ListStore {
+        self.imp()
+            .rows
+            .get()
+            .expect("rows should already be initialized")
+    }
+
+    fn setup_columns(&self) {
+        let rows = gio::ListStore::new::<BoxedAnyObject>();
+        self.imp().rows.set(rows.clone()).expect("rows shouldn't be set yet");
+
+        let column_view = &self.imp().log_cv;
+        let sort_model = SortListModel::new(Some(rows), column_view.sorter());
+        column_view.set_model(Some(&MultiSelection::new(Some(sort_model))));
+
+        let by_record = |text: fn(&LogRecord) -> String, compare: fn(&LogRecord, &LogRecord) -> Ordering| {
+            (move |window: &LogWindow, row: usize| text(&window.imp().records.borrow()[row]),
+             move |window: &LogWindow, a: usize, b: usize| {
+                 let records = window.imp().records.borrow();
+                 compare(&records[a], &records[b])
+             })
+        };
+        let (text, compare) = by_record(|r| r.timestamp.format("%H:%M:%S").to_string(), |a, b| a.timestamp.cmp(&b.timestamp));
+        self.add_column("Time", text, compare);
+        let (text, compare) = by_record(|r| r.index.to_string(), |a, b| a.index.cmp(&b.index));
+        self.add_column("Item", text, compare);
+        let (text, compare) = by_record(|r| r.star_id.clone(), |a, b| a.star_id.cmp(&b.star_id));
+        self.add_column("Star", text, compare);
+        let (text, compare) = by_record(|r| r.star_type.clone(), |a, b| a.star_type.cmp(&b.star_type));
+        self.add_column("Type", text, compare);
+        let (text, compare) = by_record(|r| r.kind().as_str().to_string(), |a, b| a.kind().as_str().cmp(b.kind().as_str()));
+        self.add_column("Kind", text, compare);
+        let (text, compare) = by_record(|r| r.filter.clone(), |a, b| a.filter.cmp(&b.filter));
+        self.add_column("Filter", text, compare);
+        let (text, compare) = by_record(|r| r.integration_time.to_string(), |a, b| a.integration_time.cmp(&b.integration_time));
+        self.add_column("Int. Time", text, compare);
+        let (text, compare) = by_record(|r| r.count1.to_string(), |a, b| a.count1.cmp(&b.count1));
+        self.add_column("Count 1", text, compare);
+        let (text, compare) = by_record(|r| r.count2.to_string(), |a, b| a.count2.cmp(&b.count2));
+        self.add_column("Count 2", text, compare);
+        let (text, compare) = by_record(|r| r.count3.to_string(), |a, b| a.count3.cmp(&b.count3));
+        self.add_column("Count 3", text, compare);
+        let (text, compare) = by_record(|r| format!("{:.2}", r.spread()), |a, b| a.spread().total_cmp(&b.spread()));
+        self.add_column("Spread", text, compare);
+        let (text, compare) = by_record(|r| r.note.clone(), |a, b| a.note.cmp(&b.note));
+        self.add_column("Note", text, compare);
+        self.add_column("Rejected",
+                        |window, row| window.imp().rejections.borrow().get(&row).cloned().unwrap_or_default(),
+                        |window, a, b| {
+                            let rejections = window.imp().rejections.borrow();
+                            rejections.get(&a).cmp(&rejections.get(&b))
+                        });
+    }
+
+    /// Adds a sortable column. Rows are identified by their position in the log.
+    fn add_column<T, C>(&self, title: &str, text: T, compare: C)
+    where T: Fn(&LogWindow, usize) -> String + 'static,
+          C: Fn(&LogWindow, usize, usize) -> Ordering + 'static {
+        let factory = SignalListItemFactory::new();
+        factory.connect_setup(move |_, list_item| {
+            let label = Label::builder()
+                .xalign(0.0)
+                .margin_start(6)
+                .margin_end(6)
+                .build();
+            list_item
+                .downcast_ref::<ListItem>()
+                .expect("should be a ListItem")
+                .set_child(Some(&label));
+        });
+        factory.connect_bind(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_, list_item| {
+                let list_item = list_item
+                    .downcast_ref::<ListItem>()
+                    .expect("should be a ListItem");
+                let row = *list_item
+                    .item()
+                    .and_downcast::<BoxedAnyObject>()
+                    .expect("item should be a BoxedAnyObject")
+                    .borrow::<usize>();
+                let label = list_item
+                    .child()
+                    .and_downcast::<Label>()
+                    .expect("child should be a Label");
+                label.set_label(&text(&window, row));
+                let high_spread = window.imp().records.borrow()[row].spread() > HIGH_SPREAD;
+                let rejected = window.imp().rejections.borrow().contains_key(&row);
+                if high_spread { label.add_css_class("error") } else { label.remove_css_class("error") }
+                if rejected { label.add_css_class("dim-label") } else { label.remove_css_class("dim-label") }
+            }
+        ));
+
+        let sorter = CustomSorter::new(clone!(
+            #[weak(rename_to = window)]
+            self,
+            #[upgrade_or]
+            gtk::Ordering::Equal,
+            move |a, b| {
+                let row = |item: &Object| *item
+                    .downcast_ref::<BoxedAnyObject>()
+                    .expect("item should be a BoxedAnyObject")
+                    .borrow::<usize>();
+                compare(&window, row(a), row(b)).into()
+            }
+        ));
+        let column = ColumnViewColumn::new(Some(title), Some(factory));
+        column.set_sorter(Some(&sorter));
+        column.set_resizable(true);
+        self.imp().log_cv.append_column(&column);
+    }
+
+    fn setup_callbacks(&self) {
+        let imp = self.imp();
+        imp.reject_button.connect_clicked(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| {
+                window.handle_reject();
+            }
+        ));
+        imp.accept_button.connect_clicked(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| {
+                let rows = window.selected_rows();
+                for row in rows.iter() {
+                    window.imp().rejections.borrow_mut().remove(row);
+                }
+                window.rows_changed(&rows);
+            }
+        ));
+        imp.save_button.connect_clicked(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| {
+                window.save();
+            }
+        ));
+    }
+
+    /// Log rows of the selected table rows.
+    fn selected_rows(&self) -> Vec<usize> {
+        let selection = self.imp().log_cv
+            .model()
+            .and_downcast::<MultiSelection>()
+            .expect("model should be a MultiSelection");
+        (0..selection.n_items())
+            .filter(|pos| selection.is_selected(*pos))
+            .filter_map(|pos| selection.item(pos).and_downcast::<BoxedAnyObject>())
+            .map(|item| *item.borrow::<usize>())
+            .collect()
+    }
+
+    fn handle_reject(&self) {
+        let rows = self.selected_rows();
+        if rows.is_empty() {
+            show_error(Some(self), Some("No Rows Selected"), "Please select the rows to reject.");
+            return;
+        }
+        let window = self.clone();
+        ask_text(self, "Reason for Rejection", "e.g. passing cloud", move |reason| {
+            let Some(reason) = reason.map(|r| r.trim().to_string()).filter(|r| !r.is_empty()) else {
+                return;
+            };
+            for row in rows.iter() {
+                window.imp().rejections.borrow_mut().insert(*row, reason.clone());
+            }
+            window.rows_changed(&rows);
+        });
+    }
+
+    fn rows_changed(&self, rows: &[usize]) {
+        self.imp().modified.set(true);
+        // New items for the rows get bound again and sorted into place if sorted by rejection.
+        // The store holds the log rows in order, so a row's position is the row itself.
+        for row in rows {
+            self.rows().splice(*row as u32, 1, &[BoxedAnyObject::new(*row)]);
+        }
+        self.update_status();
+    }
+
+    fn update_status(&self) {
+        let imp = self.imp();
+        let records = imp.records.borrow();
+        let high_spread = records.iter().filter(|r| r.spread() > HIGH_SPREAD).count();
+        let modified = if imp.modified.get() { ", not saved" } else { "" };
+        imp.status_label.set_label(&format!("{} rows, {} with high spread, {} rejected{}",
+                                            records.len(), high_spread, imp.rejections.borrow().len(), modified));
+    }
+
+    fn save(&self) -> bool {
+        let imp = self.imp();
+        let records = imp.records.borrow();
+        let mut rejections = imp.rejections.borrow()
+            .iter()
+            .map(|(row, reason)| Rejection { row: *row, timestamp: records[*row].timestamp, reason: reason.clone() })
+            .collect::<Vec<_>>();
+        rejections.sort_by_key(|r| r.row);
+        drop(records);
+        match rejection::save(imp.path.get().expect("path should be set"), &rejections) {
+            Ok(_) => {
+                imp.modified.set(false);
+                self.update_status();
+                if let Err(e) = self.update_archive() {
+                    show_error(Some(self), Some("Error Updating Archive"), e);
+                }
+                true
+            }
+            Err(e) => {
+                show_error(Some(self), Some("Error Saving Rejections"), e);
+                false
+            }
+        }
+    }
+
+    /// Takes the saved rejections into the archived run of the log and checks its check stars
+    /// again. Logs that aren't archived are left alone, they get the rejections when imported.
+    fn update_archive(&self) -> Result<(), archive::Error> {
+        let mut archive = Archive::open_default()?;
+        let Some(run_id) = archive.apply_rejections(self.imp().path.get().expect("path should be set"))? else {
+            return Ok(());
+        };
+        let Some(main_window) = self.transient_for().and_downcast::<MainWindow>() else {
+            return Ok(());
+        };
+        let results = qc::check_run(&archive, run_id, main_window.catalog().as_ref(),
+                                    main_window.settings().double("check-tolerance"))?;
+        if results.iter().any(|r| r.flagged) {
+            show_warning(Some(self), Some("Check Star QC Failed"), qc::report(&results));
+        }
+        Ok(())
+    }
+
+    fn ask_save_on_close(&self) {
+        let window = self.clone();
+        confirm(self, "Unsaved Rejections", "Save the rejections before closing?", move |save| {
+            if save && !window.save() {
+                return;
+            }
+            window.imp().modified.set(false);
+            window.close();
+        });
+    }
+}
@@ -0,0 +1,66 @@
+use crate::reduction::LogRecord;
+use gtk::subclass::prelude::*;
+use gtk::{gio, glib, CompositeTemplate, TemplateChild, Window};
+use gtk::glib::subclass::InitializingObject;
+use gtk::glib::Propagation;
+use std::cell::{Cell, OnceCell, RefCell};
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+#[derive(CompositeTemplate, Default)]
+#[template(resource = "/de/geselle_ffm/peprunner/log_window.ui")]
+pub struct LogWindow {
+    #[template_child]
+    pub log_cv: TemplateChild<gtk::ColumnView>,
+    #[template_child]
+    pub status_label: TemplateChild<gtk::Label>,
+    #[template_child]
+    pub reject_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub accept_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub save_button: TemplateChild<gtk::Button>,
+    pub path: OnceCell<PathBuf>,
+    pub records: RefCell<Vec<LogRecord>>,
+    /// Rejection reasons by log row
+    pub rejections: RefCell<HashMap<usize, String>>,
+    pub rows: OnceCell<gio::ListStore>,
+    pub modified: Cell<bool>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for LogWindow {
+    const NAME: &'static str = "LogWindow";
+    type Type = super::LogWindow;
+    type ParentType = Window;
+
+    fn class_init(klass: &mut Self::Class) {
+        klass.bind_template();
+    }
+
+    fn instance_init(obj: &InitializingObject<Self>) {
+        obj.init_template();
+    }
+}
+
+impl ObjectImpl for LogWindow {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.setup_columns();
+        obj.setup_callbacks();
+    }
+}
+
+impl WidgetImpl for LogWindow {}
+
+impl WindowImpl for LogWindow {
+    fn close_request(&self) -> Propagation {
+        if self.modified.get() {
+            self.obj().ask_save_on_close();
+            return Propagation::Stop;
+        }
+        self.parent_close_request()
+    }
+}
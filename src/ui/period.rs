@@ -0,0 +1,246 @@
+use crate::archive::Archive;
+use crate::astro::julian_date;
+use crate::period::{phase, Observation, Periodogram};
+use crate::ui::plot::{PlotPoint, Series};
+use crate::ui::MainWindow;
+use crate::util::show_error;
+use gtk::glib::{clone, Object};
+use gtk::prelude::{ButtonExt, Cast, CastNone, EntryBufferExtManual, EntryExt, GtkWindowExt, ListItemExt, ListModelExt, SelectionModelExt, WidgetExt};
+use gtk::subclass::prelude::ObjectSubclassIsExt;
+use gtk::{gio, glib, Accessible, Application, Buildable, ConstraintTarget, Label, ListItem, Native, Root, ShortcutManager, SignalListItemFactory, SingleSelection, StringList, StringObject, Widget, Window, INVALID_LIST_POSITION};
+
+mod imp;
+
+/// Number of candidates listed per method
+const CANDIDATES: usize = 5;
+
+glib::wrapper! {
+    pub struct PeriodWindow(ObjectSubclass<imp::PeriodWindow>)
+    @extends Window, Widget,
+    @implements Accessible, Buildable, ConstraintTarget, Native, Root, ShortcutManager;
+}
+
+impl PeriodWindow {
+    pub fn new(app: &Application, parent: &MainWindow) -> Self {
+        let result: Self = Object::builder()
+            .property("application", app)
+            .build();
+        result.set_transient_for(Some(parent));
+        result.load_stars();
+        result
+    }
+
+    fn setup_candidates(&self) {
+        let lines = StringList::new(&[]);
+        self.imp().candidate_lines.set(lines.clone()).expect("candidate lines shouldn't be set yet");
+
+        let factory = SignalListItemFactory::new();
+        factory.connect_setup(move |_, list_item| {
+            let label = Label::builder()
+                .xalign(0.0)
+                .margin_top(3)
+                .margin_bottom(3)
+                .margin_start(6)
+                .margin_end(6)
+                .css_classes(["monospace"])
+                .build();
+            list_item
+                .downcast_ref::<ListItem>()
+                .expect("should be a ListItem")
+                .set_child(Some(&label));
+        });
+        factory.connect_bind(move |_, list_item| {
+            let list_item = list_item
+                .downcast_ref::<ListItem>()
+                .expect("should be a ListItem");
+            let line = list_item
+                .item()
+                .and_downcast::<StringObject>()
+                .expect("item should be a StringObject")
+                .string();
+            list_item
+                .child()
+                .and_downcast::<Label>()
+                .expect("child should be a Label")
+                .set_label(&line);
+        });
+
+        let selection_model = SingleSelection::new(Some(lines));
+        selection_model.set_autoselect(false);
+        selection_model.set_can_unselect(true);
+        selection_model.connect_selection_changed(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |selection, _, _| {
+                window.select_candidate(selection.selected());
+            }
+        ));
+        self.imp().candidate_list_vw.set_model(Some(&selection_model));
+        self.imp().candidate_list_vw.set_factory(Some(&factory));
+    }
+
+    fn setup_callbacks(&self) {
+        let imp = self.imp();
+        imp.periodogram_plot.set_labels("Frequency (1/d)", "Power, Θ");
+        imp.folded_plot.set_labels("Phase", "Δmag");
+        imp.folded_plot.set_invert_y(true);
+        imp.search_button.connect_clicked(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| {
+                window.search();
+            }
+        ));
+        imp.fold_button.connect_clicked(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| {
+                window.fold();
+            }
+        ));
+    }
+
+    fn load_stars(&self) {
+        let stars = match Archive::open_default().and_then(|archive| archive.program_stars()) {
+            Ok(stars) => { stars }
+            Err(e) => {
+                show_error(Some(self), Some("Error Reading Archive"), e);
+                return;
+            }
+        };
+        let names = &self.imp().star_names;
+        let star_refs = stars.iter().map(|s| s.as_str()).collect::<Vec<_>>();
+        names.splice(0, names.n_items(), &star_refs);
+    }
+
+    fn candidate_lines(&self) -> &StringList {
+        self.imp()
+            .candidate_lines
+            .get()
+            .expect("candidate lines should already be initialized")
+    }
+
+    fn selected_string(dropdown: &gtk::DropDown) -> Option<String> {
+        dropdown.selected_item()
+            .and_downcast::<StringObject>()
+            .map(|s| s.string().to_string())
+    }
+
+    fn search(&self) {
+        let imp = self.imp();
+        let Some(star) = Self::selected_string(&imp.star_dd) else {
+            show_error(Some(self), Some("No Star"), "Please select a PGM star.");
+            return;
+        };
+        let filter = Self::selected_string(&imp.filter_dd).expect("something should always be selected in a DropDown");
+        let min_period = imp.min_period_sb.value();
+        let max_period = imp.max_period_sb.value();
+        if min_period >= max_period {
+            show_error(Some(self), Some("Invalid Period Range"), "The shortest period must be below the longest.");
+            return;
+        }
+        let magnitudes = match Archive::open_default().and_then(|archive| archive.light_curve(&star)) {
+            Ok(magnitudes) => { magnitudes }
+            Err(e) => {
+                show_error(Some(self), Some("Error Reading Archive"), e);
+                return;
+            }
+        };
+        let observations = magnitudes.iter()
+            .filter(|m| m.star_type == "PGM" && m.filter == filter)
+            .map(|m| Observation { time: julian_date(m.timestamp), magnitude: m.magnitude })
+            .collect::<Vec<_>>();
+        if observations.len() < 3 {
+            show_error(Some(self), Some("Too Few Measurements"),
+                       format!("{} has only {} measurements in {}.", star, observations.len(), filter));
+            return;
+        }
+
+        imp.search_button.set_sensitive(false);
+        imp.status_label.set_label(&format!("Searching {} measurements…", observations.len()));
+        glib::spawn_future_local(clone!(
+            #[weak(rename_to = window)]
+            self,
+            async move {
+                let result = gio::spawn_blocking(move || {
+                    let periodogram = Periodogram::compute(&observations, min_period, max_period);
+                    (observations, periodogram)
+                }).await;
+                window.imp().search_button.set_sensitive(true);
+                match result {
+                    Ok((observations, periodogram)) => {
+                        window.show_periodogram(observations, periodogram);
+                    }
+                    Err(_) => {
+                        window.imp().status_label.set_label("");
+                        show_error(Some(&window), Some("Period Search Failed"), "The period search stopped unexpectedly.");
+                    }
+                }
+            }
+        ));
+    }
+
+    fn show_periodogram(&self, observations: Vec<Observation>, periodogram: Periodogram) {
+        let imp = self.imp();
+        let candidates = periodogram.candidates(CANDIDATES);
+        let to_points = |statistic: &[f64]| periodogram.frequencies.iter()
+            .zip(statistic)
+            .map(|(f, s)| PlotPoint { x: *f, y: *s, error: 0.0 })
+            .collect::<Vec<_>>();
+        imp.periodogram_plot.set_series(vec![
+            Series { label: "Lomb-Scargle power".to_string(), color: (0.0, 0.2, 0.9), points: to_points(&periodogram.power), connected: true },
+            Series { label: "PDM Θ".to_string(), color: (0.9, 0.1, 0.0), points: to_points(&periodogram.theta), connected: true },
+        ]);
+        imp.periodogram_plot.set_x_view(None);
+
+        let lines = candidates.iter()
+            .map(|c| format!("{:<13}{:>12.5} d {:>7.3}", c.method.name(), c.period, c.statistic))
+            .collect::<Vec<_>>();
+        let line_refs = lines.iter().map(|l| l.as_str()).collect::<Vec<_>>();
+        let candidate_lines = self.candidate_lines();
+        candidate_lines.splice(0, candidate_lines.n_items(), &line_refs);
+
+        // Minimum light as default epoch, as is usual for eclipsing binaries
+        let faintest = observations.iter().max_by(|a, b| a.magnitude.total_cmp(&b.magnitude)).unwrap();
+        imp.epoch_entry.buffer().set_text(format!("{:.5}", faintest.time));
+        imp.status_label.set_label(&format!("{} measurements, {} trial frequencies",
+                                            observations.len(), periodogram.frequencies.len()));
+        imp.observations.replace(observations);
+        imp.candidates.replace(candidates);
+    }
+
+    fn select_candidate(&self, position: u32) {
+        if position == INVALID_LIST_POSITION {
+            return;
+        }
+        let Some(candidate) = self.imp().candidates.borrow().get(position as usize).copied() else {
+            return;
+        };
+        self.imp().period_entry.buffer().set_text(format!("{:.6}", candidate.period));
+        self.fold();
+    }
+
+    fn fold(&self) {
+        let imp = self.imp();
+        let parse = |entry: &gtk::Entry| entry.buffer().text().trim().parse::<f64>().ok();
+        let Some(period) = parse(&imp.period_entry).filter(|p| *p > 0.0) else {
+            show_error(Some(self), Some("Invalid Period"), "Please enter a positive period in days.");
+            return;
+        };
+        let Some(epoch) = parse(&imp.epoch_entry) else {
+            show_error(Some(self), Some("Invalid Epoch"), "Please enter the epoch as JD.");
+            return;
+        };
+        // Two cycles make features at phase 0 easier to see
+        let points = imp.observations.borrow().iter()
+            .flat_map(|o| {
+                let phase = phase(o.time, period, epoch);
+                [PlotPoint { x: phase, y: o.magnitude, error: 0.0 }, PlotPoint { x: phase + 1.0, y: o.magnitude, error: 0.0 }]
+            })
+            .collect::<Vec<_>>();
+        imp.folded_plot.set_series(vec![
+            Series { label: format!("P = {:.5} d", period), color: (0.0, 0.6, 0.0), points, connected: false },
+        ]);
+        imp.folded_plot.set_x_view(None);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::period::{Candidate, Observation};
+use crate::ui::plot::PlotArea;
+use gtk::prelude::StaticTypeExt;
+use gtk::subclass::prelude::*;
+use gtk::{glib, CompositeTemplate, StringList, TemplateChild, Window};
+use gtk::glib::subclass::InitializingObject;
+use std::cell::{OnceCell, RefCell};
+
+#[derive(CompositeTemplate, Default)]
+#[template(resource = "/de/geselle_ffm/peprunner/period_window.ui")]
+pub struct PeriodWindow {
+    #[template_child]
+    pub star_dd: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub star_names: TemplateChild<StringList>,
+    #[template_child]
+    pub filter_dd: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub min_period_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub max_period_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub search_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub periodogram_plot: TemplateChild<PlotArea>,
+    #[template_child]
+    pub candidate_list_vw: TemplateChild<gtk::ListView>,
+    #[template_child]
+    pub period_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub epoch_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub fold_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub folded_plot: TemplateChild<PlotArea>,
+    #[template_child]
+    pub status_label: TemplateChild<gtk::Label>,
+    pub observations: RefCell<Vec<Observation>>,
+    pub candidates: RefCell<Vec<Candidate>>,
+    pub candidate_lines: OnceCell<StringList>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for PeriodWindow {
+    const NAME: &'static str = "PeriodWindow";
+    type Type = super::PeriodWindow;
+    type ParentType = Window;
+
+    fn class_init(klass: &mut Self::Class) {
+        PlotArea::ensure_type();
+        klass.bind_template();
+    }
+
+    fn instance_init(obj: &InitializingObject<Self>) {
+        obj.init_template();
+    }
+}
+
+impl ObjectImpl for PeriodWindow {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.setup_candidates();
+        obj.setup_callbacks();
+    }
+}
+
+impl WidgetImpl for PeriodWindow {}
+
+impl WindowImpl for PeriodWindow {}
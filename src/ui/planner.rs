@@ -0,0 +1,245 @@
+use crate::astro::{airmass, Equatorial, Site};
+use crate::catalog::Catalog;
+use crate::common::PepRun;
+use crate::planner::{self, Night, Visibility};
+use crate::ui::plot::{PlotPoint, Series};
+use crate::ui::MainWindow;
+use crate::util::{show_error, show_warning};
+use chrono::{DateTime, NaiveDate, NaiveTime, TimeDelta, Timelike, Utc};
+use gtk::gio::Settings;
+use gtk::glib::{clone, Object};
+use gtk::prelude::{ButtonExt, Cast, CastNone, EntryBufferExtManual, EntryExt, GtkWindowExt, ListItemExt, ListModelExt, SettingsExt};
+use gtk::subclass::prelude::ObjectSubclassIsExt;
+use gtk::{glib, Accessible, Application, Buildable, ConstraintTarget, Label, ListItem, Native, Root, ShortcutManager, SignalListItemFactory, StringObject, Widget, Window};
+use std::collections::HashSet;
+
+mod imp;
+
+const CURVE_COLORS: [(f64, f64, f64); 8] = [(0.0, 0.2, 0.9), (0.9, 0.1, 0.0), (0.0, 0.6, 0.0), (0.8, 0.5, 0.0),
+    (0.5, 0.0, 0.7), (0.0, 0.6, 0.7), (0.6, 0.3, 0.1), (0.9, 0.0, 0.6)];
+const LIMIT_COLOR: (f64, f64, f64) = (0.6, 0.6, 0.6);
+const RUN_COLOR: (f64, f64, f64) = (0.0, 0.0, 0.0);
+/// Position of "Current Run" in the source drop down
+const SOURCE_RUN: u32 = 0;
+
+glib::wrapper! {
+    pub struct PlannerWindow(ObjectSubclass<imp::PlannerWindow>)
+    @extends Window, Widget,
+    @implements Accessible, Buildable, ConstraintTarget, Native, Root, ShortcutManager;
+}
+
+impl PlannerWindow {
+    /// `duration` is the estimate of `run`, the planner's default for its length.
+    pub fn new(app: &Application, parent: &MainWindow, run: PepRun, catalog: Option<Catalog>, duration: TimeDelta,
+               settings: Settings) -> Self {
+        let result: Self = Object::builder()
+            .property("application", app)
+            .build();
+        result.set_transient_for(Some(parent));
+        result.imp().run.replace(run);
+        result.imp().catalog.replace(catalog);
+        result.imp().settings.set(settings).expect("settings shouldn't be set yet");
+        result.imp().date_entry.buffer().set_text(Utc::now().format("%Y-%m-%d").to_string());
+        if duration > TimeDelta::zero() {
+            result.imp().duration_sb.set_value(((duration.num_seconds() + 59) / 60) as f64);
+        }
+        result
+    }
+
+    fn setup_lines(&self) {
+        let factory = SignalListItemFactory::new();
+        factory.connect_setup(move |_, list_item| {
+            let label = Label::builder()
+                .xalign(0.0)
+                .margin_top(3)
+                .margin_bottom(3)
+                .margin_start(6)
+                .margin_end(6)
+                .css_classes(["monospace"])
+                .build();
+            list_item
+                .downcast_ref::<ListItem>()
+                .expect("should be a ListItem")
+                .set_child(Some(&label));
+        });
+        factory.connect_bind(move |_, list_item| {
+            let list_item = list_item
+                .downcast_ref::<ListItem>()
+                .expect("should be a ListItem");
+            let line = list_item
+                .item()
+                .and_downcast::<StringObject>()
+                .expect("item should be a StringObject")
+                .string();
+            list_item
+                .child()
+                .and_downcast::<Label>()
+                .expect("child should be a Label")
+                .set_label(&line);
+        });
+        self.imp().visibility_list_vw.set_factory(Some(&factory));
+        self.imp().header_label.set_label(&format!("{:<12}{:>9}{:>10}{:>9}{:>8}{:>7}{:>15}{:>7}",
+                                                   "Star", "RA", "Dec", "Transit", "MaxAlt", "MinX", "Window", "Moon"));
+    }
+
+    fn setup_callbacks(&self) {
+        let imp = self.imp();
+        imp.altitude_plot.set_labels("UT (h, above 24 next day)", "Altitude (°)");
+        imp.compute_button.connect_clicked(clone!(
+            #[weak(rename_to = window)]
+            self,
+            move |_| {
+                window.compute();
+            }
+        ));
+    }
+
+    fn settings(&self) -> &Settings {
+        self.imp().settings.get().expect("settings should already be initialized")
+    }
+
+    fn site(&self) -> Site {
+        Site {
+            latitude: self.settings().double("site-latitude"),
+            longitude: self.settings().double("site-longitude"),
+        }
+    }
+
+    /// Stars with known coordinates, and the names of those without.
+    fn stars(&self) -> (Vec<(String, Equatorial)>, Vec<String>) {
+        let catalog = self.imp().catalog.borrow();
+        let Some(catalog) = catalog.as_ref() else {
+            return (Vec::new(), Vec::new());
+        };
+        if self.imp().source_dd.selected() != SOURCE_RUN {
+            let stars = catalog.stars().iter()
+                .filter_map(|star| star.position().map(|position| (star.name.clone(), position)))
+                .collect();
+            return (stars, Vec::new());
+        }
+        let mut seen = HashSet::new();
+        let mut stars = Vec::new();
+        let mut unknown = Vec::new();
+        for star in self.imp().run.borrow().items.iter() {
+            if !seen.insert(star.name.to_lowercase()) {
+                continue;
+            }
+            match catalog.position(&star.name) {
+                Some(position) => { stars.push((star.name.clone(), position)); }
+                None => { unknown.push(star.name.clone()); }
+            }
+        }
+        (stars, unknown)
+    }
+
+    fn compute(&self) {
+        let imp = self.imp();
+        let Ok(date) = NaiveDate::parse_from_str(imp.date_entry.buffer().text().trim(), "%Y-%m-%d") else {
+            show_error(Some(self), Some("Invalid Date"), "Please enter the date as YYYY-MM-DD.");
+            return;
+        };
+        if imp.catalog.borrow().is_none() {
+            show_error(Some(self), Some("No Catalog"), "Please configure a catalog with the coordinates of the stars.");
+            return;
+        }
+        let site = self.site();
+        let Some(night) = planner::night(&site, date) else {
+            show_error(Some(self), Some("No Night"), "The sun doesn't set far enough for a dark sky at the site.");
+            return;
+        };
+        let min_altitude = self.settings().double("min-altitude");
+        let (stars, unknown) = self.stars();
+        let visibilities = planner::plan(&stars, &site, &night, min_altitude);
+        let midnight = date.and_time(NaiveTime::MIN).and_utc();
+        let hours = |t: DateTime<Utc>| (t - midnight).num_seconds() as f64 / 3600.0;
+
+        let run_span = self.run_span(date, &night);
+        let mut series = visibilities.iter().enumerate()
+            .map(|(k, visibility)| Series {
+                label: visibility.name.clone(),
+                color: CURVE_COLORS[k % CURVE_COLORS.len()],
+                points: visibility.altitudes.iter()
+                    .filter(|(_, altitude)| *altitude >= 0.0)
+                    .map(|(t, altitude)| PlotPoint { x: hours(*t), y: *altitude, error: 0.0 })
+                    .collect(),
+                connected: true,
+            })
+            .collect::<Vec<_>>();
+        let limit = [hours(night.start), hours(night.end)].map(|x| PlotPoint { x, y: min_altitude, error: 0.0 });
+        series.push(Series { label: format!("{:.0}° limit", min_altitude), color: LIMIT_COLOR, points: limit.to_vec(), connected: true });
+        if let Some((start, end)) = run_span {
+            for (k, t) in [start, end].into_iter().enumerate() {
+                let x = hours(t);
+                series.push(Series {
+                    label: if k == 0 { "Run".to_string() } else { String::new() },
+                    color: RUN_COLOR,
+                    points: vec![PlotPoint { x, y: 0.0, error: 0.0 }, PlotPoint { x, y: 90.0, error: 0.0 }],
+                    connected: true,
+                });
+            }
+        }
+        imp.altitude_plot.set_series(series);
+        imp.altitude_plot.set_x_view(Some((hours(night.start) - 0.5, hours(night.end) + 0.5)));
+
+        let lines = visibilities.iter().map(|v| self.line(v)).collect::<Vec<_>>();
+        let line_refs = lines.iter().map(|l| l.as_str()).collect::<Vec<_>>();
+        imp.visibility_lines.splice(0, imp.visibility_lines.n_items(), &line_refs);
+
+        let mut status = format!("Night from {} to {} UT, window above {:.0}°", night.start.format("%H:%M"), night.end.format("%H:%M"), min_altitude);
+        if let Some(limit) = airmass(min_altitude) {
+            status.push_str(&format!(" (airmass below {:.2})", limit));
+        }
+        if !unknown.is_empty() {
+            status.push_str(&format!(". No coordinates in the catalog for {}", unknown.join(", ")));
+        }
+        imp.status_label.set_label(&status);
+
+        if let Some((start, end)) = run_span {
+            if imp.source_dd.selected() == SOURCE_RUN {
+                self.warn_low_stars(&visibilities, &site, min_altitude, start, end);
+            }
+        }
+    }
+
+    /// Start and end of the planned run, the start defaulting to the beginning of the night.
+    fn run_span(&self, date: NaiveDate, night: &Night) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let imp = self.imp();
+        let text = imp.start_entry.buffer().text().trim().to_string();
+        let start = if text.is_empty() {
+            night.start
+        } else {
+            let Ok(time) = NaiveTime::parse_from_str(&text, "%H:%M") else {
+                show_error(Some(self), Some("Invalid Start"), "Please enter the start of the run as HH:MM UT.");
+                return None;
+            };
+            let day = if time.hour() < 12 { date.succ_opt()? } else { date };
+            day.and_time(time).and_utc()
+        };
+        let duration = TimeDelta::minutes(imp.duration_sb.value_as_int() as i64);
+        Some((start, start + duration))
+    }
+
+    fn warn_low_stars(&self, visibilities: &[Visibility], site: &Site, min_altitude: f64, start: DateTime<Utc>, end: DateTime<Utc>) {
+        let low = visibilities.iter()
+            .filter_map(|v| v.first_below(site, min_altitude, start, end).map(|t| format!("{} from {} UT", v.name, t.format("%H:%M"))))
+            .collect::<Vec<_>>();
+        if !low.is_empty() {
+            show_warning(Some(self), Some("Stars Below Altitude Limit"),
+                         format!("During the run from {} to {} UT these stars are below {:.0}°:\n{}",
+                                 start.format("%H:%M"), end.format("%H:%M"), min_altitude, low.join("\n")));
+        }
+    }
+
+    fn line(&self, visibility: &Visibility) -> String {
+        let window = match visibility.window {
+            Some((from, to)) => { format!("{}–{}", from.format("%H:%M"), to.format("%H:%M")) }
+            None => { "never".to_string() }
+        };
+        let min_airmass = visibility.min_airmass().map(|x| format!("{:.2}", x)).unwrap_or_else(|| "-".to_string());
+        let (ra, dec) = (visibility.position.ra_hours, visibility.position.dec_degrees);
+        format!("{:<12}{:>9}{:>10}{:>9}{:>7.1}°{:>7}{:>15}{:>6.0}°",
+                visibility.name, format!("{:.3}h", ra), format!("{:+.2}°", dec), visibility.transit.format("%H:%M").to_string(),
+                visibility.max_altitude, min_airmass, window, visibility.moon_separation)
+    }
+}
+
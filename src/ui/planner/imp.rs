@@ -0,0 +1,67 @@
+use crate::catalog::Catalog;
+use crate::common::PepRun;
+use crate::ui::plot::PlotArea;
+use gtk::gio::Settings;
+use gtk::prelude::StaticTypeExt;
+use gtk::subclass::prelude::*;
+use gtk::{glib, CompositeTemplate, StringList, TemplateChild, Window};
+use gtk::glib::subclass::InitializingObject;
+use std::cell::{OnceCell, RefCell};
+
+#[derive(CompositeTemplate, Default)]
+#[template(resource = "/de/geselle_ffm/peprunner/planner_window.ui")]
+pub struct PlannerWindow {
+    #[template_child]
+    pub date_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub source_dd: TemplateChild<gtk::DropDown>,
+    #[template_child]
+    pub start_entry: TemplateChild<gtk::Entry>,
+    #[template_child]
+    pub duration_sb: TemplateChild<gtk::SpinButton>,
+    #[template_child]
+    pub compute_button: TemplateChild<gtk::Button>,
+    #[template_child]
+    pub altitude_plot: TemplateChild<PlotArea>,
+    #[template_child]
+    pub header_label: TemplateChild<gtk::Label>,
+    #[template_child]
+    pub visibility_list_vw: TemplateChild<gtk::ListView>,
+    #[template_child]
+    pub visibility_lines: TemplateChild<StringList>,
+    #[template_child]
+    pub status_label: TemplateChild<gtk::Label>,
+    pub run: RefCell<PepRun>,
+    pub catalog: RefCell<Option<Catalog>>,
+    pub settings: OnceCell<Settings>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for PlannerWindow {
+    const NAME: &'static str = "PlannerWindow";
+    type Type = super::PlannerWindow;
+    type ParentType = Window;
+
+    fn class_init(klass: &mut Self::Class) {
+        PlotArea::ensure_type();
+        klass.bind_template();
+    }
+
+    fn instance_init(obj: &InitializingObject<Self>) {
+        obj.init_template();
+    }
+}
+
+impl ObjectImpl for PlannerWindow {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        let obj = self.obj();
+        obj.setup_lines();
+        obj.setup_callbacks();
+    }
+}
+
+impl WidgetImpl for PlannerWindow {}
+
+impl WindowImpl for PlannerWindow {}
@@ -0,0 +1,332 @@
+use gtk::cairo::Context;
+use gtk::glib::{clone, Object};
+use gtk::prelude::{DrawingAreaExt, DrawingAreaExtManual, GestureDragExt, GestureSingleExt, WidgetExt};
+use gtk::subclass::prelude::ObjectSubclassIsExt;
+use gtk::{glib, Accessible, Buildable, ConstraintTarget, DrawingArea, EventControllerMotion, EventControllerScroll, EventControllerScrollFlags, GestureClick, GestureDrag, Widget};
+use gtk::glib::Propagation;
+
+mod imp;
+
+const MARGIN_LEFT: f64 = 70.0;
+const MARGIN_RIGHT: f64 = 20.0;
+const MARGIN_TOP: f64 = 20.0;
+const MARGIN_BOTTOM: f64 = 45.0;
+const ZOOM_STEP: f64 = 1.2;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PlotPoint {
+    pub x: f64,
+    pub y: f64,
+    /// Drawn as error bar if positive
+    pub error: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Series {
+    pub label: String,
+    pub color: (f64, f64, f64),
+    pub points: Vec<PlotPoint>,
+    /// Points are joined by lines instead of being drawn as markers
+    pub connected: bool,
+}
+
+glib::wrapper! {
+    pub struct PlotArea(ObjectSubclass<imp::PlotArea>)
+    @extends DrawingArea, Widget,
+    @implements Accessible, Buildable, ConstraintTarget;
+}
+
+impl Default for PlotArea {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PlotArea {
+    pub fn new() -> Self {
+        Object::builder().build()
+    }
+
+    fn setup(&self) {
+        self.set_content_width(400);
+        self.set_content_height(200);
+        self.set_draw_func(clone!(
+            #[weak(rename_to = plot)]
+            self,
+            move |_, cr, width, height| {
+                // Drawing errors leave the area incomplete but aren't worth reporting
+                let _ = plot.draw(cr, width as f64, height as f64);
+            }
+        ));
+
+        let motion = EventControllerMotion::new();
+        motion.connect_motion(clone!(
+            #[weak(rename_to = plot)]
+            self,
+            move |_, x, _| {
+                plot.imp().pointer_x.set(x);
+            }
+        ));
+        self.add_controller(motion);
+
+        let scroll = EventControllerScroll::new(EventControllerScrollFlags::VERTICAL);
+        scroll.connect_scroll(clone!(
+            #[weak(rename_to = plot)]
+            self,
+            #[upgrade_or]
+            Propagation::Proceed,
+            move |_, _, dy| {
+                plot.zoom(ZOOM_STEP.powf(dy));
+                Propagation::Stop
+            }
+        ));
+        self.add_controller(scroll);
+
+        let drag = GestureDrag::new();
+        drag.connect_drag_begin(clone!(
+            #[weak(rename_to = plot)]
+            self,
+            move |_, _, _| {
+                plot.imp().drag_view.set(plot.x_range());
+            }
+        ));
+        drag.connect_drag_update(clone!(
+            #[weak(rename_to = plot)]
+            self,
+            move |_, offset_x, _| {
+                if let Some((x0, x1)) = plot.imp().drag_view.get() {
+                    let shift = -offset_x / plot.plot_width() * (x1 - x0);
+                    plot.change_view(Some((x0 + shift, x1 + shift)));
+                }
+            }
+        ));
+        drag.connect_drag_end(clone!(
+            #[weak(rename_to = plot)]
+            self,
+            move |_, _, _| {
+                plot.imp().drag_view.set(None);
+            }
+        ));
+        self.add_controller(drag);
+
+        let click = GestureClick::new();
+        click.set_button(1);
+        click.connect_pressed(clone!(
+            #[weak(rename_to = plot)]
+            self,
+            move |_, n_press, _, _| {
+                if n_press == 2 {
+                    plot.change_view(None);
+                }
+            }
+        ));
+        self.add_controller(click);
+    }
+
+    pub fn set_series(&self, series: Vec<Series>) {
+        self.imp().series.replace(series);
+        self.queue_draw();
+    }
+
+    pub fn set_labels(&self, x_label: &str, y_label: &str) {
+        self.imp().x_label.replace(x_label.to_string());
+        self.imp().y_label.replace(y_label.to_string());
+        self.queue_draw();
+    }
+
+    /// Let y grow downwards, as is usual for magnitudes.
+    pub fn set_invert_y(&self, invert_y: bool) {
+        self.imp().invert_y.set(invert_y);
+        self.queue_draw();
+    }
+
+    /// Sets the visible x range, None shows all data. Doesn't notify view change listeners.
+    pub fn set_x_view(&self, view: Option<(f64, f64)>) {
+        self.imp().x_view.set(view);
+        self.queue_draw();
+    }
+
+    /// Called when the user zooms or pans.
+    pub fn connect_view_changed<F>(&self, callback: F)
+    where F: Fn(Option<(f64, f64)>) + 'static {
+        self.imp().view_changed.replace(Some(Box::new(callback)));
+    }
+
+    fn change_view(&self, view: Option<(f64, f64)>) {
+        self.set_x_view(view);
+        if let Some(callback) = self.imp().view_changed.borrow().as_ref() {
+            callback(view);
+        }
+    }
+
+    fn zoom(&self, factor: f64) {
+        let Some((x0, x1)) = self.x_range() else {
+            return;
+        };
+        let fraction = ((self.imp().pointer_x.get() - MARGIN_LEFT) / self.plot_width()).clamp(0.0, 1.0);
+        let center = x0 + fraction * (x1 - x0);
+        self.change_view(Some((center - (center - x0) * factor, center + (x1 - center) * factor)));
+    }
+
+    fn plot_width(&self) -> f64 {
+        (self.width() as f64 - MARGIN_LEFT - MARGIN_RIGHT).max(1.0)
+    }
+
+    /// Visible x range, by default all data with a small border.
+    fn x_range(&self) -> Option<(f64, f64)> {
+        if let Some(view) = self.imp().x_view.get() {
+            return Some(view);
+        }
+        let series = self.imp().series.borrow();
+        let xs = series.iter().flat_map(|s| s.points.iter().map(|p| p.x));
+        let (x0, x1) = bounds(xs)?;
+        let border = ((x1 - x0) * 0.02).max(1e-3);
+        Some((x0 - border, x1 + border))
+    }
+
+    /// Range of the points visible in the x range, including their error bars.
+    fn y_range(&self, x0: f64, x1: f64) -> Option<(f64, f64)> {
+        let series = self.imp().series.borrow();
+        let visible = series.iter()
+            .flat_map(|s| s.points.iter())
+            .filter(|p| p.x >= x0 && p.x <= x1);
+        let (y0, y1) = bounds(visible.flat_map(|p| [p.y - p.error.max(0.0), p.y + p.error.max(0.0)]))?;
+        if y1 - y0 < 1e-6 {
+            return Some((y0 - 0.5, y1 + 0.5));
+        }
+        let border = (y1 - y0) * 0.05;
+        Some((y0 - border, y1 + border))
+    }
+
+    fn draw(&self, cr: &Context, width: f64, height: f64) -> Result<(), gtk::cairo::Error> {
+        let imp = self.imp();
+        cr.set_source_rgb(1.0, 1.0, 1.0);
+        cr.paint()?;
+        let plot_width = (width - MARGIN_LEFT - MARGIN_RIGHT).max(1.0);
+        let plot_height = (height - MARGIN_TOP - MARGIN_BOTTOM).max(1.0);
+        cr.set_font_size(11.0);
+
+        cr.set_source_rgb(0.3, 0.3, 0.3);
+        let x_label = imp.x_label.borrow();
+        let extents = cr.text_extents(&x_label)?;
+        cr.move_to(MARGIN_LEFT + (plot_width - extents.width()) / 2.0, height - 6.0);
+        cr.show_text(&x_label)?;
+        let y_label = imp.y_label.borrow();
+        let extents = cr.text_extents(&y_label)?;
+        cr.save()?;
+        cr.move_to(14.0, MARGIN_TOP + (plot_height + extents.width()) / 2.0);
+        cr.rotate(-std::f64::consts::FRAC_PI_2);
+        cr.show_text(&y_label)?;
+        cr.restore()?;
+
+        cr.set_source_rgb(0.0, 0.0, 0.0);
+        cr.set_line_width(1.0);
+        cr.rectangle(MARGIN_LEFT, MARGIN_TOP, plot_width, plot_height);
+        cr.stroke()?;
+
+        let Some((x0, x1)) = self.x_range() else {
+            return Ok(());
+        };
+        let Some((y0, y1)) = self.y_range(x0, x1) else {
+            return Ok(());
+        };
+        let invert_y = imp.invert_y.get();
+        let to_x = |x: f64| MARGIN_LEFT + (x - x0) / (x1 - x0) * plot_width;
+        let to_y = |y: f64| {
+            if invert_y {
+                MARGIN_TOP + (y - y0) / (y1 - y0) * plot_height
+            } else {
+                MARGIN_TOP + (y1 - y) / (y1 - y0) * plot_height
+            }
+        };
+
+        cr.set_source_rgb(0.3, 0.3, 0.3);
+        for (x, text) in ticks(x0, x1) {
+            let px = to_x(x);
+            cr.move_to(px, MARGIN_TOP + plot_height);
+            cr.line_to(px, MARGIN_TOP + plot_height + 4.0);
+            cr.stroke()?;
+            let extents = cr.text_extents(&text)?;
+            cr.move_to(px - extents.width() / 2.0, MARGIN_TOP + plot_height + 16.0);
+            cr.show_text(&text)?;
+        }
+        for (y, text) in ticks(y0, y1) {
+            let py = to_y(y);
+            cr.move_to(MARGIN_LEFT - 4.0, py);
+            cr.line_to(MARGIN_LEFT, py);
+            cr.stroke()?;
+            let extents = cr.text_extents(&text)?;
+            cr.move_to(MARGIN_LEFT - 8.0 - extents.width(), py + extents.height() / 2.0);
+            cr.show_text(&text)?;
+        }
+
+        cr.save()?;
+        cr.rectangle(MARGIN_LEFT, MARGIN_TOP, plot_width, plot_height);
+        cr.clip();
+        let series = imp.series.borrow();
+        for s in series.iter() {
+            cr.set_source_rgb(s.color.0, s.color.1, s.color.2);
+            if s.connected {
+                for (i, p) in s.points.iter().enumerate() {
+                    if i == 0 {
+                        cr.move_to(to_x(p.x), to_y(p.y));
+                    } else {
+                        cr.line_to(to_x(p.x), to_y(p.y));
+                    }
+                }
+                cr.stroke()?;
+                continue;
+            }
+            for p in s.points.iter() {
+                let (px, py) = (to_x(p.x), to_y(p.y));
+                if p.error > 0.0 {
+                    cr.move_to(px, to_y(p.y - p.error));
+                    cr.line_to(px, to_y(p.y + p.error));
+                    cr.stroke()?;
+                }
+                cr.arc(px, py, 2.5, 0.0, 2.0 * std::f64::consts::PI);
+                cr.fill()?;
+            }
+        }
+        cr.restore()?;
+
+        let mut legend_y = MARGIN_TOP + 14.0;
+        for s in series.iter().filter(|s| !s.label.is_empty()) {
+            cr.set_source_rgb(s.color.0, s.color.1, s.color.2);
+            cr.rectangle(MARGIN_LEFT + 8.0, legend_y - 8.0, 8.0, 8.0);
+            cr.fill()?;
+            cr.move_to(MARGIN_LEFT + 20.0, legend_y);
+            cr.show_text(&s.label)?;
+            legend_y += 14.0;
+        }
+        Ok(())
+    }
+}
+
+fn bounds(values: impl Iterator<Item = f64>) -> Option<(f64, f64)> {
+    values.fold(None, |acc, v| match acc {
+        None => { Some((v, v)) }
+        Some((lo, hi)) => { Some((lo.min(v), hi.max(v))) }
+    })
+}
+
+/// Tick positions at 1, 2 or 5 times a power of ten, with their labels.
+fn ticks(from: f64, to: f64) -> Vec<(f64, String)> {
+    let raw_step = (to - from) / 6.0;
+    if raw_step <= 0.0 || !raw_step.is_finite() {
+        return Vec::new();
+    }
+    let magnitude = 10f64.powf(raw_step.log10().floor());
+    let step = [1.0, 2.0, 5.0, 10.0].into_iter()
+        .map(|f| f * magnitude)
+        .find(|step| *step >= raw_step)
+        .unwrap_or(10.0 * magnitude);
+    let decimals = (-step.log10().floor()).max(0.0) as usize;
+    let mut result = Vec::new();
+    let mut tick = (from / step).ceil() * step;
+    while tick <= to {
+        result.push((tick, format!("{:.*}", decimals, tick)));
+        tick += step;
+    }
+    result
+}
@@ -0,0 +1,35 @@
+use crate::ui::plot::Series;
+use gtk::subclass::prelude::*;
+use gtk::{glib, DrawingArea};
+use std::cell::{Cell, RefCell};
+
+#[derive(Default)]
+pub struct PlotArea {
+    pub series: RefCell<Vec<Series>>,
+    pub x_label: RefCell<String>,
+    pub y_label: RefCell<String>,
+    pub invert_y: Cell<bool>,
+    pub x_view: Cell<Option<(f64, f64)>>,
+    pub drag_view: Cell<Option<(f64, f64)>>,
+    pub pointer_x: Cell<f64>,
+    pub view_changed: RefCell<Option<Box<dyn Fn(Option<(f64, f64)>)>>>,
+}
+
+#[glib::object_subclass]
+impl ObjectSubclass for PlotArea {
+    const NAME: &'static str = "PlotArea";
+    type Type = super::PlotArea;
+    type ParentType = DrawingArea;
+}
+
+impl ObjectImpl for PlotArea {
+    fn constructed(&self) {
+        self.parent_constructed();
+
+        self.obj().setup();
+    }
+}
+
+impl WidgetImpl for PlotArea {}
+
+impl DrawingAreaImpl for PlotArea {}
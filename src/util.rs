@@ -1,6 +1,6 @@
 use std::fmt::Display;
-use gtk::prelude::{DialogExt, GtkWindowExt, IsA, WidgetExt};
-use gtk::{ButtonsType, DialogFlags, MessageDialog, MessageType, Window};
+use gtk::prelude::{BoxExt, DialogExt, EditableExt, GtkWindowExt, IsA, WidgetExt};
+use gtk::{ButtonsType, Dialog, DialogFlags, Entry, MessageDialog, MessageType, ResponseType, Window};
 
 pub fn show_error<E: Display>(parent: Option<&impl IsA<Window>>, title: Option<&str>, error: E) {
     let msg_dialog = MessageDialog::new(
@@ -12,4 +12,74 @@ pub fn show_error<E: Display>(parent: Option<&impl IsA<Window>>, title: Option<&
     msg_dialog.set_title(title);
     msg_dialog.connect_response(|dlg, _| dlg.destroy());
     msg_dialog.show()
+}
+
+pub fn show_info<M: Display>(parent: Option<&impl IsA<Window>>, title: Option<&str>, message: M) {
+    let msg_dialog = MessageDialog::new(
+        parent,
+        DialogFlags::MODAL,
+        MessageType::Info,
+        ButtonsType::Ok,
+        format!("{message}"));
+    msg_dialog.set_title(title);
+    msg_dialog.connect_response(|dlg, _| dlg.destroy());
+    msg_dialog.show()
+}
+
+pub fn show_warning<M: Display>(parent: Option<&impl IsA<Window>>, title: Option<&str>, message: M) {
+    let msg_dialog = MessageDialog::new(
+        parent,
+        DialogFlags::MODAL,
+        MessageType::Warning,
+        ButtonsType::Ok,
+        format!("{message}"));
+    msg_dialog.set_title(title);
+    msg_dialog.connect_response(|dlg, _| dlg.destroy());
+    msg_dialog.show()
+}
+
+/// Asks a yes/no question. The callback receives `true` if the operator agrees.
+pub fn confirm<F: Fn(bool) + 'static>(parent: &impl IsA<Window>, title: &str, message: &str, callback: F) {
+    let msg_dialog = MessageDialog::new(
+        Some(parent),
+        DialogFlags::MODAL,
+        MessageType::Question,
+        ButtonsType::YesNo,
+        message);
+    msg_dialog.set_title(Some(title));
+    msg_dialog.connect_response(move |dlg, response| {
+        dlg.destroy();
+        callback(response == ResponseType::Yes);
+    });
+    msg_dialog.show()
+}
+
+/// Asks for a line of text. The callback receives `None` if the operator cancels.
+pub fn ask_text<F: Fn(Option<String>) + 'static>(parent: &impl IsA<Window>, title: &str, placeholder: &str, callback: F) {
+    let dialog = Dialog::with_buttons(
+        Some(title),
+        Some(parent),
+        DialogFlags::MODAL,
+        &[("Cancel", ResponseType::Cancel), ("OK", ResponseType::Ok)]);
+    let entry = Entry::builder()
+        .placeholder_text(placeholder)
+        .activates_default(true)
+        .margin_top(12)
+        .margin_bottom(12)
+        .margin_start(12)
+        .margin_end(12)
+        .build();
+    dialog.content_area().append(&entry);
+    dialog.set_default_response(ResponseType::Ok);
+    dialog.connect_response(move |dlg, response| {
+        let text = entry.text().to_string();
+        dlg.hide();
+        dlg.destroy();
+        if response == ResponseType::Ok {
+            callback(Some(text));
+        } else {
+            callback(None);
+        }
+    });
+    dialog.show()
 }
\ No newline at end of file